@@ -29,15 +29,20 @@ async function initBrowser() {
 }
 
 async function handleMessage(message) {
+    let id;
+    // Todas las respuestas repiten el id del comando para que el Orchestrator las correlacione
+    const reply = (data) => console.log(JSON.stringify({ id, ...data }));
     try {
-        const { action, payload } = JSON.parse(message);
+        const command = JSON.parse(message);
+        id = command.id;
+        const { action, payload } = command;
 
         if (action === 'init') {
             await initBrowser();
-            console.log(JSON.stringify({ status: 'ready' }));
+            reply({ status: 'ready' });
         } else if (action === 'navigate') {
             await page.goto(payload.url);
-            console.log(JSON.stringify({ status: 'navigated', url: payload.url }));
+            reply({ status: 'navigated', url: payload.url });
         } else if (action === 'chat_glm') {
             await page.goto('https://chatglm.cn');
             try {
@@ -56,15 +61,15 @@ async function handleMessage(message) {
                     const responses = await page.$$('.markdown-body'); // Common class
                     if (responses.length > 0) {
                         const lastResponse = await responses[responses.length - 1].innerText();
-                        console.log(JSON.stringify({ status: 'response_received', content: lastResponse }));
+                        reply({ status: 'response_received', content: lastResponse });
                     } else {
-                        console.log(JSON.stringify({ status: 'response_received', content: "Response extraction failed (GLM)." }));
+                        reply({ status: 'response_received', content: "Response extraction failed (GLM)." });
                     }
                 } else {
-                    console.log(JSON.stringify({ status: 'glm_opened' }));
+                    reply({ status: 'glm_opened' });
                 }
             } catch (e) {
-                console.log(JSON.stringify({ status: 'error', error: 'GLM interaction failed', details: e.message }));
+                reply({ status: 'error', error: 'GLM interaction failed', details: e.message });
             }
         } else if (action === 'chat_kimi') {
             await page.goto('https://kimi.moonshot.cn');
@@ -84,15 +89,15 @@ async function handleMessage(message) {
                     const responses = await page.$$('.markdown');
                     if (responses.length > 0) {
                         const lastResponse = await responses[responses.length - 1].innerText();
-                        console.log(JSON.stringify({ status: 'response_received', content: lastResponse }));
+                        reply({ status: 'response_received', content: lastResponse });
                     } else {
-                        console.log(JSON.stringify({ status: 'response_received', content: "Response extraction failed (Kimi)." }));
+                        reply({ status: 'response_received', content: "Response extraction failed (Kimi)." });
                     }
                 } else {
-                    console.log(JSON.stringify({ status: 'kimi_opened' }));
+                    reply({ status: 'kimi_opened' });
                 }
             } catch (e) {
-                console.log(JSON.stringify({ status: 'error', error: 'Kimi interaction failed', details: e.message }));
+                reply({ status: 'error', error: 'Kimi interaction failed', details: e.message });
             }
         } else if (action === 'chat_deepseek') {
             await page.goto('https://chat.deepseek.com');
@@ -109,16 +114,16 @@ async function handleMessage(message) {
                     const responses = await page.$$('.ds-markdown'); // DeepSeek specific class guess
                     if (responses.length > 0) {
                         const lastResponse = await responses[responses.length - 1].innerText();
-                        console.log(JSON.stringify({ status: 'response_received', content: lastResponse }));
+                        reply({ status: 'response_received', content: lastResponse });
                     } else {
                         // Fallback attempt
-                        console.log(JSON.stringify({ status: 'response_received', content: "Response extraction failed (DeepSeek)." }));
+                        reply({ status: 'response_received', content: "Response extraction failed (DeepSeek)." });
                     }
                 } else {
-                    console.log(JSON.stringify({ status: 'deepseek_opened' }));
+                    reply({ status: 'deepseek_opened' });
                 }
             } catch (e) {
-                console.log(JSON.stringify({ status: 'error', error: 'DeepSeek interaction failed', details: e.message }));
+                reply({ status: 'error', error: 'DeepSeek interaction failed', details: e.message });
             }
         } else if (action === 'chat_chatgpt') {
             await page.goto('https://chat.openai.com');
@@ -138,25 +143,56 @@ async function handleMessage(message) {
                     const responses = await page.$$('[data-message-author-role="assistant"]');
                     if (responses.length > 0) {
                         const lastResponse = await responses[responses.length - 1].innerText();
-                        console.log(JSON.stringify({ status: 'response_received', content: lastResponse }));
+                        reply({ status: 'response_received', content: lastResponse });
                     } else {
-                        console.log(JSON.stringify({ status: 'response_received', content: "No se pudo extraer la respuesta." }));
+                        reply({ status: 'response_received', content: "No se pudo extraer la respuesta." });
                     }
                 } else {
-                    console.log(JSON.stringify({ status: 'chatgpt_opened' }));
+                    reply({ status: 'chatgpt_opened' });
                 }
             } catch (e) {
-                console.log(JSON.stringify({ status: 'error', error: 'Login required or timeout', details: e.message }));
+                reply({ status: 'error', error: 'Login required or timeout', details: e.message });
             }
         } else if (action === 'close') {
             if (context) await context.close();
             process.exit(0);
         }
     } catch (error) {
-        console.error(JSON.stringify({ error: error.message }));
+        if (id) {
+            reply({ status: 'error', error: error.message });
+        } else {
+            console.error(JSON.stringify({ error: error.message }));
+        }
     }
 }
 
+// Todos los comandos comparten la misma página: se procesan de uno en uno
+let queue = Promise.resolve();
+// Ids en cola, y los que el Orchestrator ha cancelado (han vencido esperando turno) y no deben ejecutarse
+const queued = new Set();
+const cancelled = new Set();
 rl.on('line', (line) => {
-    handleMessage(line);
+    let id;
+    try {
+        const command = JSON.parse(line);
+        id = command.id;
+        // El heartbeat del supervisor no espera a la cola: solo comprueba que el proceso sigue vivo
+        if (command.action === 'ping') {
+            console.log(JSON.stringify({ id, status: 'pong' }));
+            return;
+        }
+        if (command.action === 'cancel') {
+            const target = command.payload && command.payload.id;
+            if (queued.has(target)) cancelled.add(target);
+            return;
+        }
+    } catch (e) {
+        // Línea inválida: handleMessage la reporta
+    }
+    if (id) queued.add(id);
+    queue = queue.then(() => {
+        queued.delete(id);
+        if (cancelled.delete(id)) return;
+        return handleMessage(line);
+    });
 });
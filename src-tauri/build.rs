@@ -1,3 +1,5 @@
 fn main() {
+    // `sqlx::migrate!` embebe los ficheros de `migrations/`: recompilar si cambian
+    println!("cargo:rerun-if-changed=migrations");
     tauri_build::build()
 }
@@ -0,0 +1,216 @@
+-- Esquema inicial. Todo es `IF NOT EXISTS` porque en las bases de datos anteriores a las migraciones
+-- (ver `db/legacy.rs`) las tablas ya existen y esta migración solo crea lo que falte.
+
+CREATE TABLE IF NOT EXISTS conversations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    title TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    pinned INTEGER NOT NULL DEFAULT 0,
+    archived INTEGER NOT NULL DEFAULT 0,
+    -- Último mensaje de la rama activa; NULL = el mensaje más reciente
+    active_message_id INTEGER
+);
+
+-- Árbol de mensajes: cada uno apunta al anterior del hilo
+CREATE TABLE IF NOT EXISTS messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    conversation_id INTEGER NOT NULL,
+    role TEXT NOT NULL,
+    content TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    parent_id INTEGER REFERENCES messages(id) ON DELETE CASCADE,
+    -- Proveedor con el que se envió (usuario) o generó (asistente) el turno
+    model TEXT,
+    agent_id INTEGER,
+    prompt_tokens INTEGER,
+    completion_tokens INTEGER,
+    latency_ms INTEGER,
+    cost REAL,
+    error TEXT,
+    FOREIGN KEY(conversation_id) REFERENCES conversations(id) ON DELETE CASCADE
+);
+
+-- Chunks de RAG usados para generar un mensaje (copia de la referencia, no FK a documents)
+CREATE TABLE IF NOT EXISTS message_citations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    message_id INTEGER NOT NULL,
+    n INTEGER NOT NULL,
+    document_id INTEGER NOT NULL,
+    filename TEXT NOT NULL,
+    start_line INTEGER,
+    end_line INTEGER,
+    symbol TEXT,
+    score REAL NOT NULL DEFAULT 0.0,
+    cited BOOLEAN NOT NULL DEFAULT 0,
+    FOREIGN KEY(message_id) REFERENCES messages(id) ON DELETE CASCADE
+);
+
+-- Agregado diario antiguo; ya no se escribe (las estadísticas salen de los metadatos de `messages`)
+CREATE TABLE IF NOT EXISTS usage_stats (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    model_id TEXT NOT NULL,
+    date DATE DEFAULT (DATE('now')),
+    count INTEGER DEFAULT 1,
+    estimated_cost REAL DEFAULT 0.0,
+    UNIQUE(model_id, date)
+);
+
+CREATE TABLE IF NOT EXISTS accounts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    service TEXT NOT NULL,
+    username TEXT NOT NULL,
+    is_active BOOLEAN DEFAULT 1,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE(service, username)
+);
+
+CREATE TABLE IF NOT EXISTS agents (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    description TEXT,
+    system_prompt TEXT NOT NULL,
+    default_model TEXT DEFAULT 'chatgpt',
+    is_built_in BOOLEAN DEFAULT 0
+);
+
+-- Agente incluido. `name` no es único, así que se comprueba a mano (las bases antiguas ya lo tienen)
+INSERT INTO agents (name, description, system_prompt, default_model, is_built_in)
+SELECT 'Project Scaffolder', 'Genera estructuras de proyectos', 'Eres un experto en inicializar proyectos. Tu objetivo es generar comandos de terminal y estructuras de archivos para nuevos proyectos. Usa bloques de código para los comandos.', 'chatgpt', 1
+WHERE NOT EXISTS (SELECT 1 FROM agents WHERE name = 'Project Scaffolder');
+
+-- Un documento ingerido (fichero o texto pegado); sus chunks en `documents` lo referencian.
+-- El hash permite saltarse re-ingestas sin cambios y el mtime evitar incluso leer el fichero.
+CREATE TABLE IF NOT EXISTS sources (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    collection TEXT NOT NULL,
+    path TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    mtime INTEGER,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    -- Lenguaje/formato detectado por extensión, para filtrar búsquedas
+    language TEXT,
+    UNIQUE(collection, path)
+);
+
+-- Chunks de los documentos
+CREATE TABLE IF NOT EXISTS documents (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    collection TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    content TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    -- Posición (bytes) del chunk en el documento original
+    start_offset INTEGER,
+    end_offset INTEGER,
+    -- Líneas (1-based) y símbolo (función, clase...) para citar código como `file.rs:120-180`
+    start_line INTEGER,
+    end_line INTEGER,
+    symbol TEXT,
+    source_id INTEGER REFERENCES sources(id) ON DELETE CASCADE
+);
+
+-- Etiquetas libres por documento (en minúsculas)
+CREATE TABLE IF NOT EXISTS source_tags (
+    source_id INTEGER NOT NULL REFERENCES sources(id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (source_id, tag)
+);
+
+-- Carpetas vigiladas: se vuelven a vigilar al arrancar la app
+CREATE TABLE IF NOT EXISTS watch_folders (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    collection TEXT NOT NULL,
+    path TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE(collection, path)
+);
+
+-- Configuración por colección (chunker como JSON de `ChunkerConfig`)
+CREATE TABLE IF NOT EXISTS collections (
+    name TEXT PRIMARY KEY,
+    chunker TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Vectores de embeddings por chunk (f32 little-endian), etiquetados con el modelo que los generó
+CREATE TABLE IF NOT EXISTS embeddings (
+    document_id INTEGER PRIMARY KEY,
+    model TEXT NOT NULL,
+    dim INTEGER NOT NULL,
+    vector BLOB NOT NULL,
+    FOREIGN KEY(document_id) REFERENCES documents(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS endpoints (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    base_url TEXT NOT NULL,
+    model TEXT NOT NULL,
+    headers TEXT NOT NULL DEFAULT '{}',
+    cost_per_1k_tokens REAL DEFAULT 0.0,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    context_window INTEGER
+);
+
+-- Índice FTS5 (external content) sobre `documents`, sincronizado por triggers
+CREATE VIRTUAL TABLE IF NOT EXISTS documents_fts USING fts5(
+    content,
+    content='documents',
+    content_rowid='id',
+    tokenize='unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER IF NOT EXISTS documents_fts_ai AFTER INSERT ON documents BEGIN
+    INSERT INTO documents_fts(rowid, content) VALUES (new.id, new.content);
+END;
+
+CREATE TRIGGER IF NOT EXISTS documents_fts_ad AFTER DELETE ON documents BEGIN
+    INSERT INTO documents_fts(documents_fts, rowid, content) VALUES ('delete', old.id, old.content);
+END;
+
+CREATE TRIGGER IF NOT EXISTS documents_fts_au AFTER UPDATE ON documents BEGIN
+    INSERT INTO documents_fts(documents_fts, rowid, content) VALUES ('delete', old.id, old.content);
+    INSERT INTO documents_fts(rowid, content) VALUES (new.id, new.content);
+END;
+
+-- Índices FTS5 (external content) del historial: contenido de los mensajes y títulos de conversaciones
+CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+    content,
+    content='messages',
+    content_rowid='id',
+    tokenize='unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER IF NOT EXISTS messages_fts_ai AFTER INSERT ON messages BEGIN
+    INSERT INTO messages_fts(rowid, content) VALUES (new.id, new.content);
+END;
+
+CREATE TRIGGER IF NOT EXISTS messages_fts_ad AFTER DELETE ON messages BEGIN
+    INSERT INTO messages_fts(messages_fts, rowid, content) VALUES ('delete', old.id, old.content);
+END;
+
+CREATE TRIGGER IF NOT EXISTS messages_fts_au AFTER UPDATE OF content ON messages BEGIN
+    INSERT INTO messages_fts(messages_fts, rowid, content) VALUES ('delete', old.id, old.content);
+    INSERT INTO messages_fts(rowid, content) VALUES (new.id, new.content);
+END;
+
+CREATE VIRTUAL TABLE IF NOT EXISTS conversations_fts USING fts5(
+    title,
+    content='conversations',
+    content_rowid='id',
+    tokenize='unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER IF NOT EXISTS conversations_fts_ai AFTER INSERT ON conversations BEGIN
+    INSERT INTO conversations_fts(rowid, title) VALUES (new.id, new.title);
+END;
+
+CREATE TRIGGER IF NOT EXISTS conversations_fts_ad AFTER DELETE ON conversations BEGIN
+    INSERT INTO conversations_fts(conversations_fts, rowid, title) VALUES ('delete', old.id, old.title);
+END;
+
+CREATE TRIGGER IF NOT EXISTS conversations_fts_au AFTER UPDATE OF title ON conversations BEGIN
+    INSERT INTO conversations_fts(conversations_fts, rowid, title) VALUES ('delete', old.id, old.title);
+    INSERT INTO conversations_fts(rowid, title) VALUES (new.id, new.title);
+END;
@@ -0,0 +1,12 @@
+-- Rama que se estaba viendo bajo cada mensaje, para que al volver a una rama se recupere su último
+-- hilo en vez del más reciente. NULL = el hijo más reciente.
+ALTER TABLE messages ADD COLUMN active_child_id INTEGER REFERENCES messages(id) ON DELETE SET NULL;
+
+-- Las ramas activas actuales quedan marcadas en todos sus niveles
+WITH RECURSIVE path(id, parent_id) AS (
+    SELECT m.id, m.parent_id FROM conversations c JOIN messages m ON m.id = c.active_message_id
+    UNION ALL
+    SELECT m.id, m.parent_id FROM messages m JOIN path p ON m.id = p.parent_id
+)
+UPDATE messages SET active_child_id = (SELECT p.id FROM path p WHERE p.parent_id = messages.id)
+WHERE id IN (SELECT parent_id FROM path);
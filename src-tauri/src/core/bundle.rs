@@ -0,0 +1,260 @@
+use crate::core::chunking::ChunkerConfig;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+
+/// Identificador del formato en el manifiesto, para rechazar zips que no son bundles.
+pub const BUNDLE_FORMAT: &str = "antigravity-rag-bundle";
+/// Versión del formato que escribe (y la más nueva que sabe leer) esta versión de la app.
+pub const BUNDLE_FORMAT_VERSION: u32 = 1;
+
+const MANIFEST_FILE: &str = "manifest.json";
+const SOURCES_FILE: &str = "sources.jsonl";
+const CHUNKS_FILE: &str = "chunks.jsonl";
+
+/// Modelo con el que se calcularon los embeddings del bundle.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EmbeddingInfo {
+    pub model: String,
+    pub dim: usize,
+    /// `EmbeddingEngine::model_hash` si el modelo estaba cargado al exportar
+    #[serde(default)]
+    pub model_hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub format: String,
+    pub format_version: u32,
+    /// Versión de la app que exportó el bundle (informativa)
+    pub app_version: String,
+    pub collection: String,
+    pub chunker: ChunkerConfig,
+    /// `CHUNKER_VERSION` de la app que partió los chunks (0 en bundles que no la registraban)
+    #[serde(default)]
+    pub chunker_version: u32,
+    /// `None` si el bundle no lleva embeddings
+    pub embedding: Option<EmbeddingInfo>,
+    pub sources: usize,
+    pub chunks: usize,
+    pub exported_at: String,
+}
+
+/// Documento (source) del bundle; sus chunks lo referencian por `path`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleSource {
+    pub path: String,
+    pub hash: String,
+    pub mtime: Option<i64>,
+    pub language: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleChunk {
+    pub path: String,
+    pub content: String,
+    pub start_offset: Option<i64>,
+    pub end_offset: Option<i64>,
+    pub start_line: Option<i64>,
+    pub end_line: Option<i64>,
+    pub symbol: Option<String>,
+    /// Vector del modelo de `manifest.embedding`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<Vec<f32>>,
+}
+
+/// Colección exportada: un zip con `manifest.json`, `sources.jsonl` y `chunks.jsonl`.
+pub struct Bundle {
+    pub manifest: BundleManifest,
+    pub sources: Vec<BundleSource>,
+    pub chunks: Vec<BundleChunk>,
+}
+
+impl Bundle {
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let file = std::fs::File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut zip = zip::ZipWriter::new(file);
+        let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+        zip.start_file(MANIFEST_FILE, options).map_err(|e| e.to_string())?;
+        serde_json::to_writer_pretty(&mut zip, &self.manifest).map_err(|e| e.to_string())?;
+
+        zip.start_file(SOURCES_FILE, options).map_err(|e| e.to_string())?;
+        write_jsonl(&mut zip, &self.sources)?;
+
+        zip.start_file(CHUNKS_FILE, options).map_err(|e| e.to_string())?;
+        write_jsonl(&mut zip, &self.chunks)?;
+
+        zip.finish().map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Lee y valida el bundle: formato y versión del manifiesto, que los conteos cuadren, que las rutas
+    /// de los sources no estén vacías ni repetidas, que cada chunk apunte a un source del bundle y que
+    /// los embeddings tengan la dimensión declarada.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut archive = zip::ZipArchive::new(file).map_err(|e| format!("Not a bundle archive: {}", e))?;
+
+        let manifest: BundleManifest = {
+            let mut json = String::new();
+            archive
+                .by_name(MANIFEST_FILE)
+                .map_err(|_| format!("Bundle has no {}", MANIFEST_FILE))?
+                .read_to_string(&mut json)
+                .map_err(|e| e.to_string())?;
+            serde_json::from_str(&json).map_err(|e| format!("Invalid manifest: {}", e))?
+        };
+        if manifest.format != BUNDLE_FORMAT {
+            return Err(format!("Unknown bundle format: {}", manifest.format));
+        }
+        if manifest.format_version > BUNDLE_FORMAT_VERSION {
+            return Err(format!(
+                "Bundle format version {} is newer than supported ({}); update the app",
+                manifest.format_version, BUNDLE_FORMAT_VERSION
+            ));
+        }
+
+        let sources: Vec<BundleSource> = read_jsonl(archive.by_name(SOURCES_FILE).map_err(|_| format!("Bundle has no {}", SOURCES_FILE))?)?;
+        let chunks: Vec<BundleChunk> = read_jsonl(archive.by_name(CHUNKS_FILE).map_err(|_| format!("Bundle has no {}", CHUNKS_FILE))?)?;
+
+        if sources.len() != manifest.sources || chunks.len() != manifest.chunks {
+            return Err(format!(
+                "Bundle is incomplete: manifest lists {} documents and {} chunks, found {} and {}",
+                manifest.sources, manifest.chunks, sources.len(), chunks.len()
+            ));
+        }
+        let mut paths = std::collections::HashSet::new();
+        for source in &sources {
+            if source.path.trim().is_empty() {
+                return Err("Bundle has a document with an empty path".to_string());
+            }
+            if !paths.insert(source.path.as_str()) {
+                return Err(format!("Bundle lists document {} twice", source.path));
+            }
+        }
+        if let Some(chunk) = chunks.iter().find(|c| !paths.contains(c.path.as_str())) {
+            return Err(format!("Chunk references unknown document: {}", chunk.path));
+        }
+        for chunk in &chunks {
+            match (&manifest.embedding, &chunk.embedding) {
+                (Some(info), Some(vector)) if vector.len() != info.dim => {
+                    return Err(format!("Embedding of {} has {} dimensions, manifest says {}", chunk.path, vector.len(), info.dim));
+                }
+                (None, Some(_)) => return Err("Bundle has embeddings but the manifest declares no model".to_string()),
+                _ => {}
+            }
+        }
+
+        Ok(Self { manifest, sources, chunks })
+    }
+}
+
+fn write_jsonl<T: Serialize>(writer: &mut impl Write, items: &[T]) -> Result<(), String> {
+    for item in items {
+        serde_json::to_writer(&mut *writer, item).map_err(|e| e.to_string())?;
+        writer.write_all(b"\n").map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn read_jsonl<T: DeserializeOwned>(reader: impl Read) -> Result<Vec<T>, String> {
+    let mut items = Vec::new();
+    for (i, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        items.push(serde_json::from_str(&line).map_err(|e| format!("Line {}: {}", i + 1, e))?);
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(sources: usize, chunks: usize) -> BundleManifest {
+        BundleManifest {
+            format: BUNDLE_FORMAT.to_string(),
+            format_version: BUNDLE_FORMAT_VERSION,
+            app_version: "0.0.0".to_string(),
+            collection: "docs".to_string(),
+            chunker: ChunkerConfig::default(),
+            chunker_version: crate::core::chunking::CHUNKER_VERSION,
+            embedding: None,
+            sources,
+            chunks,
+            exported_at: "2026-01-01T00:00:00+00:00".to_string(),
+        }
+    }
+
+    fn source(path: &str) -> BundleSource {
+        BundleSource { path: path.to_string(), hash: "abc".to_string(), mtime: None, language: None, tags: Vec::new() }
+    }
+
+    fn chunk(path: &str) -> BundleChunk {
+        BundleChunk {
+            path: path.to_string(),
+            content: "contenido".to_string(),
+            start_offset: None,
+            end_offset: None,
+            start_line: None,
+            end_line: None,
+            symbol: None,
+            embedding: None,
+        }
+    }
+
+    /// Escribe el bundle y lo vuelve a leer.
+    fn round_trip(name: &str, sources: Vec<BundleSource>, chunks: Vec<BundleChunk>) -> Result<Bundle, String> {
+        let path = std::env::temp_dir().join(format!("bundle-{}-{}.zip", name, std::process::id()));
+        let bundle = Bundle { manifest: manifest(sources.len(), chunks.len()), sources, chunks };
+        bundle.write(&path).unwrap();
+        let read = Bundle::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        read
+    }
+
+    fn rejection(result: Result<Bundle, String>) -> String {
+        match result {
+            Ok(_) => panic!("bundle accepted"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn valid_bundle_reads_back() {
+        let bundle = round_trip("valid", vec![source("a.md"), source("b.md")], vec![chunk("a.md"), chunk("b.md"), chunk("b.md")]).unwrap();
+        assert_eq!(bundle.sources.iter().map(|s| s.path.as_str()).collect::<Vec<_>>(), ["a.md", "b.md"]);
+        assert_eq!(bundle.chunks.len(), 3);
+        assert_eq!(bundle.manifest.chunker_version, crate::core::chunking::CHUNKER_VERSION);
+    }
+
+    #[test]
+    fn bundles_with_bad_paths_are_rejected() {
+        let unknown = round_trip("unknown", vec![source("a.md")], vec![chunk("a.md"), chunk("../otro.md")]);
+        assert!(rejection(unknown).contains("unknown document"));
+
+        let duplicated = round_trip("duplicated", vec![source("a.md"), source("a.md")], vec![chunk("a.md")]);
+        assert!(rejection(duplicated).contains("twice"));
+
+        let empty = round_trip("empty", vec![source(" ")], Vec::new());
+        assert!(rejection(empty).contains("empty path"));
+    }
+
+    #[test]
+    fn embeddings_must_match_the_manifest() {
+        let path = std::env::temp_dir().join(format!("bundle-dims-{}.zip", std::process::id()));
+        let mut bundle = Bundle { manifest: manifest(1, 1), sources: vec![source("a.md")], chunks: vec![chunk("a.md")] };
+        bundle.chunks[0].embedding = Some(vec![0.1, 0.2]);
+        bundle.manifest.embedding = Some(EmbeddingInfo { model: "mini".to_string(), dim: 3, model_hash: None });
+        bundle.write(&path).unwrap();
+        let read = Bundle::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(rejection(read).contains("2 dimensions"));
+    }
+}
@@ -0,0 +1,332 @@
+use serde::{Deserialize, Serialize};
+use tokenizers::Tokenizer;
+
+/// Fragmento de un documento con su posición (offsets en bytes) en el texto original.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub content: String,
+    pub start: usize,
+    pub end: usize,
+    /// Función, clase... que contiene el chunk (solo en chunks de código)
+    pub symbol: Option<String>,
+}
+
+/// Estrategia de chunking. Se guarda por colección (JSON en `collections.chunker`).
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChunkerConfig {
+    /// Párrafos separados por línea en blanco (sin límite de tamaño)
+    Paragraph,
+    /// Ventana fija de tokens con solapamiento
+    TokenWindow { size: usize, overlap: usize },
+    /// Una sección por heading de Markdown; las secciones largas se parten por párrafos (y líneas)
+    Markdown { max_chars: usize },
+    /// Frases agrupadas hasta `max_chars`
+    Sentence { max_chars: usize },
+}
+
+impl Default for ChunkerConfig {
+    /// Cabe con margen en los 512 tokens de un sentence-transformer típico
+    fn default() -> Self {
+        ChunkerConfig::TokenWindow { size: 256, overlap: 32 }
+    }
+}
+
+/// Chunks de menos caracteres que esto no aportan contexto útil
+const MIN_CHUNK_CHARS: usize = 10;
+
+/// Versión del algoritmo de chunking (no de su configuración). Se sube cuando el mismo texto con la
+/// misma `ChunkerConfig` pasa a partirse de otra forma.
+pub const CHUNKER_VERSION: u32 = 1;
+
+impl ChunkerConfig {
+    /// `tokenizer` solo lo usa `TokenWindow`; sin él se aproxima un token por palabra.
+    pub fn chunk(&self, text: &str, tokenizer: Option<&Tokenizer>) -> Vec<Chunk> {
+        let chunks = match self {
+            ChunkerConfig::Paragraph => split_paragraphs(text, 0),
+            ChunkerConfig::TokenWindow { size, overlap } => token_window(text, tokenizer, *size, *overlap),
+            ChunkerConfig::Markdown { max_chars } => markdown_sections(text, *max_chars),
+            ChunkerConfig::Sentence { max_chars } => group_spans(text, &sentence_spans(text), *max_chars),
+        };
+
+        chunks
+            .into_iter()
+            .filter_map(|(start, end)| trimmed(text, start, end))
+            .filter(|c| c.content.len() >= MIN_CHUNK_CHARS)
+            .collect()
+    }
+}
+
+/// Recorta espacios al principio y al final ajustando los offsets.
+fn trimmed(text: &str, start: usize, end: usize) -> Option<Chunk> {
+    let slice = &text[start..end];
+    let leading = slice.len() - slice.trim_start().len();
+    let content = slice.trim();
+    if content.is_empty() {
+        return None;
+    }
+    let start = start + leading;
+    Some(Chunk { content: content.to_string(), start, end: start + content.len(), symbol: None })
+}
+
+/// Rangos de párrafos (separados por "\n\n") dentro de `text[base..]`.
+fn split_paragraphs(text: &str, base: usize) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = 0;
+    for (pos, _) in text.match_indices("\n\n") {
+        spans.push((base + start, base + pos));
+        start = pos + 2;
+    }
+    spans.push((base + start, base + text.len()));
+    spans
+}
+
+fn token_window(text: &str, tokenizer: Option<&Tokenizer>, size: usize, overlap: usize) -> Vec<(usize, usize)> {
+    let size = size.max(1);
+    let step = size.saturating_sub(overlap).max(1);
+
+    // Offsets (en bytes) de cada token en el texto original
+    let offsets: Vec<(usize, usize)> = match tokenizer.and_then(|t| t.encode(text, false).ok()) {
+        Some(encoding) => encoding.get_offsets().to_vec(),
+        None => {
+            let mut words = Vec::new();
+            let mut current: Option<usize> = None;
+            for (i, c) in text.char_indices() {
+                match (c.is_whitespace(), current) {
+                    (false, None) => current = Some(i),
+                    (true, Some(s)) => {
+                        words.push((s, i));
+                        current = None;
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(s) = current {
+                words.push((s, text.len()));
+            }
+            words
+        }
+    };
+
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < offsets.len() {
+        let last = (i + size).min(offsets.len()) - 1;
+        spans.push((offsets[i].0, offsets[last].1));
+        if last == offsets.len() - 1 {
+            break;
+        }
+        i += step;
+    }
+    spans
+}
+
+fn markdown_sections(text: &str, max_chars: usize) -> Vec<(usize, usize)> {
+    // Inicio de cada línea que es un heading (fuera de bloques de código)
+    let mut heading_starts = vec![0];
+    let mut in_code = false;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            in_code = !in_code;
+        } else if !in_code && trimmed.starts_with('#') && offset > 0 {
+            heading_starts.push(offset);
+        }
+        offset += line.len();
+    }
+    heading_starts.push(text.len());
+
+    let mut spans = Vec::new();
+    for w in heading_starts.windows(2) {
+        let (start, end) = (w[0], w[1]);
+        if end - start <= max_chars {
+            spans.push((start, end));
+        } else {
+            // Sección larga: párrafos agrupados, el heading queda en el primer trozo
+            let paragraphs = split_paragraphs(&text[start..end], start);
+            spans.extend(group_spans(text, &paragraphs, max_chars));
+        }
+    }
+    spans
+}
+
+/// Rangos de frases: terminan en `.`, `!` o `?` seguidos de espacio, o en salto de párrafo.
+fn sentence_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, n)| n);
+        let is_end = matches!(c, '.' | '!' | '?') && next.is_none_or(|n| n.is_whitespace())
+            || c == '\n' && next == Some('\n');
+        if is_end {
+            let end = i + c.len_utf8();
+            spans.push((start, end));
+            start = end;
+        }
+    }
+    if start < text.len() {
+        spans.push((start, text.len()));
+    }
+    spans
+}
+
+/// Agrupa rangos consecutivos mientras no superen `max_chars`. Un rango que por sí solo no cabe
+/// (un fichero sin líneas en blanco, una "frase" de miles de caracteres) se parte antes.
+pub fn group_spans(text: &str, spans: &[(usize, usize)], max_chars: usize) -> Vec<(usize, usize)> {
+    let mut grouped = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+    let pieces = spans.iter().flat_map(|&(start, end)| split_oversized(text, start, end, max_chars));
+    for (start, end) in pieces {
+        if text[start..end].trim().is_empty() {
+            continue;
+        }
+        current = match current {
+            Some((s, _)) if end - s <= max_chars => Some((s, end)),
+            Some(group) => {
+                grouped.push(group);
+                Some((start, end))
+            }
+            None => Some((start, end)),
+        };
+    }
+    grouped.extend(current);
+    grouped
+}
+
+/// Parte un rango de más de `max_chars` por líneas y, si una línea sola no cabe, por caracteres.
+fn split_oversized(text: &str, start: usize, end: usize, max_chars: usize) -> Vec<(usize, usize)> {
+    let max_chars = max_chars.max(1);
+    if end - start <= max_chars {
+        return vec![(start, end)];
+    }
+
+    let mut pieces = Vec::new();
+    let mut offset = start;
+    for line in text[start..end].split_inclusive('\n') {
+        let line_end = offset + line.len();
+        if line.len() <= max_chars {
+            pieces.push((offset, line_end));
+        } else {
+            let mut piece_start = offset;
+            for (i, c) in line.char_indices() {
+                let pos = offset + i;
+                if pos > piece_start && pos + c.len_utf8() - piece_start > max_chars {
+                    pieces.push((piece_start, pos));
+                    piece_start = pos;
+                }
+            }
+            pieces.push((piece_start, line_end));
+        }
+        offset = line_end;
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cada chunk es exactamente el trozo del texto original que indican sus offsets.
+    fn assert_offsets(text: &str, chunks: &[Chunk]) {
+        for chunk in chunks {
+            assert_eq!(&text[chunk.start..chunk.end], chunk.content);
+        }
+        assert!(chunks.windows(2).all(|w| w[0].start <= w[1].start), "chunks out of order");
+    }
+
+    /// Fichero de `lines` líneas sin líneas en blanco, headings ni puntos.
+    fn wall_of_text(lines: usize) -> String {
+        (0..lines).map(|i| format!("let value_{} = compute(value_{}) + ñandú\n", i, i)).collect()
+    }
+
+    #[test]
+    fn paragraph_offsets() {
+        let text = "Primer párrafo con algo de texto.\n\n  Segundo párrafo, con sangría.  \n\n\n\nTercero después de varias líneas.";
+        let chunks = ChunkerConfig::Paragraph.chunk(text, None);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[1].content, "Segundo párrafo, con sangría.");
+        assert_offsets(text, &chunks);
+    }
+
+    #[test]
+    fn token_window_offsets_and_overlap() {
+        let text = "uno dos tres cuatro cinco seis siete ocho nueve diez once doce";
+        let chunks = ChunkerConfig::TokenWindow { size: 4, overlap: 2 }.chunk(text, None);
+        assert_offsets(text, &chunks);
+
+        let words: Vec<Vec<&str>> = chunks.iter().map(|c| c.content.split(' ').collect()).collect();
+        assert_eq!(words[0], ["uno", "dos", "tres", "cuatro"]);
+        for w in words.windows(2) {
+            assert!(w[0].len() <= 4 && w[1].len() <= 4);
+            // Las 2 últimas palabras de un chunk son las 2 primeras del siguiente
+            assert_eq!(w[0][w[0].len() - 2..], w[1][..2]);
+        }
+        assert_eq!(words.last().unwrap().last(), Some(&"doce"));
+    }
+
+    #[test]
+    fn token_window_bounds_a_wall_of_text() {
+        let text = wall_of_text(5000);
+        let chunks = ChunkerConfig::TokenWindow { size: 64, overlap: 8 }.chunk(&text, None);
+        assert!(chunks.len() > 100);
+        assert!(chunks.iter().all(|c| c.content.split_whitespace().count() <= 64));
+        assert_offsets(&text, &chunks);
+    }
+
+    #[test]
+    fn markdown_offsets_split_on_headings() {
+        let text = "# Título\nIntro del documento.\n\n## Uso\nCómo se usa.\n```\n# no es heading\n```\n## Fin\nÚltima sección.";
+        let chunks = ChunkerConfig::Markdown { max_chars: 1000 }.chunk(text, None);
+        assert_offsets(text, &chunks);
+        let firsts: Vec<&str> = chunks.iter().map(|c| c.content.lines().next().unwrap()).collect();
+        assert_eq!(firsts, ["# Título", "## Uso", "## Fin"]);
+        assert!(chunks[1].content.contains("# no es heading"));
+    }
+
+    #[test]
+    fn markdown_groups_paragraphs_of_long_sections() {
+        let paragraph = "Un párrafo de relleno con bastantes palabras.";
+        let text = format!("# Largo\n{}", [paragraph; 10].join("\n\n"));
+        let chunks = ChunkerConfig::Markdown { max_chars: 120 }.chunk(&text, None);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.content.len() <= 120));
+        assert!(chunks[0].content.starts_with("# Largo"));
+        assert_offsets(&text, &chunks);
+    }
+
+    #[test]
+    fn markdown_splits_oversized_sections_without_blank_lines() {
+        let text = wall_of_text(5000);
+        let chunks = ChunkerConfig::Markdown { max_chars: 1000 }.chunk(&text, None);
+        assert!(chunks.len() > 100);
+        assert!(chunks.iter().all(|c| c.content.len() <= 1000));
+        assert_offsets(&text, &chunks);
+    }
+
+    #[test]
+    fn sentence_offsets_and_grouping() {
+        let text = "Primera frase. ¿Segunda frase? ¡Tercera frase! Cuarta sin punto final";
+        let chunks = ChunkerConfig::Sentence { max_chars: 35 }.chunk(text, None);
+        assert_offsets(text, &chunks);
+        let contents: Vec<&str> = chunks.iter().map(|c| c.content.as_str()).collect();
+        assert_eq!(contents, ["Primera frase. ¿Segunda frase?", "¡Tercera frase!", "Cuarta sin punto final"]);
+    }
+
+    #[test]
+    fn sentence_splits_oversized_sentences() {
+        let text = wall_of_text(5000);
+        let chunks = ChunkerConfig::Sentence { max_chars: 500 }.chunk(&text, None);
+        assert!(chunks.len() > 100);
+        assert!(chunks.iter().all(|c| c.content.len() <= 500));
+        assert_offsets(&text, &chunks);
+
+        // Una sola línea gigante se parte por caracteres, sin cortar caracteres multibyte
+        let line = "ñandú".repeat(1000);
+        let chunks = ChunkerConfig::Sentence { max_chars: 128 }.chunk(&line, None);
+        assert!(chunks.iter().all(|c| c.content.len() <= 128));
+        assert_eq!(chunks.iter().map(|c| c.content.as_str()).collect::<String>(), line);
+        assert_offsets(&line, &chunks);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::core::rag::DocumentChunk;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Chunk que se pasó como contexto `[n]` al modelo. Guarda una copia de la referencia (fichero, líneas)
+/// para que la cita siga siendo legible aunque el documento se re-ingiera o se borre.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Citation {
+    /// Número con el que el chunk aparece en el contexto (`[n]`)
+    pub n: usize,
+    pub document_id: i64,
+    pub filename: String,
+    pub start_line: Option<i64>,
+    pub end_line: Option<i64>,
+    pub symbol: Option<String>,
+    pub score: f64,
+    /// La respuesta contiene al menos un marcador `[n]` que apunta a este chunk
+    pub cited: bool,
+}
+
+/// Citas de los chunks incluidos en el contexto (numerados desde 1, en orden), marcando las que la respuesta usa.
+pub fn from_answer(included: &[DocumentChunk], answer: &str) -> Vec<Citation> {
+    let markers = parse_markers(answer, included.len());
+    included
+        .iter()
+        .enumerate()
+        .map(|(i, doc)| Citation {
+            n: i + 1,
+            document_id: doc.id,
+            filename: doc.filename.clone(),
+            start_line: doc.start_line,
+            end_line: doc.end_line,
+            symbol: doc.symbol.clone(),
+            score: doc.score,
+            cited: markers.contains(&(i + 1)),
+        })
+        .collect()
+}
+
+/// Números citados en la respuesta: `[1]`, `[1, 3]`, `[2-4]`. Se ignoran los que no están en `1..=max`
+/// y los corchetes con otro contenido (`[x]`) o que son el texto de un enlace Markdown (`[1](url)`).
+pub fn parse_markers(answer: &str, max: usize) -> BTreeSet<usize> {
+    let mut markers = BTreeSet::new();
+
+    for (start, _) in answer.match_indices('[') {
+        let Some(len) = answer[start + 1..].find(']') else { break };
+        let inner = &answer[start + 1..start + 1 + len];
+        if inner.is_empty() || !inner.chars().all(|c| c.is_ascii_digit() || matches!(c, ',' | '-' | ' ')) {
+            continue;
+        }
+        if answer[start + 2 + len..].starts_with('(') {
+            continue;
+        }
+
+        for part in inner.split(',') {
+            let bounds: Vec<Option<usize>> = part.split('-').map(|n| n.trim().parse().ok()).collect();
+            let (from, to) = match bounds.as_slice() {
+                [Some(n)] => (*n, *n),
+                [Some(from), Some(to)] if from <= to => (*from, *to),
+                _ => continue,
+            };
+            markers.extend((from..=to.min(max)).filter(|n| *n >= 1));
+        }
+    }
+    markers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn markers(answer: &str, max: usize) -> Vec<usize> {
+        parse_markers(answer, max).into_iter().collect()
+    }
+
+    #[test]
+    fn single_markers() {
+        assert_eq!(markers("Según [1], y también [3].", 5), [1, 3]);
+        assert_eq!(markers("Repetido [2] y [2]", 5), [2]);
+    }
+
+    #[test]
+    fn lists_and_ranges() {
+        assert_eq!(markers("Ver [1, 3]", 5), [1, 3]);
+        assert_eq!(markers("Ver [1,3]", 5), [1, 3]);
+        assert_eq!(markers("Ver [2-4]", 5), [2, 3, 4]);
+        assert_eq!(markers("Ver [1, 3 - 4]", 5), [1, 3, 4]);
+        // Rango al revés: no es una cita
+        assert_eq!(markers("Ver [4-2]", 5), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn out_of_range_numbers_are_ignored() {
+        assert_eq!(markers("[0] [6] [9]", 5), Vec::<usize>::new());
+        assert_eq!(markers("[4-9]", 5), [4, 5]);
+        assert_eq!(markers("[1]", 0), Vec::<usize>::new());
+        assert_eq!(markers("[99999999999999999999999]", 5), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn other_brackets_and_links_do_not_count() {
+        assert_eq!(markers("Un [enlace](https://example.com) y [x]", 5), Vec::<usize>::new());
+        assert_eq!(markers("Un [2](https://example.com/2) numerado", 5), Vec::<usize>::new());
+        assert_eq!(markers("Lista [] vacía, [ ] y [1]", 5), [1]);
+        assert_eq!(markers("Sin cerrar [1", 5), Vec::<usize>::new());
+        assert_eq!(markers("[enlace](url) y luego [2]", 5), [2]);
+    }
+
+    #[test]
+    fn from_answer_marks_cited_chunks() {
+        let chunk = |id: i64| -> DocumentChunk {
+            serde_json::from_value(serde_json::json!({
+                "id": id,
+                "collection": "docs",
+                "filename": format!("doc{}.md", id),
+                "content": "contenido",
+                "start_line": 3,
+                "end_line": 8,
+            }))
+            .unwrap()
+        };
+        let citations = from_answer(&[chunk(10), chunk(20), chunk(30)], "Como dice [2] (y [7], que no existe)");
+
+        assert_eq!(citations.iter().map(|c| (c.n, c.document_id, c.cited)).collect::<Vec<_>>(), [(1, 10, false), (2, 20, true), (3, 30, false)]);
+        assert_eq!(citations[1].filename, "doc20.md");
+        assert_eq!((citations[1].start_line, citations[1].end_line), (Some(3), Some(8)));
+    }
+}
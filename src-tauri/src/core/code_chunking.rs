@@ -0,0 +1,291 @@
+use crate::core::chunking::{group_spans, Chunk};
+use std::path::Path;
+use tree_sitter::{Language, Node, Parser};
+
+/// Tamaño a partir del cual un item contenedor (impl, class...) se parte en sus miembros, y
+/// cualquier otro item (una función muy larga) en ventanas de líneas con el mismo símbolo.
+const MAX_CODE_CHUNK_BYTES: usize = 4000;
+
+/// Nodos que agrupan miembros y pueden partirse si son demasiado grandes.
+const CONTAINER_KINDS: &[&str] = &[
+    "impl_item",
+    "trait_item",
+    "mod_item",
+    "class_declaration",
+    "abstract_class_declaration",
+    "interface_declaration",
+    "class_definition",
+    "class",
+];
+
+fn language_for(filename: &str) -> Option<Language> {
+    let extension = Path::new(filename).extension()?.to_str()?.to_ascii_lowercase();
+    let language = match extension.as_str() {
+        "rs" => tree_sitter_rust::LANGUAGE.into(),
+        "ts" | "mts" | "cts" => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+        "tsx" => tree_sitter_typescript::LANGUAGE_TSX.into(),
+        "js" | "jsx" | "mjs" | "cjs" => tree_sitter_javascript::LANGUAGE.into(),
+        "py" => tree_sitter_python::LANGUAGE.into(),
+        _ => return None,
+    };
+    Some(language)
+}
+
+/// Chunks alineados con los items de nivel superior (funciones, impls, clases...) de un fichero de código.
+/// `None` si la extensión no es de un lenguaje soportado o el fichero no tiene items reconocibles.
+pub fn chunk_source(filename: &str, text: &str) -> Option<Vec<Chunk>> {
+    let language = language_for(filename)?;
+    let mut parser = Parser::new();
+    parser.set_language(&language).ok()?;
+    let tree = parser.parse(text, None)?;
+
+    let mut chunks = Vec::new();
+    collect_items(tree.root_node(), text, None, 0, &mut chunks);
+
+    if chunks.iter().all(|c| c.symbol.is_none()) {
+        return None;
+    }
+    Some(chunks)
+}
+
+/// Recorre los hijos de `parent`: cada item con nombre es un chunk (con los comentarios y atributos que
+/// lo preceden) y lo demás (imports, constantes...) se agrupa en chunks sin símbolo.
+/// `leading_start` es el inicio de la cabecera del contenedor, que se adjunta al primer miembro.
+fn collect_items(parent: Node, text: &str, prefix: Option<&str>, leading_start: usize, chunks: &mut Vec<Chunk>) {
+    let mut leading: Option<usize> = (leading_start < parent.start_byte()).then_some(leading_start);
+    let mut pending: Option<(usize, usize)> = None;
+
+    let mut cursor = parent.walk();
+    for node in parent.named_children(&mut cursor) {
+        if is_leading_trivia(node) {
+            leading.get_or_insert(node.start_byte());
+            continue;
+        }
+
+        let name = symbol_name(node, text);
+        let is_item = name.is_some() && node.start_position().row != node.end_position().row;
+        if !is_item {
+            let start = leading.take().unwrap_or(node.start_byte());
+            pending = match pending {
+                Some((s, _)) if node.end_byte() - s <= MAX_CODE_CHUNK_BYTES => Some((s, node.end_byte())),
+                Some(range) => {
+                    push_chunk(chunks, text, range, None);
+                    Some((start, node.end_byte()))
+                }
+                None => Some((start, node.end_byte())),
+            };
+            continue;
+        }
+
+        if let Some(range) = pending.take() {
+            push_chunk(chunks, text, range, None);
+        }
+
+        let symbol = match prefix {
+            Some(prefix) => format!("{}::{}", prefix, name.unwrap_or_default()),
+            None => name.unwrap_or_default(),
+        };
+        let start = leading.take().unwrap_or(node.start_byte());
+        let definition = unwrap_definition(node);
+
+        let body = definition.child_by_field_name("body");
+        match body {
+            Some(body) if node.end_byte() - start > MAX_CODE_CHUNK_BYTES && CONTAINER_KINDS.contains(&definition.kind()) => {
+                let first = chunks.len();
+                collect_items(body, text, Some(&symbol), start, chunks);
+                // El cierre del contenedor (`}`) va con el último miembro
+                if let Some(last) = chunks.get_mut(first..).and_then(|c| c.last_mut()) {
+                    let end = node.end_byte();
+                    last.content = text[last.start..end].to_string();
+                    last.end = end;
+                }
+            }
+            _ => {
+                for (window_start, window_end) in group_spans(text, &[(start, node.end_byte())], MAX_CODE_CHUNK_BYTES) {
+                    // Sin el salto de línea final, para que la línea de fin sea la última con código
+                    let window_end = window_start + text[window_start..window_end].trim_end().len();
+                    push_chunk(chunks, text, (window_start, window_end), Some(symbol.clone()));
+                }
+            }
+        }
+    }
+
+    if let Some(start) = leading {
+        pending = Some(pending.map_or((start, parent.end_byte()), |(s, _)| (s, parent.end_byte())));
+    }
+    if let Some(range) = pending {
+        push_chunk(chunks, text, range, None);
+    }
+}
+
+fn push_chunk(chunks: &mut Vec<Chunk>, text: &str, (start, end): (usize, usize), symbol: Option<String>) {
+    let content = &text[start..end];
+    if content.trim().is_empty() {
+        return;
+    }
+    chunks.push(Chunk { content: content.to_string(), start, end, symbol });
+}
+
+/// Comentarios, atributos (`#[...]`) y decoradores se adjuntan al item que los sigue.
+fn is_leading_trivia(node: Node) -> bool {
+    node.kind().contains("comment") || matches!(node.kind(), "attribute_item" | "decorator")
+}
+
+/// `export ...` (TS/JS) y `@decorador def ...` (Python) envuelven la definición real.
+fn unwrap_definition(node: Node) -> Node {
+    node.child_by_field_name("declaration")
+        .or_else(|| node.child_by_field_name("definition"))
+        .map(unwrap_definition)
+        .unwrap_or(node)
+}
+
+fn symbol_name(node: Node, text: &str) -> Option<String> {
+    let node = unwrap_definition(node);
+    let field_text = |field: &str| node.child_by_field_name(field).and_then(|n| n.utf8_text(text.as_bytes()).ok()).map(String::from);
+
+    if node.kind() == "impl_item" {
+        let ty = field_text("type")?;
+        return Some(match field_text("trait") {
+            Some(trait_name) => format!("{} for {}", trait_name, ty),
+            None => ty,
+        });
+    }
+    if let Some(name) = field_text("name") {
+        return Some(name);
+    }
+
+    // `const foo = () => {...}`: el nombre está en el primer declarator
+    if matches!(node.kind(), "lexical_declaration" | "variable_declaration") {
+        let mut cursor = node.walk();
+        let declarator = node.named_children(&mut cursor).find(|n| n.kind() == "variable_declarator")?;
+        return declarator.child_by_field_name("name")?.utf8_text(text.as_bytes()).ok().map(String::from);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (símbolo, primera línea, última línea), con líneas 1-based como las guarda `rag`.
+    fn items(filename: &str, text: &str) -> Vec<(Option<String>, usize, usize)> {
+        let line = |offset: usize| text[..offset].matches('\n').count() + 1;
+        chunk_source(filename, text)
+            .unwrap()
+            .into_iter()
+            .map(|c| {
+                assert_eq!(&text[c.start..c.end], c.content);
+                (c.symbol, line(c.start), line(c.end))
+            })
+            .collect()
+    }
+
+    fn symbol(name: &str, start: usize, end: usize) -> (Option<String>, usize, usize) {
+        (Some(name.to_string()), start, end)
+    }
+
+    #[test]
+    fn rust_items() {
+        let text = "use std::fmt;
+
+/// Doc de Foo
+#[derive(Debug)]
+struct Foo {
+    a: i32,
+}
+
+impl fmt::Display for Foo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, \"{}\", self.a)
+    }
+}
+
+fn main() {
+    println!(\"hola\");
+}
+";
+        assert_eq!(
+            items("main.rs", text),
+            vec![
+                (None, 1, 1),
+                symbol("Foo", 3, 7),
+                symbol("fmt::Display for Foo", 9, 13),
+                symbol("main", 15, 17),
+            ]
+        );
+    }
+
+    #[test]
+    fn typescript_items() {
+        let text = "import { x } from './x';
+
+export function greet(name: string): string {
+  return `hola ${name}`;
+}
+
+export class Greeter {
+  hello() {
+    return greet('mundo');
+  }
+}
+
+const shout = (s: string) => {
+  return s.toUpperCase();
+};
+";
+        assert_eq!(
+            items("app.ts", text),
+            vec![(None, 1, 1), symbol("greet", 3, 5), symbol("Greeter", 7, 11), symbol("shout", 13, 15)]
+        );
+    }
+
+    #[test]
+    fn python_items() {
+        let text = "import os
+
+@cache
+def load(path):
+    return open(path).read()
+
+class Store:
+    def get(self, key):
+        return key
+";
+        assert_eq!(items("store.py", text), vec![(None, 1, 1), symbol("load", 3, 5), symbol("Store", 7, 9)]);
+    }
+
+    #[test]
+    fn large_containers_split_into_members() {
+        let methods: String = (0..60)
+            .map(|i| format!("    fn method_{}(&self) -> usize {{\n        {} + self.value * {}\n    }}\n\n", i, i, i))
+            .collect();
+        let text = format!("impl Big {{\n{}}}\n", methods);
+        let chunks = items("big.rs", &text);
+
+        assert_eq!(chunks.len(), 60);
+        assert_eq!(chunks[0], symbol("Big::method_0", 1, 4));
+        assert_eq!(chunks[1], symbol("Big::method_1", 6, 8));
+        // El cierre del impl va con el último miembro
+        assert_eq!(chunks[59].2, text.lines().count());
+    }
+
+    #[test]
+    fn oversized_functions_split_into_line_windows() {
+        let body: String = (0..2000).map(|i| format!("    let v{} = v{} + {};\n", i + 1, i, i)).collect();
+        let text = format!("fn huge() {{\n    let v0 = 0;\n{}}}\n", body);
+        let chunks = chunk_source("huge.rs", &text).unwrap();
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.symbol.as_deref() == Some("huge")));
+        assert!(chunks.iter().all(|c| c.content.len() <= MAX_CODE_CHUNK_BYTES));
+        // Ventanas contiguas de líneas completas que cubren toda la función
+        assert_eq!(chunks[0].start, 0);
+        assert_eq!(chunks.last().unwrap().end, text.trim_end().len());
+        let line = |offset: usize| text[..offset].matches('\n').count() + 1;
+        for w in chunks.windows(2) {
+            assert!(w[0].content.ends_with(';'));
+            assert_eq!(text[w[0].end..w[1].start].trim(), "");
+            assert_eq!(line(w[1].start), line(w[0].end) + 1);
+        }
+    }
+}
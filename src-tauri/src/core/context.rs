@@ -0,0 +1,217 @@
+use crate::core::openai::Message;
+use crate::core::rag::DocumentChunk;
+use serde::Serialize;
+
+/// Por debajo de esto no merece la pena meter un chunk recortado.
+const MIN_TRIMMED_TOKENS: usize = 64;
+/// Tokens que añade cada mensaje del historial aparte de su contenido (rol, separadores).
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Chunk recuperado que no ha entrado (entero) en el contexto.
+#[derive(Debug, Clone, Serialize)]
+pub struct DroppedChunk {
+    pub id: i64,
+    pub filename: String,
+    pub tokens: usize,
+    /// "dropped" si se ha quedado fuera, "trimmed" si ha entrado recortado
+    pub reason: &'static str,
+}
+
+/// Resultado de empaquetar los chunks en el presupuesto de tokens.
+pub struct PackedContext {
+    /// Chunks incluidos, en el orden de ranking (el de un chunk recortado lleva el contenido recortado)
+    pub included: Vec<DocumentChunk>,
+    pub dropped: Vec<DroppedChunk>,
+    pub tokens: usize,
+}
+
+/// Línea con la que cada chunk entra en el prompt, numerada para poder citarla.
+pub fn format_chunk(n: usize, doc: &DocumentChunk) -> String {
+    let source = match &doc.symbol {
+        Some(symbol) => format!("{} `{}`", doc.citation(), symbol),
+        None => doc.citation(),
+    };
+    format!("[{}] (from {}): {}\n", n, source, doc.content)
+}
+
+/// Mete los chunks (ya ordenados por relevancia) mientras quepan en `budget` tokens.
+/// Los que no caben enteros se saltan, de modo que uno más pequeño y peor rankeado puede entrar;
+/// si al final sobra sitio, el mejor de los descartados entra recortado.
+pub fn pack(chunks: Vec<DocumentChunk>, budget: usize, count_tokens: impl Fn(&str) -> usize) -> PackedContext {
+    let mut included: Vec<(usize, DocumentChunk)> = Vec::new();
+    let mut skipped: Vec<(usize, DocumentChunk, usize)> = Vec::new();
+    let mut used = 0;
+
+    for (rank, doc) in chunks.into_iter().enumerate() {
+        let tokens = count_tokens(&format_chunk(included.len() + 1, &doc));
+        if used + tokens <= budget {
+            used += tokens;
+            included.push((rank, doc));
+        } else {
+            skipped.push((rank, doc, tokens));
+        }
+    }
+
+    let mut dropped = Vec::new();
+    let remaining = budget.saturating_sub(used);
+    let mut skipped = skipped.into_iter();
+    if remaining >= MIN_TRIMMED_TOKENS {
+        if let Some((rank, mut doc, tokens)) = skipped.next() {
+            doc.content = trim_to_tokens(&doc, remaining, &count_tokens);
+            used += count_tokens(&format_chunk(included.len() + 1, &doc));
+            dropped.push(DroppedChunk { id: doc.id, filename: doc.filename.clone(), tokens, reason: "trimmed" });
+            included.push((rank, doc));
+            included.sort_by_key(|(rank, _)| *rank);
+        }
+    }
+    dropped.extend(skipped.map(|(_, doc, tokens)| DroppedChunk { id: doc.id, filename: doc.filename, tokens, reason: "dropped" }));
+
+    PackedContext { included: included.into_iter().map(|(_, doc)| doc).collect(), dropped, tokens: used }
+}
+
+/// Prefijo más largo del contenido (cortado en límite de carácter) cuya línea formateada cabe en `budget`.
+fn trim_to_tokens(doc: &DocumentChunk, budget: usize, count_tokens: &impl Fn(&str) -> usize) -> String {
+    let boundaries: Vec<usize> = doc.content.char_indices().map(|(i, _)| i).chain([doc.content.len()]).collect();
+    let fits = |end: usize| {
+        let candidate = DocumentChunk { content: format!("{}…", &doc.content[..end]), ..doc.clone() };
+        count_tokens(&format_chunk(0, &candidate)) <= budget
+    };
+
+    // Búsqueda binaria sobre los límites de carácter
+    let (mut lo, mut hi) = (0, boundaries.len() - 1);
+    while lo < hi {
+        let mid = (lo + hi).div_ceil(2);
+        if fits(boundaries[mid]) {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    format!("{}…", &doc.content[..boundaries[lo]])
+}
+
+/// Turnos anteriores de la conversación (`(rol, contenido)`, del más antiguo al más reciente) que caben en
+/// `budget` tokens. Se conservan los más recientes; los antiguos se descartan enteros.
+/// Devuelve los mensajes en orden cronológico y cuántos se han descartado.
+pub fn fit_history(history: &[(String, String)], budget: usize, count_tokens: impl Fn(&str) -> usize) -> (Vec<Message>, usize) {
+    let turns: Vec<&(String, String)> = history.iter().filter(|(role, _)| role == "user" || role == "assistant").collect();
+    let mut used = 0;
+    let mut kept = Vec::new();
+
+    for (role, content) in turns.iter().rev() {
+        let tokens = count_tokens(content) + MESSAGE_OVERHEAD_TOKENS;
+        if used + tokens > budget {
+            break;
+        }
+        used += tokens;
+        kept.push(Message { role: role.clone(), content: content.clone() });
+    }
+
+    let dropped = turns.len() - kept.len();
+    kept.reverse();
+    (kept, dropped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Un token por carácter, para que los presupuestos sean fáciles de calcular.
+    fn chars(text: &str) -> usize {
+        text.chars().count()
+    }
+
+    fn chunk(id: i64, content: &str) -> DocumentChunk {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "collection": "docs",
+            "filename": format!("doc{}.txt", id),
+            "content": content,
+        }))
+        .unwrap()
+    }
+
+    fn ids(packed: &PackedContext) -> Vec<i64> {
+        packed.included.iter().map(|d| d.id).collect()
+    }
+
+    #[test]
+    fn pack_skips_chunks_that_do_not_fit() {
+        let (a, b, c) = (chunk(1, &"a".repeat(50)), chunk(2, &"b".repeat(300)), chunk(3, &"c".repeat(40)));
+        // Lo que sobra tras A y C no llega a MIN_TRIMMED_TOKENS: B se queda fuera entero
+        let budget = chars(&format_chunk(1, &a)) + chars(&format_chunk(2, &c)) + MIN_TRIMMED_TOKENS - 1;
+        let packed = pack(vec![a, b, c], budget, chars);
+
+        assert_eq!(ids(&packed), [1, 3]);
+        assert_eq!(packed.dropped.len(), 1);
+        assert_eq!((packed.dropped[0].id, packed.dropped[0].reason), (2, "dropped"));
+        assert!(packed.tokens <= budget);
+    }
+
+    #[test]
+    fn pack_trims_the_best_skipped_chunk_into_the_remaining_budget() {
+        let (a, b, c) = (chunk(1, &"a".repeat(50)), chunk(2, &"b".repeat(300)), chunk(3, &"c".repeat(40)));
+        let budget = chars(&format_chunk(1, &a)) + chars(&format_chunk(2, &c)) + 150;
+        let packed = pack(vec![a, b, c], budget, chars);
+
+        // El recortado conserva su puesto en el ranking
+        assert_eq!(ids(&packed), [1, 2, 3]);
+        assert!(packed.included[1].content.ends_with('…'));
+        assert!(packed.included[1].content.len() < 300);
+        assert_eq!((packed.dropped[0].id, packed.dropped[0].reason), (2, "trimmed"));
+        assert!(packed.tokens <= budget);
+    }
+
+    #[test]
+    fn pack_with_no_budget_drops_everything() {
+        let packed = pack(vec![chunk(1, "uno"), chunk(2, "dos")], 0, chars);
+        assert!(packed.included.is_empty());
+        assert_eq!(packed.dropped.iter().map(|d| d.reason).collect::<Vec<_>>(), ["dropped", "dropped"]);
+        assert_eq!(packed.tokens, 0);
+    }
+
+    #[test]
+    fn trim_to_tokens_returns_the_longest_prefix_that_fits() {
+        let doc = chunk(1, "ñandú, pingüino y cigüeña");
+        let overhead = chars(&format_chunk(0, &chunk(1, "…")));
+
+        for keep in 0..doc.content.chars().count() {
+            let trimmed = trim_to_tokens(&doc, overhead + keep, &chars);
+            let expected: String = doc.content.chars().take(keep).collect();
+            assert_eq!(trimmed, format!("{}…", expected));
+        }
+        // Ni siquiera cabe la cabecera: queda solo la marca de recorte
+        assert_eq!(trim_to_tokens(&doc, 0, &chars), "…");
+    }
+
+    fn turn(role: &str, content: &str) -> (String, String) {
+        (role.to_string(), content.to_string())
+    }
+
+    #[test]
+    fn fit_history_keeps_the_most_recent_turns() {
+        let history = [turn("user", &"a".repeat(10)), turn("assistant", &"b".repeat(10)), turn("system", "x"), turn("user", &"c".repeat(10))];
+        let budget = 2 * (10 + MESSAGE_OVERHEAD_TOKENS) + 5;
+        let (kept, dropped) = fit_history(&history, budget, chars);
+
+        // Los mensajes de sistema no cuentan como turnos
+        assert_eq!(kept.iter().map(|m| m.role.as_str()).collect::<Vec<_>>(), ["assistant", "user"]);
+        assert_eq!(kept[1].content, "c".repeat(10));
+        assert_eq!(dropped, 1);
+    }
+
+    #[test]
+    fn fit_history_stops_at_the_first_turn_that_does_not_fit() {
+        // El primer turno cabría, pero se descarta para no dejar un hueco en la conversación
+        let history = [turn("user", "a"), turn("assistant", &"b".repeat(100)), turn("user", &"c".repeat(10))];
+        let (kept, dropped) = fit_history(&history, 30, chars);
+
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].content, "c".repeat(10));
+        assert_eq!(dropped, 2);
+
+        let (kept, dropped) = fit_history(&history, 0, chars);
+        assert!(kept.is_empty());
+        assert_eq!(dropped, 3);
+    }
+}
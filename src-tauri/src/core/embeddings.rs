@@ -0,0 +1,179 @@
+use ort::session::builder::GraphOptimizationLevel;
+use ort::session::Session;
+use ort::value::Tensor;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+/// Tokens máximos por chunk que acepta un sentence-transformer típico (MiniLM, BGE...).
+const MAX_SEQ_LEN: usize = 512;
+
+/// Modelo de embeddings tipo sentence-transformer exportado a ONNX. Corre en CPU y offline.
+pub struct EmbeddingEngine {
+    session: Session,
+    tokenizer: Tokenizer,
+    model_id: String,
+    model_hash: String,
+    has_token_type_ids: bool,
+}
+
+impl EmbeddingEngine {
+    /// `model_id` identifica el modelo en la DB: vectores de modelos distintos no son comparables.
+    pub fn new<P: AsRef<Path>>(model_id: &str, model_path: P, tokenizer_path: P) -> Result<Self, String> {
+        let mut tokenizer = Tokenizer::from_file(tokenizer_path).map_err(|e| e.to_string())?;
+        tokenizer
+            .with_truncation(Some(TruncationParams { max_length: MAX_SEQ_LEN, ..Default::default() }))
+            .map_err(|e| e.to_string())?;
+        tokenizer.with_padding(Some(PaddingParams::default()));
+
+        let mut hasher = Sha256::new();
+        let mut file = std::fs::File::open(model_path.as_ref()).map_err(|e| e.to_string())?;
+        std::io::copy(&mut file, &mut hasher).map_err(|e| e.to_string())?;
+        let model_hash = format!("{:x}", hasher.finalize());
+
+        let session = Session::builder()
+            .map_err(|e: ort::Error| e.to_string())?
+            .with_optimization_level(GraphOptimizationLevel::Level3)
+            .map_err(|e: ort::Error| e.to_string())?
+            .with_intra_threads(4)
+            .map_err(|e: ort::Error| e.to_string())?
+            .commit_from_file(model_path)
+            .map_err(|e| e.to_string())?;
+
+        let has_token_type_ids = session.inputs().iter().any(|i| i.name() == "token_type_ids");
+
+        Ok(EmbeddingEngine { session, tokenizer, model_id: model_id.to_string(), model_hash, has_token_type_ids })
+    }
+
+    pub fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    /// SHA-256 del `.onnx`: dos instalaciones pueden tener ficheros distintos con el mismo id.
+    pub fn model_hash(&self) -> &str {
+        &self.model_hash
+    }
+
+    /// Copia del tokenizer sin truncado ni padding, para medir chunks en tokens de este modelo.
+    pub fn chunking_tokenizer(&self) -> Result<Tokenizer, String> {
+        let mut tokenizer = self.tokenizer.clone();
+        tokenizer.with_truncation(None).map_err(|e| e.to_string())?;
+        tokenizer.with_padding(None);
+        Ok(tokenizer)
+    }
+
+    /// Embeddings normalizados (L2) de cada texto, con mean pooling sobre la attention mask.
+    pub fn embed(&mut self, texts: &[&str]) -> Result<Vec<Vec<f32>>, String> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let encodings = self.tokenizer.encode_batch(texts.to_vec(), true).map_err(|e| e.to_string())?;
+        let batch = encodings.len();
+        let seq_len = encodings[0].get_ids().len();
+
+        let flatten = |f: fn(&tokenizers::Encoding) -> &[u32]| -> Vec<i64> {
+            encodings.iter().flat_map(|e| f(e).iter().map(|&v| v as i64)).collect()
+        };
+        let input_ids = flatten(|e| e.get_ids());
+        let attention_mask = flatten(|e| e.get_attention_mask());
+        let token_type_ids = flatten(|e| e.get_type_ids());
+
+        let mut inputs = ort::inputs![
+            "input_ids" => Tensor::from_array(([batch, seq_len], input_ids)).map_err(|e| e.to_string())?,
+            "attention_mask" => Tensor::from_array(([batch, seq_len], attention_mask.clone())).map_err(|e| e.to_string())?,
+        ];
+        if self.has_token_type_ids {
+            inputs.push((
+                "token_type_ids".into(),
+                Tensor::from_array(([batch, seq_len], token_type_ids)).map_err(|e| e.to_string())?.into(),
+            ));
+        }
+
+        let outputs = self.session.run(inputs).map_err(|e| e.to_string())?;
+        // Algunos exports ya devuelven `sentence_embedding` [batch, dim]; si no, `last_hidden_state` [batch, seq, dim]
+        let hidden = outputs[0].try_extract_array::<f32>().map_err(|e| e.to_string())?;
+
+        let mut embeddings = Vec::with_capacity(batch);
+        for b in 0..batch {
+            let mut vector = if hidden.ndim() == 2 {
+                hidden.index_axis(ndarray::Axis(0), b).iter().copied().collect::<Vec<f32>>()
+            } else {
+                let tokens = hidden.index_axis(ndarray::Axis(0), b);
+                let dim = tokens.shape()[1];
+                let mut sum = vec![0.0f32; dim];
+                let mut count = 0.0f32;
+                for (t, row) in tokens.outer_iter().enumerate() {
+                    if attention_mask[b * seq_len + t] == 0 {
+                        continue;
+                    }
+                    count += 1.0;
+                    sum.iter_mut().zip(row.iter()).for_each(|(s, v)| *s += v);
+                }
+                sum.iter_mut().for_each(|s| *s /= count.max(1.0));
+                sum
+            };
+
+            let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt().max(1e-12);
+            vector.iter_mut().for_each(|v| *v /= norm);
+            embeddings.push(vector);
+        }
+
+        Ok(embeddings)
+    }
+}
+
+/// Similitud coseno. Con vectores ya normalizados equivale al producto escalar.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|v| v * v).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+/// Serialización de vectores para columnas BLOB (f32 little-endian).
+pub fn vector_to_blob(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+pub fn blob_to_vector(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cosine_similarity_ignores_magnitude() {
+        assert!((cosine_similarity(&[1.0, 2.0, 3.0], &[2.0, 4.0, 6.0]) - 1.0).abs() < 1e-6);
+        assert!((cosine_similarity(&[1.0, 0.0], &[-1.0, 0.0]) + 1.0).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 3.0]), 0.0);
+        assert!((cosine_similarity(&[3.0, 4.0], &[1.0, 0.0]) - 0.6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn cosine_similarity_with_a_zero_vector_is_zero() {
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 2.0]), 0.0);
+        assert_eq!(cosine_similarity(&[1.0, 2.0], &[0.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&[], &[]), 0.0);
+    }
+
+    #[test]
+    fn vectors_round_trip_through_blobs() {
+        let vector = vec![0.0, -1.5, 3.25, f32::MIN_POSITIVE, f32::MAX, -0.0];
+        let blob = vector_to_blob(&vector);
+        assert_eq!(blob.len(), vector.len() * 4);
+        assert_eq!(&blob[4..8], &(-1.5f32).to_le_bytes());
+
+        let restored = blob_to_vector(&blob);
+        assert_eq!(restored.iter().map(|v| v.to_bits()).collect::<Vec<_>>(), vector.iter().map(|v| v.to_bits()).collect::<Vec<_>>());
+        assert!(blob_to_vector(&[]).is_empty());
+    }
+}
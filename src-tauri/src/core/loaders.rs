@@ -0,0 +1,194 @@
+use quick_xml::events::Event;
+use serde::Serialize;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Ficheros más grandes que esto se saltan al ingerir un directorio (salvo que se indique otro límite).
+pub const DEFAULT_MAX_FILE_BYTES: u64 = 5 * 1024 * 1024;
+
+/// Extensiones que se leen tal cual como texto (documentación y código fuente).
+const PLAIN_TEXT_EXTENSIONS: &[&str] = &[
+    "md", "markdown", "mdx", "txt", "rst", "adoc", "org", "csv", "json", "yaml", "yml", "toml", "ini", "xml",
+    "rs", "ts", "tsx", "js", "jsx", "mjs", "cjs", "py", "go", "java", "kt", "c", "h", "cpp", "hpp", "cc", "cs",
+    "rb", "php", "swift", "sh", "bash", "ps1", "sql", "svelte", "vue", "css", "scss", "lua", "dart", "scala",
+];
+
+/// Fichero no ingerido y el motivo (demasiado grande, formato no soportado, error al extraer...).
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: String,
+    /// `false` si se ha saltado a propósito (formato no soportado, tamaño); `true` si ha fallado
+    pub error: bool,
+}
+
+impl SkippedFile {
+    fn ignored(path: &Path, reason: String) -> Self {
+        Self { path: source_name(path), reason, error: false }
+    }
+
+    pub fn failed(path: &Path, reason: String) -> Self {
+        Self { path: source_name(path), reason, error: true }
+    }
+}
+
+/// Ficheros candidatos del directorio, respetando `.gitignore`, `.ignore` y ocultos.
+/// Los de formato no soportado o que superan `max_bytes` se devuelven aparte como saltados.
+pub fn walk_directory(root: &Path, max_bytes: u64) -> (Vec<PathBuf>, Vec<SkippedFile>) {
+    walk_paths(root, &[], max_bytes)
+}
+
+/// Como `walk_directory`, pero solo los ficheros que son (o cuelgan de) alguno de `targets`.
+/// Solo se recorren los directorios que llevan a ellos, con las mismas reglas de `.gitignore`.
+pub fn walk_paths(root: &Path, targets: &[PathBuf], max_bytes: u64) -> (Vec<PathBuf>, Vec<SkippedFile>) {
+    let mut files = Vec::new();
+    let mut skipped = Vec::new();
+
+    let targets = targets.to_vec();
+    // require_git(false): el .gitignore se respeta aunque el directorio no sea un repo
+    let walker = ignore::WalkBuilder::new(root)
+        .require_git(false)
+        .filter_entry(move |entry| {
+            targets.is_empty() || targets.iter().any(|t| t.starts_with(entry.path()) || entry.path().starts_with(t))
+        })
+        .build();
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                skipped.push(SkippedFile::failed(root, e.to_string()));
+                continue;
+            }
+        };
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+
+        let path = entry.into_path();
+        if !is_supported(&path) {
+            skipped.push(SkippedFile::ignored(&path, UNSUPPORTED_FORMAT.to_string()));
+            continue;
+        }
+        match path.metadata() {
+            Ok(meta) if meta.len() > max_bytes => {
+                skipped.push(SkippedFile::ignored(&path, format!("File too large ({} bytes, limit {})", meta.len(), max_bytes)))
+            }
+            Ok(_) => files.push(path),
+            Err(e) => skipped.push(SkippedFile::failed(&path, e.to_string())),
+        }
+    }
+
+    files.sort();
+    (files, skipped)
+}
+
+/// Ruta completa con `/`, que es lo que se guarda como `filename` de los ficheros ingeridos desde
+/// disco: dos carpetas de la misma colección pueden tener un `README.md` cada una.
+pub fn source_name(path: &Path) -> String {
+    let name = path.to_string_lossy().replace('\\', "/");
+    // `canonicalize` en Windows devuelve rutas `\\?\C:\...`
+    match name.strip_prefix("//?/") {
+        Some(stripped) => stripped.to_string(),
+        None => name,
+    }
+}
+
+/// Ruta relativa a `root` con `/`, como se guardaban antes los ficheros ingeridos desde una carpeta.
+pub fn relative_name(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// Lenguaje o formato del fichero según su extensión (`rust`, `markdown`, `pdf`...), para filtrar búsquedas.
+/// Las extensiones sin nombre propio se devuelven tal cual.
+pub fn language_for(filename: &str) -> Option<String> {
+    let extension = Path::new(filename).extension()?.to_str()?.to_ascii_lowercase();
+    let language = match extension.as_str() {
+        "rs" => "rust",
+        "ts" | "tsx" | "mts" | "cts" => "typescript",
+        "js" | "jsx" | "mjs" | "cjs" => "javascript",
+        "py" => "python",
+        "md" | "markdown" | "mdx" => "markdown",
+        "htm" | "html" => "html",
+        "yml" | "yaml" => "yaml",
+        "h" | "c" => "c",
+        "hpp" | "cc" | "cpp" => "cpp",
+        "cs" => "csharp",
+        "kt" => "kotlin",
+        "rb" => "ruby",
+        "sh" | "bash" => "shell",
+        "ps1" => "powershell",
+        "txt" => "text",
+        other => other,
+    };
+    Some(language.to_string())
+}
+
+const UNSUPPORTED_FORMAT: &str = "Unsupported file format";
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// `true` si `extract_text` sabe leer el fichero (por su extensión).
+pub fn is_supported(path: &Path) -> bool {
+    let extension = extension(path);
+    matches!(extension.as_str(), "html" | "htm" | "pdf" | "docx") || PLAIN_TEXT_EXTENSIONS.contains(&extension.as_str())
+}
+
+/// Texto del fichero según su extensión. `Err` si el formato no está soportado o no se puede extraer.
+pub fn extract_text(path: &Path) -> Result<String, String> {
+    match extension(path).as_str() {
+        "html" | "htm" => {
+            let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+            html2text::from_read(bytes.as_slice(), 100).map_err(|e| e.to_string())
+        }
+        "pdf" => pdf_extract::extract_text(path).map_err(|e| e.to_string()),
+        "docx" => extract_docx(path),
+        ext if PLAIN_TEXT_EXTENSIONS.contains(&ext) => {
+            let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+            String::from_utf8(bytes).map_err(|_| "Not valid UTF-8 text".to_string())
+        }
+        _ => Err(UNSUPPORTED_FORMAT.to_string()),
+    }
+}
+
+/// Texto de `word/document.xml`: un párrafo (`w:p`) por bloque, separados por línea en blanco.
+fn extract_docx(path: &Path) -> Result<String, String> {
+    let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+    let mut xml = String::new();
+    archive
+        .by_name("word/document.xml")
+        .map_err(|e| e.to_string())?
+        .read_to_string(&mut xml)
+        .map_err(|e| e.to_string())?;
+
+    let mut reader = quick_xml::Reader::from_str(&xml);
+    let mut text = String::new();
+    let mut in_text = false;
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Start(e) if e.name().as_ref() == b"w:t" => in_text = true,
+            Event::End(e) => match e.name().as_ref() {
+                b"w:t" => in_text = false,
+                b"w:p" => text.push_str("\n\n"),
+                _ => {}
+            },
+            Event::Empty(e) => match e.name().as_ref() {
+                b"w:tab" => text.push('\t'),
+                b"w:br" => text.push('\n'),
+                _ => {}
+            },
+            Event::Text(t) if in_text => text.push_str(&t.unescape().map_err(|e| e.to_string())?),
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(text)
+}
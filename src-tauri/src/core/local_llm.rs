@@ -1,11 +1,155 @@
+use ndarray::s;
+use ort::memory::Allocator;
 use ort::session::builder::GraphOptimizationLevel;
-use ort::session::Session;
+use ort::session::{Session, SessionInputValue};
+use ort::tensor::TensorElementType;
+use ort::value::{DynTensor, DynValue, Tensor, ValueType};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
 use tokenizers::Tokenizer;
 
+/// Parámetros de muestreo. Un campo sin fijar (`None`) desactiva ese ajuste: no se envía a los
+/// endpoints y en local no se aplica, de modo que por defecto se decodifica greedy.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationConfig {
+    /// Tokens a generar como máximo; sin fijar, 256 en local y el límite del servidor en endpoints
+    pub max_new_tokens: Option<usize>,
+    /// Temperatura del muestreo; sin fijar se elige siempre el token más probable
+    pub temperature: Option<f32>,
+    /// Muestrea solo entre los `k` tokens más probables
+    pub top_k: Option<usize>,
+    /// Muestrea solo en el menor conjunto de tokens cuya probabilidad acumulada llega a `p`
+    pub top_p: Option<f32>,
+    /// Penaliza los logits de los tokens ya presentes en el contexto
+    pub repetition_penalty: Option<f32>,
+    /// Con semilla fija la salida es determinista
+    pub seed: Option<u64>,
+    /// Textos que cortan la generación al aparecer
+    pub stop_sequences: Vec<String>,
+}
+
+/// Tokens generados por el modelo local si la configuración no fija `max_new_tokens`.
+const LOCAL_MAX_NEW_TOKENS: usize = 256;
+
+/// SplitMix64: suficiente para muestrear y reproducible con la misma semilla sin depender de `rand`.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniforme en [0, 1)
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Elige el siguiente token a partir de los logits de la última posición.
+fn sample_token(logits: &[f32], history: &[i64], config: &GenerationConfig, rng: &mut SplitMix64) -> Option<u32> {
+    let mut logits = logits.to_vec();
+    let temperature = config.temperature.unwrap_or(0.0);
+    let top_p = config.top_p.unwrap_or(1.0);
+
+    if let Some(penalty) = config.repetition_penalty {
+        let seen: HashSet<usize> = history.iter().map(|&t| t as usize).collect();
+        for t in seen {
+            if let Some(l) = logits.get_mut(t) {
+                *l = if *l > 0.0 { *l / penalty } else { *l * penalty };
+            }
+        }
+    }
+
+    if temperature <= 0.0 {
+        return logits
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(i, _)| i as u32);
+    }
+
+    let mut candidates: Vec<(usize, f32)> = logits
+        .iter()
+        .enumerate()
+        .map(|(i, &l)| (i, l / temperature))
+        .collect();
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+    if let Some(k) = config.top_k {
+        candidates.truncate(k.max(1));
+    }
+
+    // Softmax sobre los candidatos
+    let max = candidates.first()?.1;
+    let mut probs: Vec<(usize, f32)> = candidates.iter().map(|&(i, l)| (i, (l - max).exp())).collect();
+    let sum: f32 = probs.iter().map(|p| p.1).sum();
+    probs.iter_mut().for_each(|p| p.1 /= sum);
+
+    if top_p < 1.0 {
+        let mut cumulative = 0.0;
+        let mut keep = probs.len();
+        for (n, p) in probs.iter().enumerate() {
+            cumulative += p.1;
+            if cumulative >= top_p {
+                keep = n + 1;
+                break;
+            }
+        }
+        probs.truncate(keep);
+    }
+
+    let total: f32 = probs.iter().map(|p| p.1).sum();
+    let mut target = rng.next_f32() * total;
+    for &(i, p) in &probs {
+        if target < p {
+            return Some(i as u32);
+        }
+        target -= p;
+    }
+    probs.last().map(|p| p.0 as u32)
+}
+
+/// Modelo cargado. El tokenizer va aparte del motor para poder contar tokens mientras se genera.
+#[derive(Clone)]
+pub struct LoadedEngine {
+    pub engine: Arc<Mutex<LocalInferenceEngine>>,
+    pub tokenizer: Arc<Tokenizer>,
+}
+
+impl LoadedEngine {
+    pub fn new(engine: LocalInferenceEngine) -> Self {
+        let tokenizer = engine.tokenizer.clone();
+        Self { engine: Arc::new(Mutex::new(engine)), tokenizer }
+    }
+}
+
+/// Slot del modelo cargado en `AppState`. El motor va en su propio `Mutex` para que generar no
+/// bloquee el slot: se clona el `Arc` y se suelta el lock exterior antes de decodificar.
+pub type EngineSlot = Arc<Mutex<Option<LoadedEngine>>>;
+
+/// Entrada `past_key_values.N.{key,value}` del modelo y la salida `present.N.{key,value}` que la alimenta.
+struct PastKeyValue {
+    input: String,
+    output: String,
+    ty: TensorElementType,
+    num_heads: usize,
+    head_dim: usize,
+}
+
 pub struct LocalInferenceEngine {
     session: Session,
-    tokenizer: Tokenizer,
+    tokenizer: Arc<Tokenizer>,
+    past_key_values: Vec<PastKeyValue>,
+    has_position_ids: bool,
+    eos_token_id: Option<u32>,
+    default_config: GenerationConfig,
 }
 
 impl LocalInferenceEngine {
@@ -22,24 +166,294 @@ impl LocalInferenceEngine {
             .commit_from_file(model_path)
             .map_err(|e| e.to_string())?;
 
-        Ok(LocalInferenceEngine { session, tokenizer })
+        // Los exports de decoder (optimum / onnxruntime-genai) exponen la caché KV como
+        // `past_key_values.N.key` -> `present.N.key`, con forma [batch, heads, seq, head_dim]
+        let mut past_key_values = Vec::new();
+        for input in session.inputs() {
+            let Some(suffix) = input.name().strip_prefix("past_key_values") else { continue };
+            let ValueType::Tensor { ty, shape, .. } = input.dtype() else { continue };
+            if shape.len() != 4 || shape[1] < 0 || shape[3] < 0 {
+                return Err(format!("Unsupported KV cache shape for {}: {:?}", input.name(), shape));
+            }
+            past_key_values.push(PastKeyValue {
+                input: input.name().to_string(),
+                output: format!("present{}", suffix),
+                ty: *ty,
+                num_heads: shape[1] as usize,
+                head_dim: shape[3] as usize,
+            });
+        }
+
+        let has_position_ids = session.inputs().iter().any(|i| i.name() == "position_ids");
+
+        // Phi-2 / GPT-style tokenizers usan <|endoftext|>
+        let eos_token_id = ["<|endoftext|>", "</s>", "<|end|>"]
+            .iter()
+            .find_map(|t| tokenizer.token_to_id(t));
+
+        Ok(LocalInferenceEngine {
+            session,
+            tokenizer: Arc::new(tokenizer),
+            past_key_values,
+            has_position_ids,
+            eos_token_id,
+            default_config: GenerationConfig::default(),
+        })
+    }
+
+    /// Configuración usada cuando `generate` no recibe una propia.
+    pub fn set_default_config(&mut self, config: GenerationConfig) {
+        self.default_config = config;
     }
 
-    pub fn generate(&self, prompt: &str) -> Result<String, String> {
-        // Simple generation placeholder
-        // Dependency mismatch between ort and ndarray prevents actual inference logic for now.
-        // We keep the session loaded but return a mock response.
+    /// Decodificación token a token reutilizando la caché KV entre pasos.
+    /// Para en EOS, en una stop sequence o al llegar a `max_new_tokens`.
+    pub fn generate(&mut self, prompt: &str, config: Option<&GenerationConfig>) -> Result<String, String> {
+        let config = config.cloned().unwrap_or_else(|| self.default_config.clone());
 
         let encoding = self
             .tokenizer
             .encode(prompt, true)
             .map_err(|e| e.to_string())?;
-        let input_ids = encoding.get_ids();
 
-        // Suppress unused warning
-        let _ = &self.session;
+        let tokens: Vec<i64> = encoding.get_ids().iter().map(|&id| id as i64).collect();
+        if tokens.is_empty() {
+            return Ok(String::new());
+        }
+
+        // Primer paso: caché vacía (seq = 0) y todo el prompt como entrada
+        let mut past: Vec<DynValue> = self
+            .past_key_values
+            .iter()
+            .map(|kv| {
+                DynTensor::new(&Allocator::default(), kv.ty, [1, kv.num_heads, 0, kv.head_dim]).map(|t| t.into_dyn())
+            })
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.to_string())?;
+
+        let session = &mut self.session;
+        let past_key_values = &self.past_key_values;
+        let has_position_ids = self.has_position_ids;
+        let tokenizer = &self.tokenizer;
+
+        let next_logits = |step_ids: &[i64], total_len: usize| -> Result<Vec<f32>, String> {
+            let past_len = total_len - step_ids.len();
+
+            let mut inputs: Vec<(Cow<str>, SessionInputValue)> = vec![
+                ("input_ids".into(), Tensor::from_array(([1, step_ids.len()], step_ids.to_vec())).map_err(|e| e.to_string())?.into()),
+                ("attention_mask".into(), Tensor::from_array(([1, total_len], vec![1i64; total_len])).map_err(|e| e.to_string())?.into()),
+            ];
+            if has_position_ids {
+                let positions: Vec<i64> = (past_len..total_len).map(|p| p as i64).collect();
+                inputs.push(("position_ids".into(), Tensor::from_array(([1, positions.len()], positions)).map_err(|e| e.to_string())?.into()));
+            }
+            for (kv, value) in past_key_values.iter().zip(past.drain(..)) {
+                inputs.push((kv.input.as_str().into(), value.into()));
+            }
+
+            let mut outputs = session.run(inputs).map_err(|e| e.to_string())?;
+
+            let last = {
+                let logits = outputs
+                    .get("logits")
+                    .ok_or("Model has no \"logits\" output")?
+                    .try_extract_array::<f32>()
+                    .map_err(|e| format!("Unsupported logits output: {}", e))?;
+                if logits.ndim() != 3 {
+                    return Err(format!("Unsupported logits shape: {:?}", logits.shape()));
+                }
+                // logits: [batch, seq, vocab] -> solo interesa la última posición
+                logits.slice(s![0, -1, ..]).iter().copied().collect()
+            };
+
+            for kv in past_key_values {
+                past.push(outputs.remove(&kv.output).ok_or_else(|| format!("Missing model output {}", kv.output))?);
+            }
+            Ok(last)
+        };
+
+        decode_loop(
+            tokens,
+            &config,
+            self.eos_token_id,
+            next_logits,
+            |ids| tokenizer.decode(ids, true).map_err(|e| e.to_string()),
+        )
+    }
+}
+
+/// Bucle de decodificación independiente del modelo. `next_logits` recibe los ids nuevos de este
+/// paso (todo el prompt en el primero, luego solo el último token) y la longitud total de la
+/// secuencia, y devuelve los logits de la última posición; `decode` pasa ids generados a texto.
+fn decode_loop(
+    mut tokens: Vec<i64>,
+    config: &GenerationConfig,
+    eos_token_id: Option<u32>,
+    mut next_logits: impl FnMut(&[i64], usize) -> Result<Vec<f32>, String>,
+    decode: impl Fn(&[u32]) -> Result<String, String>,
+) -> Result<String, String> {
+    let seed = config.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default()
+    });
+    let mut rng = SplitMix64(seed);
+
+    let mut step_ids = tokens.clone();
+    let mut generated: Vec<u32> = Vec::new();
+
+    for _ in 0..config.max_new_tokens.unwrap_or(LOCAL_MAX_NEW_TOKENS) {
+        let logits = next_logits(&step_ids, tokens.len())?;
+        let next = sample_token(&logits, &tokens, config, &mut rng).ok_or("Empty logits")?;
+
+        if Some(next) == eos_token_id {
+            break;
+        }
+        generated.push(next);
+        tokens.push(next as i64);
+        step_ids = vec![next as i64];
+
+        if !config.stop_sequences.is_empty() {
+            let text = decode(&generated)?;
+            if let Some(pos) = config.stop_sequences.iter().filter_map(|stop| text.find(stop.as_str())).min() {
+                return Ok(text[..pos].to_string());
+            }
+        }
+    }
+
+    decode(&generated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VOCAB: usize = 8;
+    const EOS: u32 = 7;
+
+    /// Modelo de juguete: siempre predice el token siguiente al último (`n -> n + 1`).
+    fn counting_model(steps: &mut Vec<(Vec<i64>, usize)>) -> impl FnMut(&[i64], usize) -> Result<Vec<f32>, String> + '_ {
+        move |step_ids, total_len| {
+            steps.push((step_ids.to_vec(), total_len));
+            let mut logits = vec![0.0; VOCAB];
+            logits[(*step_ids.last().unwrap() as usize + 1) % VOCAB] = 10.0;
+            Ok(logits)
+        }
+    }
+
+    fn decode_digits(ids: &[u32]) -> Result<String, String> {
+        Ok(ids.iter().map(|id| id.to_string()).collect())
+    }
+
+    #[test]
+    fn decode_loop_feeds_prompt_once_then_one_token_per_step() {
+        let mut steps = Vec::new();
+        let config = GenerationConfig { max_new_tokens: Some(3), ..Default::default() };
+        let text = decode_loop(vec![0, 1], &config, Some(EOS), counting_model(&mut steps), decode_digits).unwrap();
+
+        assert_eq!(text, "234");
+        assert_eq!(steps, vec![(vec![0, 1], 2), (vec![2], 3), (vec![3], 4)]);
+    }
+
+    #[test]
+    fn decode_loop_stops_at_eos_without_emitting_it() {
+        let mut steps = Vec::new();
+        let config = GenerationConfig { max_new_tokens: Some(100), ..Default::default() };
+        let text = decode_loop(vec![4], &config, Some(EOS), counting_model(&mut steps), decode_digits).unwrap();
+
+        assert_eq!(text, "56");
+        assert_eq!(steps.len(), 3);
+    }
+
+    #[test]
+    fn decode_loop_cuts_at_stop_sequence() {
+        let mut steps = Vec::new();
+        let config = GenerationConfig { max_new_tokens: Some(100), stop_sequences: vec!["45".to_string()], ..Default::default() };
+        let text = decode_loop(vec![1], &config, None, counting_model(&mut steps), decode_digits).unwrap();
+
+        assert_eq!(text, "23");
+        assert_eq!(steps.len(), 4);
+    }
+
+    fn sampling(temperature: f32, top_k: Option<usize>, top_p: f32, seed: u64) -> GenerationConfig {
+        GenerationConfig { temperature: Some(temperature), top_k, top_p: Some(top_p), seed: Some(seed), ..Default::default() }
+    }
+
+    /// Índices elegidos en `draws` muestreos seguidos con la semilla de `config`.
+    fn draw(logits: &[f32], config: &GenerationConfig, draws: usize) -> Vec<u32> {
+        let mut rng = SplitMix64(config.seed.unwrap());
+        (0..draws).map(|_| sample_token(logits, &[], config, &mut rng).unwrap()).collect()
+    }
+
+    #[test]
+    fn fixed_seed_is_deterministic() {
+        let logits = [1.0, 0.5, 0.8, 0.2, 0.9];
+        let config = sampling(1.0, None, 1.0, 42);
+        let first = draw(&logits, &config, 50);
+        assert_eq!(first, draw(&logits, &config, 50));
+        assert_ne!(first, draw(&logits, &sampling(1.0, None, 1.0, 43), 50));
+
+        let mut steps = Vec::new();
+        let sampled = |steps: &mut Vec<(Vec<i64>, usize)>| {
+            let config = GenerationConfig { max_new_tokens: Some(20), ..sampling(5.0, None, 1.0, 7) };
+            decode_loop(vec![0], &config, None, counting_model(steps), decode_digits).unwrap()
+        };
+        assert_eq!(sampled(&mut steps), sampled(&mut Vec::new()));
+    }
+
+    #[test]
+    fn greedy_picks_argmax() {
+        let config = GenerationConfig::default();
+        let mut rng = SplitMix64(1);
+        assert_eq!(sample_token(&[0.1, 3.0, 2.9], &[], &config, &mut rng), Some(1));
+        assert_eq!(sample_token(&[], &[], &config, &mut rng), None);
+    }
+
+    #[test]
+    fn top_k_keeps_only_the_k_best_tokens() {
+        let logits = [2.0, 1.9, 1.8, 1.7, 1.6];
+        let drawn = draw(&logits, &sampling(1.0, Some(2), 1.0, 3), 500);
+        assert!(drawn.iter().all(|&t| t < 2), "{:?}", drawn);
+        assert!(drawn.contains(&0) && drawn.contains(&1));
+
+        assert!(draw(&logits, &sampling(1.0, Some(1), 1.0, 3), 100).iter().all(|&t| t == 0));
+    }
+
+    #[test]
+    fn top_p_keeps_the_smallest_nucleus() {
+        // Probabilidades 0.6 / 0.3 / 0.1
+        let logits = [0.6f32.ln(), 0.3f32.ln(), 0.1f32.ln()];
+        assert!(draw(&logits, &sampling(1.0, None, 0.5, 9), 300).iter().all(|&t| t == 0));
+
+        let drawn = draw(&logits, &sampling(1.0, None, 0.8, 9), 300);
+        assert!(drawn.iter().all(|&t| t < 2), "{:?}", drawn);
+        assert!(drawn.contains(&1));
+
+        assert!(draw(&logits, &sampling(1.0, None, 1.0, 9), 300).contains(&2));
+    }
+
+    #[test]
+    fn repetition_penalty_discourages_seen_tokens() {
+        let mut rng = SplitMix64(0);
+        let penalized = |penalty: f32| GenerationConfig { repetition_penalty: Some(penalty), ..Default::default() };
+
+        // Logit positivo: se divide (2.0 / 1.5 < 1.9)
+        assert_eq!(sample_token(&[2.0, 1.9], &[0], &GenerationConfig::default(), &mut rng), Some(0));
+        assert_eq!(sample_token(&[2.0, 1.9], &[0], &penalized(1.5), &mut rng), Some(1));
+        // Logit negativo: se multiplica (-1.0 * 2 < -1.2)
+        assert_eq!(sample_token(&[-1.0, -1.2], &[0], &penalized(2.0), &mut rng), Some(1));
+        // Un token repetido en el historial se penaliza una sola vez
+        assert_eq!(sample_token(&[2.0, 1.2], &[0, 0, 0], &penalized(1.5), &mut rng), Some(0));
+    }
+
+    #[test]
+    fn decode_loop_propagates_model_errors() {
+        let config = GenerationConfig::default();
+        let err = decode_loop(vec![1], &config, None, |_, _| Err("Model has no \"logits\" output".to_string()), decode_digits).unwrap_err();
+        assert!(err.contains("logits"));
 
-        // Mock response
-        Ok(format!("Local Model (Mock): Processed {} tokens. Model loaded successfully (Inference skipped due to dependency mismatch).", input_ids.len()))
+        let err = decode_loop(vec![1], &config, None, |_, _| Ok(Vec::new()), decode_digits).unwrap_err();
+        assert_eq!(err, "Empty logits");
     }
 }
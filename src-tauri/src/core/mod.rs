@@ -1,9 +1,19 @@
 pub mod auth;
+pub mod bundle;
+pub mod chunking;
+pub mod citations;
+pub mod code_chunking;
+pub mod context;
+pub mod embeddings;
+pub mod loaders;
 pub mod local_llm;
 pub mod openai;
 pub mod orchestrator;
+pub mod provider;
 pub mod rag;
+pub mod reranker;
 pub mod sandbox;
 pub mod security;
 pub mod telemetry;
 pub mod updater;
+pub mod watcher;
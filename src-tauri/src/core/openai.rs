@@ -1,10 +1,15 @@
 use serde::{Deserialize, Serialize};
 use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
 use serde_json::json;
+use futures_util::StreamExt;
+use tauri::Emitter;
+use crate::core::local_llm::GenerationConfig;
 
 #[derive(Serialize, Deserialize)]
 struct ChatCompletionResponse {
     choices: Vec<Choice>,
+    // Algunos servidores compatibles (llama.cpp) no siempre devuelven `usage`
+    #[serde(default)]
     usage: Usage,
 }
 
@@ -19,26 +24,118 @@ pub struct Message {
     pub content: String,
 }
 
-#[derive(Serialize, Deserialize)]
-struct Usage {
-    total_tokens: u32,
+/// Tokens consumidos según el servidor (0 si no los informa).
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+#[serde(default)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+pub const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+pub const OPENAI_DEFAULT_MODEL: &str = "gpt-3.5-turbo";
+
+/// Perfil de un endpoint compatible con la API de OpenAI (OpenAI, vLLM, llama.cpp, gateways tipo Azure).
+/// La API key no se guarda aquí sino en el keyring (servicio `openai_endpoint`, usuario = `name`).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EndpointProfile {
+    pub name: String,
+    pub base_url: String,
+    pub model: String,
+    #[serde(default)]
+    pub headers: std::collections::HashMap<String, String>,
+    #[serde(default)]
+    pub cost_per_1k_tokens: f64,
+    /// Tokens de contexto del modelo; `None` = `DEFAULT_CONTEXT_WINDOW`
+    #[serde(default)]
+    pub context_window: Option<usize>,
+}
+
+impl EndpointProfile {
+    /// Perfil por defecto usado por el modelo `openai_api`.
+    pub fn openai_default() -> Self {
+        Self {
+            name: "default".to_string(),
+            base_url: OPENAI_BASE_URL.to_string(),
+            model: OPENAI_DEFAULT_MODEL.to_string(),
+            headers: Default::default(),
+            // Estimación costo GPT-3.5 Turbo: $0.0005 / 1K input, $0.0015 / 1K output.
+            // Simplificación: $0.0015 / 1K total tokens medio
+            cost_per_1k_tokens: 0.0015,
+            context_window: Some(16_385),
+        }
+    }
+
+    /// Acepta tanto la URL base (`.../v1`) como la URL completa del endpoint (útil si lleva query, p.ej. `api-version`).
+    fn completions_url(&self) -> String {
+        if self.base_url.contains("/chat/completions") {
+            self.base_url.clone()
+        } else {
+            format!("{}/chat/completions", self.base_url.trim_end_matches('/'))
+        }
+    }
+
+    /// Cuerpo de la petición; de los parámetros de muestreo solo se envían los que fija quien llama.
+    fn body(&self, messages: Vec<Message>, config: Option<&GenerationConfig>, stream: bool) -> serde_json::Value {
+        let mut body = json!({
+            "model": self.model,
+            "messages": messages
+        });
+
+        if stream {
+            body["stream"] = json!(true);
+            // Pide que el último chunk incluya el uso de tokens
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+
+        if let Some(config) = config {
+            if let Some(temperature) = config.temperature {
+                body["temperature"] = json!(temperature);
+            }
+            if let Some(top_p) = config.top_p {
+                body["top_p"] = json!(top_p);
+            }
+            if let Some(max_tokens) = config.max_new_tokens {
+                body["max_tokens"] = json!(max_tokens);
+            }
+            if let Some(seed) = config.seed {
+                body["seed"] = json!(seed);
+            }
+            if !config.stop_sequences.is_empty() {
+                // La API acepta como máximo 4 stop sequences
+                body["stop"] = json!(config.stop_sequences.iter().take(4).collect::<Vec<_>>());
+            }
+        }
+        body
+    }
+
+    fn request(&self, api_key: Option<&str>, body: &serde_json::Value) -> reqwest::RequestBuilder {
+        let mut req = reqwest::Client::new()
+            .post(self.completions_url())
+            .header(CONTENT_TYPE, "application/json");
+
+        // Servidores locales (vLLM, llama.cpp) normalmente no piden key
+        if let Some(key) = api_key {
+            req = req.header(AUTHORIZATION, format!("Bearer {}", key));
+        }
+        for (name, value) in &self.headers {
+            req = req.header(name.as_str(), value.as_str());
+        }
+        req.json(body)
+    }
 }
 
+/// Devuelve el texto de la respuesta y el total de tokens consumidos.
 pub async fn send_chat_completion(
-    api_key: &str,
+    endpoint: &EndpointProfile,
+    api_key: Option<&str>,
     messages: Vec<Message>,
-) -> Result<(String, f64), String> {
-    let client = reqwest::Client::new();
-    
-    let body = json!({
-        "model": "gpt-3.5-turbo",
-        "messages": messages
-    });
-
-    let res = client.post("https://api.openai.com/v1/chat/completions")
-        .header(CONTENT_TYPE, "application/json")
-        .header(AUTHORIZATION, format!("Bearer {}", api_key))
-        .json(&body)
+    config: Option<&GenerationConfig>,
+) -> Result<(String, Usage), String> {
+    let body = endpoint.body(messages, config, false);
+
+    let res = endpoint.request(api_key, &body)
         .send()
         .await
         .map_err(|e| e.to_string())?;
@@ -53,9 +150,337 @@ pub async fn send_chat_completion(
         .map(|c| c.message.content.clone())
         .ok_or("No content in response")?;
 
-    // Estimación costo GPT-3.5 Turbo: $0.0005 / 1K input, $0.0015 / 1K output.
-    // Simplificación: $0.0015 / 1K total tokens medio
-    let cost = (response.usage.total_tokens as f64 / 1000.0) * 0.0015;
+    Ok((content, response.usage))
+}
+
+#[derive(Deserialize)]
+struct ChatCompletionChunk {
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct ChunkChoice {
+    delta: Delta,
+}
+
+#[derive(Deserialize)]
+struct Delta {
+    content: Option<String>,
+}
+
+/// Evento emitido a la UI por cada fragmento recibido en streaming. El último lleva `done`
+/// y, si el stream no terminó bien, el error.
+#[derive(Serialize, Clone)]
+pub struct StreamEvent {
+    pub request_id: String,
+    pub delta: String,
+    pub done: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub const STREAM_EVENT: &str = "chat_stream";
+
+/// Parsea una línea SSE (`data: {...}`). Devuelve `Ok(None)` para líneas vacías, comentarios,
+/// otros campos SSE o el marcador final `[DONE]`, y `Err` si el JSON no es un chunk válido.
+fn parse_sse_line(line: &str) -> Result<Option<ChatCompletionChunk>, String> {
+    let Some(data) = line.trim().strip_prefix("data:") else { return Ok(None) };
+    let data = data.trim();
+    if data.is_empty() || data == "[DONE]" {
+        return Ok(None);
+    }
+    serde_json::from_str(data).map(Some).map_err(|e| format!("{} in SSE line: {}", e, data))
+}
+
+/// Acumula los bytes del stream y devuelve las líneas completas. Se decodifica por líneas y no
+/// por chunk de red, porque un carácter UTF-8 multibyte puede llegar partido entre dos chunks.
+#[derive(Default)]
+struct LineBuffer {
+    bytes: Vec<u8>,
+}
+
+impl LineBuffer {
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.bytes.extend_from_slice(bytes);
+        let mut lines = Vec::new();
+        while let Some(pos) = self.bytes.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.bytes.drain(..=pos).collect();
+            lines.push(String::from_utf8_lossy(&line).into_owned());
+        }
+        lines
+    }
+
+    /// Resto sin salto de línea final (el servidor cerró sin `\n`).
+    fn finish(self) -> Option<String> {
+        (!self.bytes.is_empty()).then(|| String::from_utf8_lossy(&self.bytes).into_owned())
+    }
+}
+
+/// Variante en streaming de `send_chat_completion`: emite cada delta como evento
+/// `chat_stream` con el `request_id` dado y devuelve el texto completo y el uso
+/// de tokens cuando el stream termina. Siempre emite un evento final `done`, con el error si falla.
+pub async fn stream_chat_completion(
+    app_handle: &tauri::AppHandle,
+    request_id: &str,
+    endpoint: &EndpointProfile,
+    api_key: Option<&str>,
+    messages: Vec<Message>,
+    config: Option<&GenerationConfig>,
+) -> Result<(String, Usage), String> {
+    let emit = |delta: String, done: bool, error: Option<String>| {
+        let _ = app_handle.emit(STREAM_EVENT, StreamEvent {
+            request_id: request_id.to_string(),
+            delta,
+            done,
+            error,
+        });
+    };
+
+    let result = read_stream(endpoint, api_key, messages, config, |delta| emit(delta, false, None)).await;
+    emit(String::new(), true, result.as_ref().err().cloned());
+    result
+}
+
+async fn read_stream(
+    endpoint: &EndpointProfile,
+    api_key: Option<&str>,
+    messages: Vec<Message>,
+    config: Option<&GenerationConfig>,
+    mut on_delta: impl FnMut(String),
+) -> Result<(String, Usage), String> {
+    let body = endpoint.body(messages, config, true);
+
+    let res = endpoint.request(api_key, &body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !res.status().is_success() {
+        return Err(format!("API Error: {}", res.status()));
+    }
+
+    let mut stream = res.bytes_stream();
+    // Los eventos SSE pueden llegar partidos entre varios chunks de bytes
+    let mut buffer = LineBuffer::default();
+    let mut content = String::new();
+    let mut usage = Usage::default();
+
+    let mut handle_line = |line: &str, content: &mut String| {
+        let chunk = match parse_sse_line(line) {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("Skipping unparseable stream chunk: {}", e);
+                return;
+            }
+        };
+
+        if let Some(chunk_usage) = chunk.usage {
+            usage = chunk_usage;
+        }
+
+        for choice in chunk.choices {
+            if let Some(delta) = choice.delta.content {
+                if delta.is_empty() { continue; }
+                content.push_str(&delta);
+                on_delta(delta);
+            }
+        }
+    };
+
+    while let Some(bytes) = stream.next().await {
+        let bytes = bytes.map_err(|e| format!("Stream interrupted: {}", e))?;
+        for line in buffer.push(&bytes) {
+            handle_line(&line, &mut content);
+        }
+    }
+    if let Some(line) = buffer.finish() {
+        handle_line(&line, &mut content);
+    }
+
+    Ok((content, usage))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Servidor HTTP de una sola petición: responde `body` y devuelve la petición recibida (cabeceras en minúsculas).
+    pub(crate) async fn mock_server(body: &'static str) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length = text[..end]
+                        .lines()
+                        .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+                    if request.len() >= end + 4 + length {
+                        break;
+                    }
+                }
+                if n == 0 {
+                    break;
+                }
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            let text = String::from_utf8_lossy(&request).to_string();
+            let (head, rest) = text.split_once("\r\n\r\n").unwrap();
+            format!("{}\r\n\r\n{}", head.to_ascii_lowercase(), rest)
+        });
+        (url, handle)
+    }
+
+    pub(crate) const REPLY: &str = r#"{"choices":[{"message":{"role":"assistant","content":"hola"}}],"usage":{"prompt_tokens":7,"completion_tokens":3,"total_tokens":10}}"#;
+
+    pub(crate) fn profile(base_url: String) -> EndpointProfile {
+        EndpointProfile {
+            name: "test".to_string(),
+            base_url,
+            model: "mock-model".to_string(),
+            headers: Default::default(),
+            cost_per_1k_tokens: 0.0,
+            context_window: None,
+        }
+    }
+
+    pub(crate) fn user(content: &str) -> Vec<Message> {
+        vec![Message { role: "user".to_string(), content: content.to_string() }]
+    }
+
+    #[test]
+    fn body_sends_only_the_sampling_fields_that_were_set() {
+        let endpoint = profile("http://localhost".to_string());
+
+        let body = endpoint.body(user("hi"), None, false);
+        for field in ["temperature", "top_p", "max_tokens", "seed", "stop"] {
+            assert!(body.get(field).is_none(), "{}", field);
+        }
+
+        let partial: GenerationConfig = serde_json::from_str(r#"{"seed": 5}"#).unwrap();
+        let body = endpoint.body(user("hi"), Some(&partial), false);
+        assert_eq!(body["seed"], 5);
+        for field in ["temperature", "top_p", "max_tokens", "stop"] {
+            assert!(body.get(field).is_none(), "{}", field);
+        }
 
-    Ok((content, cost))
+        let full: GenerationConfig = serde_json::from_str(
+            r#"{"temperature": 0.7, "top_p": 0.9, "max_new_tokens": 64, "stop_sequences": ["a", "b", "c", "d", "e"]}"#,
+        )
+        .unwrap();
+        let body = endpoint.body(user("hi"), Some(&full), true);
+        assert_eq!(body["max_tokens"], 64);
+        assert!((body["temperature"].as_f64().unwrap() - 0.7).abs() < 1e-6);
+        assert!((body["top_p"].as_f64().unwrap() - 0.9).abs() < 1e-6);
+        assert_eq!(body["stop"].as_array().unwrap().len(), 4);
+        assert_eq!(body["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn base_url_gets_chat_completions_appended() {
+        let (url, server) = mock_server(REPLY).await;
+        let (content, usage) = send_chat_completion(&profile(format!("{}/v1/", url)), None, user("hi"), None).await.unwrap();
+        let request = server.await.unwrap();
+
+        assert!(request.starts_with("post /v1/chat/completions http/1.1"), "{}", request);
+        assert!(request.contains(r#""model":"mock-model""#));
+        assert_eq!(content, "hola");
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.total_tokens), (7, 3, 10));
+    }
+
+    #[tokio::test]
+    async fn full_completions_url_is_used_as_is() {
+        let (url, server) = mock_server(REPLY).await;
+        let full = format!("{}/openai/deployments/gpt/chat/completions?api-version=2024-02-01", url);
+        send_chat_completion(&profile(full), None, user("hi"), None).await.unwrap();
+        let request = server.await.unwrap();
+
+        assert!(request.starts_with("post /openai/deployments/gpt/chat/completions?api-version=2024-02-01 http/1.1"), "{}", request);
+    }
+
+    #[tokio::test]
+    async fn extra_headers_and_api_key_are_sent() {
+        let (url, server) = mock_server(REPLY).await;
+        let mut endpoint = profile(url);
+        endpoint.headers.insert("api-key".to_string(), "azure-secret".to_string());
+        send_chat_completion(&endpoint, Some("sk-test"), user("hi"), None).await.unwrap();
+        let request = server.await.unwrap();
+
+        assert!(request.contains("\r\napi-key: azure-secret\r\n"), "{}", request);
+        assert!(request.contains("\r\nauthorization: bearer sk-test\r\n"), "{}", request);
+    }
+
+    #[tokio::test]
+    async fn api_key_is_optional() {
+        let (url, server) = mock_server(REPLY).await;
+        send_chat_completion(&profile(url), None, user("hi"), None).await.unwrap();
+        let request = server.await.unwrap();
+
+        assert!(!request.contains("authorization:"), "{}", request);
+    }
+
+    #[tokio::test]
+    async fn missing_usage_defaults_to_zero() {
+        let (url, server) = mock_server(r#"{"choices":[{"message":{"role":"assistant","content":"sin uso"}}]}"#).await;
+        let (content, usage) = send_chat_completion(&profile(url), None, user("hi"), None).await.unwrap();
+        server.await.unwrap();
+
+        assert_eq!(content, "sin uso");
+        assert_eq!(usage.total_tokens, 0);
+    }
+
+    #[test]
+    fn line_buffer_keeps_multibyte_chars_split_across_chunks() {
+        let line = "data: {\"choices\":[{\"delta\":{\"content\":\"canción 日本\"}}]}\n";
+        let bytes = line.as_bytes();
+        // Corta dentro de la "ó" (2 bytes) y dentro de "日" (3 bytes)
+        let o = line.find('ó').unwrap() + 1;
+        let ja = line.find('日').unwrap() + 2;
+        let mut buffer = LineBuffer::default();
+        assert!(buffer.push(&bytes[..o]).is_empty());
+        assert!(buffer.push(&bytes[o..ja]).is_empty());
+        let lines = buffer.push(&bytes[ja..]);
+        assert_eq!(lines, vec![line.to_string()]);
+        assert!(buffer.finish().is_none());
+
+        let chunk = parse_sse_line(&lines[0]).unwrap().unwrap();
+        assert_eq!(chunk.choices[0].delta.content.as_deref(), Some("canción 日本"));
+    }
+
+    #[test]
+    fn line_buffer_returns_unterminated_tail() {
+        let mut buffer = LineBuffer::default();
+        assert_eq!(buffer.push(b"data: a\ndata: b"), vec!["data: a\n".to_string()]);
+        assert_eq!(buffer.finish().as_deref(), Some("data: b"));
+    }
+
+    #[test]
+    fn parse_sse_line_skips_markers_and_reports_bad_json() {
+        assert!(parse_sse_line("").unwrap().is_none());
+        assert!(parse_sse_line(": keep-alive").unwrap().is_none());
+        assert!(parse_sse_line("event: message").unwrap().is_none());
+        assert!(parse_sse_line("data: [DONE]").unwrap().is_none());
+        assert!(parse_sse_line("data: {not json").is_err());
+
+        let chunk = parse_sse_line(r#"data: {"choices":[],"usage":{"prompt_tokens":3,"completion_tokens":2,"total_tokens":5}}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(chunk.usage.unwrap().total_tokens, 5);
+    }
 }
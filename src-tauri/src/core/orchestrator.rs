@@ -1,77 +1,568 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::io::{BufRead, BufReader, Write};
 use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
 
 #[derive(Clone, Serialize, Deserialize)]
 struct PlaywrightCommand {
+    // El adapter devuelve este mismo id en cada respuesta para poder correlacionarla
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
     action: String,
     payload: Option<serde_json::Value>,
 }
 
+/// Tiempo máximo de espera por una respuesta de un web chat (navegación + generación).
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Margen que tiene el adapter para cerrar el navegador tras `close` antes de matarlo.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(3);
+
+/// Peticiones en vuelo: id -> canal por el que se entrega la respuesta del adapter.
+type PendingRequests = Arc<Mutex<HashMap<String, oneshot::Sender<serde_json::Value>>>>;
+
+/// Estado del proceso Node visible para la UI. `Unavailable` es recuperable: el supervisor reintenta.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", content = "reason")]
+pub enum BackendStatus {
+    Starting,
+    Ready,
+    Unavailable(String),
+}
+
+/// Lo que le ocurre al proceso Node, visto por el Orchestrator.
+#[derive(Debug, Clone)]
+enum StatusEvent {
+    Started,
+    AdapterReady,
+    Exited,
+    HeartbeatFailed,
+    /// No se pudo lanzar el proceso o escribir en su stdin.
+    Failed(String),
+}
+
+impl BackendStatus {
+    /// Máquina de estados del backend: el estado siguiente solo depende del actual y del evento.
+    fn next(&self, event: StatusEvent) -> BackendStatus {
+        match event {
+            StatusEvent::Started => BackendStatus::Starting,
+            StatusEvent::AdapterReady => BackendStatus::Ready,
+            StatusEvent::Exited => BackendStatus::Unavailable("Node process exited".to_string()),
+            // Si ya se sabía por qué no está disponible, se conserva ese motivo
+            StatusEvent::HeartbeatFailed => match self {
+                BackendStatus::Unavailable(_) => self.clone(),
+                _ => BackendStatus::Unavailable("Node process not responding".to_string()),
+            },
+            StatusEvent::Failed(reason) => BackendStatus::Unavailable(reason),
+        }
+    }
+}
+
+fn transition(status: &Mutex<BackendStatus>, event: StatusEvent) {
+    let mut status = status.lock().unwrap();
+    *status = status.next(event);
+}
+
+/// Espera entre reinicios: empieza en 1 s, se dobla en cada fallo hasta `MAX_BACKOFF` y vuelve
+/// a empezar en cuanto el proceso responde.
+struct RestartPolicy {
+    backoff: Duration,
+}
+
+impl RestartPolicy {
+    fn new() -> Self {
+        RestartPolicy { backoff: INITIAL_BACKOFF }
+    }
+
+    fn healthy(&mut self) {
+        self.backoff = INITIAL_BACKOFF;
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.backoff;
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+        delay
+    }
+}
+
 pub struct Orchestrator {
+    adapter_path: PathBuf,
+    child: Mutex<Option<Child>>,
     stdin: Arc<Mutex<Option<std::process::ChildStdin>>>,
+    pending: PendingRequests,
+    status: Arc<Mutex<BackendStatus>>,
+    // Cada arranque incrementa la generación; el hilo lector de un proceso antiguo no toca el estado del nuevo
+    generation: Arc<AtomicU64>,
+    shutting_down: AtomicBool,
 }
 
 impl Orchestrator {
+    /// No falla si Node no está disponible: el backend queda `Unavailable` y el supervisor lo reintentará.
     pub fn new(adapter_path: PathBuf) -> Self {
-        // Validation moved to lib.rs / updater.rs
+        let orchestrator = Orchestrator {
+            adapter_path,
+            child: Mutex::new(None),
+            stdin: Arc::new(Mutex::new(None)),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            status: Arc::new(Mutex::new(BackendStatus::Starting)),
+            generation: Arc::new(AtomicU64::new(0)),
+            shutting_down: AtomicBool::new(false),
+        };
 
-        let mut child = Command::new("node")
-            .arg(adapter_path)
+        if let Err(e) = orchestrator.start() {
+            eprintln!("{}", e);
+        }
+        orchestrator
+    }
+
+    pub fn status(&self) -> BackendStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Lanza (o relanza) el proceso Node y reenvía `init`.
+    fn start(&self) -> Result<(), String> {
+        self.kill_child();
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        transition(&self.status, StatusEvent::Started);
+
+        let spawned = Command::new("node")
+            .arg(&self.adapter_path)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
-            .spawn()
-            .expect("Failed to start node service"); // Changed service to process
+            .spawn();
+
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                let reason = format!("Failed to start node process: {}", e);
+                transition(&self.status, StatusEvent::Failed(reason.clone()));
+                return Err(reason);
+            }
+        };
 
-        let stdin = child.stdin.take().expect("Failed to open stdin"); // Take stdin handle / Tomar handle stdin
-        let stdout = child.stdout.take().expect("Failed to open stdout");
+        let stdin = child.stdin.take().ok_or("Failed to open stdin")?; // Take stdin handle / Tomar handle stdin
+        let stdout = child.stdout.take().ok_or("Failed to open stdout")?;
 
         // Hilo para leer stdout del proceso Node y loguearlo o emitirlo
         // HINT/PISTA: We spawn a thread to read stdout asynchronously. This prevents blocking the main thread
         // while waiting for Node.js output. We use channels or direct calls for communication.
         // Lanzamos un hilo para leer stdout asíncronamente. Previene bloquear el hilo principal
         // mientras esperamos salida de Node.js.
+        let pending_reader = self.pending.clone();
+        let status_reader = self.status.clone();
+        let generation_reader = self.generation.clone();
         thread::spawn(move || {
             let reader = BufReader::new(stdout);
-            for line in reader.lines() {
-                if let Ok(l) = line {
-                    println!("[Node]: {}", l);
-                    // Aquí podríamos emitir eventos a la UI si tuviéramos acceso al handle de Tauri
+            for l in reader.lines().map_while(Result::ok) {
+                // Las respuestas con id se entregan a quien las espera; el resto solo se loguea
+                let message: Option<serde_json::Value> = serde_json::from_str(&l).ok();
+                let id = message.as_ref().and_then(|m| m.get("id")).and_then(|id| id.as_str()).map(String::from);
+                let waiter = id.and_then(|id| pending_reader.lock().unwrap().remove(&id));
+
+                if message.as_ref().and_then(|m| m.get("status")).and_then(|s| s.as_str()) == Some("ready") {
+                    transition(&status_reader, StatusEvent::AdapterReady);
+                }
+
+                match (waiter, message) {
+                    (Some(tx), Some(message)) => { let _ = tx.send(message); }
+                    _ => println!("[Node]: {}", l),
                 }
             }
+
+            // EOF: el proceso ha terminado. Al soltar los senders, las peticiones en vuelo fallan en vez de colgarse.
+            if generation_reader.load(Ordering::SeqCst) == generation {
+                pending_reader.lock().unwrap().clear();
+                transition(&status_reader, StatusEvent::Exited);
+            }
         });
 
-        Orchestrator {
-            stdin: Arc::new(Mutex::new(Some(stdin))),
+        *self.stdin.lock().unwrap() = Some(stdin);
+        *self.child.lock().unwrap() = Some(child);
+
+        self.send_command("init", None);
+        Ok(())
+    }
+
+    fn kill_child(&self) {
+        *self.stdin.lock().unwrap() = None;
+        self.pending.lock().unwrap().clear();
+        if let Some(mut child) = self.child.lock().unwrap().take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    fn child_exited(&self) -> bool {
+        match self.child.lock().unwrap().as_mut() {
+            Some(child) => !matches!(child.try_wait(), Ok(None)),
+            None => true,
+        }
+    }
+
+    /// Comprueba que el proceso responde al `ping` del adapter.
+    async fn heartbeat(&self) -> bool {
+        if self.child_exited() {
+            return false;
+        }
+        let Ok((id, rx)) = self.request("ping", None) else { return false };
+        match tokio::time::timeout(HEARTBEAT_TIMEOUT, rx).await {
+            Ok(Ok(_)) => true,
+            _ => {
+                self.cancel(&id);
+                false
+            }
+        }
+    }
+
+    /// Bucle del supervisor: heartbeat periódico y reinicio con backoff exponencial si el proceso cae.
+    pub async fn supervise(orchestrator: Arc<Orchestrator>) {
+        let mut policy = RestartPolicy::new();
+
+        loop {
+            tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+            if orchestrator.shutting_down.load(Ordering::SeqCst) {
+                return;
+            }
+            if orchestrator.heartbeat().await {
+                policy.healthy();
+                continue;
+            }
+
+            loop {
+                if orchestrator.shutting_down.load(Ordering::SeqCst) {
+                    return;
+                }
+                transition(&orchestrator.status, StatusEvent::HeartbeatFailed);
+                let delay = policy.next_delay();
+                eprintln!("Browser backend unavailable, restarting in {:?}", delay);
+                tokio::time::sleep(delay).await;
+
+                if orchestrator.start().is_ok() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Cierre limpio al salir de la app: `close` al adapter y kill si no termina a tiempo.
+    pub fn shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        self.send_command("close", None);
+
+        let started = Instant::now();
+        while !self.child_exited() && started.elapsed() < SHUTDOWN_GRACE {
+            thread::sleep(Duration::from_millis(100));
+        }
+        self.kill_child();
+    }
+
+    /// Envía un comando con id y devuelve el canal por el que llegará la respuesta del adapter.
+    /// Quien llama debe esperar con timeout y llamar a `cancel` si vence.
+    pub fn request(&self, action: &str, payload: Option<serde_json::Value>) -> Result<(String, oneshot::Receiver<serde_json::Value>), String> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id.clone(), tx);
+
+        if let Err(e) = self.write_command(Some(id.clone()), action, payload) {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+        Ok((id, rx))
+    }
+
+    /// Olvida la petición y pide al adapter que la descarte si aún no la ha empezado: los comandos se
+    /// ejecutan de uno en uno y uno que ha vencido esperando turno no debe ejecutarse después.
+    pub fn cancel(&self, id: &str) {
+        if self.pending.lock().unwrap().remove(id).is_some() {
+            self.send_command("cancel", Some(serde_json::json!({ "id": id })));
         }
     }
 
     pub fn send_command(&self, action: &str, payload: Option<serde_json::Value>) {
+        if let Err(e) = self.write_command(None, action, payload) {
+            eprintln!("{}", e);
+        }
+    }
+
+    fn write_command(&self, id: Option<String>, action: &str, payload: Option<serde_json::Value>) -> Result<(), String> {
         // Security Check
         if action == "navigate" {
             if let Some(p) = &payload {
                 if let Some(url) = p.get("url").and_then(|u| u.as_str()) {
                     if !crate::core::security::SecurityPolicy::is_url_allowed(url) {
-                        eprintln!("Security Violation: Blocked navigation to {}", url);
-                        return;
+                        return Err(format!("Security Violation: Blocked navigation to {}", url));
                     }
                 }
             }
         }
 
+        if let BackendStatus::Unavailable(reason) = self.status() {
+            return Err(format!("Browser backend unavailable: {}", reason));
+        }
+
         let cmd = PlaywrightCommand {
+            id,
             action: action.to_string(),
             payload,
         };
-        let json = serde_json::to_string(&cmd).unwrap();
+        let json = serde_json::to_string(&cmd).map_err(|e| e.to_string())?;
+
+        let mut guard = self.stdin.lock().unwrap();
+        let stdin = guard.as_mut().ok_or("Browser backend unavailable: not running")?;
+        if let Err(e) = writeln!(stdin, "{}", json) {
+            drop(guard);
+            let reason = format!("Failed to write to node stdin: {}", e);
+            transition(&self.status, StatusEvent::Failed(reason.clone()));
+            return Err(reason);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if let Ok(mut guard) = self.stdin.lock() {
-            if let Some(stdin) = guard.as_mut() {
-                writeln!(stdin, "{}", json).expect("Failed to write to node stdin");
+    /// Los tests arrancan adapters falsos con Node; sin Node instalado no hay nada que probar.
+    fn node_available() -> bool {
+        Command::new("node").arg("--version").output().is_ok()
+    }
+
+    fn adapter_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fake-adapter-{}-{}.js", name, std::process::id()))
+    }
+
+    /// Arranca el Orchestrator con un adapter falso escrito en `script`.
+    fn fake_adapter(name: &str, script: &str) -> Orchestrator {
+        let path = adapter_path(name);
+        std::fs::write(&path, script).unwrap();
+        Orchestrator::new(path)
+    }
+
+    async fn wait_for(orchestrator: &Orchestrator, expected: fn(&BackendStatus) -> bool) {
+        for _ in 0..100 {
+            if expected(&orchestrator.status()) {
+                return;
             }
+            tokio::time::sleep(Duration::from_millis(50)).await;
         }
+        panic!("unexpected backend status: {:?}", orchestrator.status());
+    }
+
+    async fn wait_ready(orchestrator: &Orchestrator) {
+        wait_for(orchestrator, |s| matches!(s, BackendStatus::Ready)).await;
+    }
+
+    async fn wait_unavailable(orchestrator: &Orchestrator) {
+        wait_for(orchestrator, |s| matches!(s, BackendStatus::Unavailable(_))).await;
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap_and_resets_when_healthy() {
+        let mut policy = RestartPolicy::new();
+        let delays: Vec<u64> = (0..9).map(|_| policy.next_delay().as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60, 60]);
+
+        policy.healthy();
+        assert_eq!(policy.next_delay(), INITIAL_BACKOFF);
+        assert_eq!(policy.next_delay(), Duration::from_secs(2));
+    }
+
+    fn reason(status: &BackendStatus) -> Option<&str> {
+        match status {
+            BackendStatus::Unavailable(reason) => Some(reason),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn status_follows_the_process_lifecycle() {
+        let starting = BackendStatus::Unavailable("Node process exited".to_string()).next(StatusEvent::Started);
+        assert!(matches!(starting, BackendStatus::Starting));
+        let ready = starting.next(StatusEvent::AdapterReady);
+        assert!(matches!(ready, BackendStatus::Ready));
+
+        let exited = ready.next(StatusEvent::Exited);
+        assert_eq!(reason(&exited), Some("Node process exited"));
+        assert_eq!(reason(&ready.next(StatusEvent::HeartbeatFailed)), Some("Node process not responding"));
+        // Un heartbeat fallido no tapa el motivo ya conocido
+        assert_eq!(reason(&exited.next(StatusEvent::HeartbeatFailed)), Some("Node process exited"));
+        assert_eq!(reason(&ready.next(StatusEvent::Failed("no node".to_string()))), Some("no node"));
+    }
+
+    /// Responde a las peticiones `echo` de dos en dos y en orden inverso.
+    const OUT_OF_ORDER: &str = r#"
+        const rl = require('readline').createInterface({ input: process.stdin });
+        let held = [];
+        rl.on('line', (line) => {
+            const { id, action, payload } = JSON.parse(line);
+            if (action === 'init') console.log(JSON.stringify({ status: 'ready' }));
+            if (action === 'close') process.exit(0);
+            if (action === 'echo') {
+                held.push({ id, payload });
+                if (held.length === 2) {
+                    held.reverse().forEach((c) => console.log(JSON.stringify({ id: c.id, content: c.payload.text })));
+                    held = [];
+                }
+            }
+        });
+    "#;
+
+    #[tokio::test]
+    async fn responses_are_matched_to_requests_by_id() {
+        if !node_available() {
+            return;
+        }
+        let orchestrator = fake_adapter("order", OUT_OF_ORDER);
+        wait_ready(&orchestrator).await;
+
+        let (_, first) = orchestrator.request("echo", Some(serde_json::json!({ "text": "primero" }))).unwrap();
+        let (_, second) = orchestrator.request("echo", Some(serde_json::json!({ "text": "segundo" }))).unwrap();
+        let timeout = Duration::from_secs(5);
+        let second = tokio::time::timeout(timeout, second).await.unwrap().unwrap();
+        let first = tokio::time::timeout(timeout, first).await.unwrap().unwrap();
+
+        assert_eq!(first["content"], "primero");
+        assert_eq!(second["content"], "segundo");
+        assert!(orchestrator.pending.lock().unwrap().is_empty());
+        orchestrator.shutdown();
+    }
+
+    /// Guarda los comandos recibidos y los devuelve al pedir `seen`.
+    const RECORDER: &str = r#"
+        const rl = require('readline').createInterface({ input: process.stdin });
+        const seen = [];
+        rl.on('line', (line) => {
+            const { id, action, payload } = JSON.parse(line);
+            if (action === 'init') return console.log(JSON.stringify({ status: 'ready' }));
+            if (action === 'close') process.exit(0);
+            if (action === 'seen') return console.log(JSON.stringify({ id, seen }));
+            seen.push({ action, id: id || null, target: (payload && payload.id) || null });
+        });
+    "#;
+
+    #[tokio::test]
+    async fn cancel_tells_the_adapter_to_drop_the_command() {
+        if !node_available() {
+            return;
+        }
+        let orchestrator = fake_adapter("cancel", RECORDER);
+        wait_ready(&orchestrator).await;
+
+        let (id, _rx) = orchestrator.request("chat_glm", None).unwrap();
+        orchestrator.cancel(&id);
+        // Cancelar otra vez (o algo ya respondido) no vuelve a avisar al adapter
+        orchestrator.cancel(&id);
+
+        let (_, rx) = orchestrator.request("seen", None).unwrap();
+        let seen = tokio::time::timeout(Duration::from_secs(5), rx).await.unwrap().unwrap();
+        assert_eq!(
+            seen["seen"],
+            serde_json::json!([
+                { "action": "chat_glm", "id": id, "target": null },
+                { "action": "cancel", "id": null, "target": id },
+            ])
+        );
+        orchestrator.shutdown();
+    }
+
+    /// Solo se declara listo al recibir `init`, y termina con `crash` o `close`.
+    const RESTARTABLE: &str = r#"
+        const rl = require('readline').createInterface({ input: process.stdin });
+        rl.on('line', (line) => {
+            const { action } = JSON.parse(line);
+            if (action === 'init') console.log(JSON.stringify({ status: 'ready' }));
+            if (action === 'crash' || action === 'close') process.exit(0);
+        });
+    "#;
+
+    #[tokio::test]
+    async fn restart_replays_init() {
+        if !node_available() {
+            return;
+        }
+        let orchestrator = fake_adapter("restart", RESTARTABLE);
+        wait_ready(&orchestrator).await;
+
+        orchestrator.send_command("crash", None);
+        wait_unavailable(&orchestrator).await;
+        assert_eq!(reason(&orchestrator.status()), Some("Node process exited"));
+        assert!(orchestrator.request("echo", None).is_err());
+
+        // El adapter nuevo solo llega a Ready si el Orchestrator le vuelve a mandar `init`
+        orchestrator.start().unwrap();
+        wait_ready(&orchestrator).await;
+        orchestrator.shutdown();
+    }
+
+    #[tokio::test]
+    async fn unavailable_backend_becomes_available_after_restart() {
+        if !node_available() {
+            return;
+        }
+        // Sin script, Node termina enseguida y el backend queda no disponible
+        let path = adapter_path("late");
+        let _ = std::fs::remove_file(&path);
+        let orchestrator = Orchestrator::new(path.clone());
+        wait_unavailable(&orchestrator).await;
+
+        std::fs::write(&path, RESTARTABLE).unwrap();
+        orchestrator.start().unwrap();
+        wait_ready(&orchestrator).await;
+        assert!(orchestrator.request("echo", None).is_ok());
+        orchestrator.shutdown();
+    }
+
+    /// Ignora `close`, como un navegador que no consigue cerrarse.
+    const STUBBORN: &str = r#"
+        const rl = require('readline').createInterface({ input: process.stdin });
+        rl.on('line', (line) => {
+            if (JSON.parse(line).action === 'init') console.log(JSON.stringify({ status: 'ready' }));
+        });
+    "#;
+
+    fn child_pid(orchestrator: &Orchestrator) -> u32 {
+        orchestrator.child.lock().unwrap().as_ref().unwrap().id()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn shutdown_kills_a_child_that_ignores_close_after_the_grace_period() {
+        if !node_available() {
+            return;
+        }
+        let orchestrator = fake_adapter("stubborn", STUBBORN);
+        wait_ready(&orchestrator).await;
+        let pid = child_pid(&orchestrator);
+
+        let started = Instant::now();
+        orchestrator.shutdown();
+        assert!(started.elapsed() >= SHUTDOWN_GRACE);
+        assert!(!std::path::Path::new(&format!("/proc/{}", pid)).exists());
+        assert!(orchestrator.child.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn shutdown_does_not_wait_for_a_child_that_obeys_close() {
+        if !node_available() {
+            return;
+        }
+        let orchestrator = fake_adapter("obedient", RESTARTABLE);
+        wait_ready(&orchestrator).await;
+
+        let started = Instant::now();
+        orchestrator.shutdown();
+        assert!(started.elapsed() < SHUTDOWN_GRACE);
+        assert!(orchestrator.child.lock().unwrap().is_none());
     }
 }
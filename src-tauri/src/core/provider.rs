@@ -0,0 +1,521 @@
+use crate::core::local_llm::{EngineSlot, GenerationConfig};
+use crate::core::openai::{EndpointProfile, Message, StreamEvent, Usage, STREAM_EVENT};
+use crate::core::orchestrator::{Orchestrator, REQUEST_TIMEOUT};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use tauri::Emitter;
+use tokenizers::Tokenizer;
+
+/// Qué soporta cada backend, para que `send_prompt` (y la UI) no tengan que adivinarlo.
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    pub streaming: bool,
+    pub system_prompt: bool,
+    pub multi_turn: bool,
+    /// Tokens de contexto del modelo (prompt + respuesta)
+    pub context_window: usize,
+    #[serde(skip)]
+    pub tokens: TokenCounter,
+}
+
+impl Capabilities {
+    /// Tokens del texto según el tokenizer del modelo (o la estimación, si no hay tokenizer).
+    pub fn count_tokens(&self, text: &str) -> usize {
+        self.tokens.count(text)
+    }
+}
+
+/// Cómo se cuentan los tokens para presupuestar el contexto.
+#[derive(Clone)]
+pub enum TokenCounter {
+    /// Tokenizer del modelo o, en los de API, un BPE de la misma familia
+    Tokenizer(Arc<Tokenizer>),
+    /// Sin tokenizer, por caracteres. Mejor quedarse corto: un valor bajo sobreestima los tokens y deja margen
+    CharsPerToken(f32),
+}
+
+impl TokenCounter {
+    pub fn count(&self, text: &str) -> usize {
+        match self {
+            TokenCounter::Tokenizer(tokenizer) => match tokenizer.encode(text, false) {
+                Ok(encoding) => encoding.len(),
+                Err(_) => estimate_tokens(text, API_CHARS_PER_TOKEN),
+            },
+            TokenCounter::CharsPerToken(chars_per_token) => estimate_tokens(text, *chars_per_token),
+        }
+    }
+
+    /// Contador de los modelos de API: el BPE incluido con la app (`tokenizer.json`), o la estimación
+    /// por caracteres si no está.
+    pub fn api(tokenizer_path: Option<&Path>) -> Self {
+        match tokenizer_path.map(Tokenizer::from_file) {
+            Some(Ok(tokenizer)) => TokenCounter::Tokenizer(Arc::new(tokenizer)),
+            Some(Err(e)) => {
+                eprintln!("API tokenizer not available, estimating tokens by length: {}", e);
+                TokenCounter::CharsPerToken(API_CHARS_PER_TOKEN)
+            }
+            None => TokenCounter::CharsPerToken(API_CHARS_PER_TOKEN),
+        }
+    }
+}
+
+impl std::fmt::Debug for TokenCounter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenCounter::Tokenizer(_) => f.write_str("Tokenizer"),
+            TokenCounter::CharsPerToken(chars) => write!(f, "CharsPerToken({})", chars),
+        }
+    }
+}
+
+fn estimate_tokens(text: &str, chars_per_token: f32) -> usize {
+    (text.chars().count() as f32 / chars_per_token).ceil() as usize
+}
+
+/// Ventana de contexto supuesta para endpoints que no la declaran.
+pub const DEFAULT_CONTEXT_WINDOW: usize = 8192;
+
+/// BPE (formato `tokenizer.json` de Hugging Face, p.ej. cl100k) con el que se cuentan los tokens de
+/// los modelos de API, entre los recursos de la app.
+pub const API_TOKENIZER_RESOURCE: &str = "tokenizers/api-bpe.json";
+
+/// Sin el BPE: rondan 4 caracteres por token en inglés, pero bastantes menos en español o en código.
+const API_CHARS_PER_TOKEN: f32 = 3.0;
+
+/// Resultado de una llamada a un proveedor.
+pub struct Completion {
+    pub content: String,
+    /// 0 si el backend no informa de los tokens
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+impl Completion {
+    /// Completion de un backend que no informa de tokens.
+    pub fn text(content: String) -> Self {
+        Self { content, prompt_tokens: 0, completion_tokens: 0, total_tokens: 0 }
+    }
+
+    pub fn with_usage(content: String, usage: Usage) -> Self {
+        Self {
+            content,
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            total_tokens: usage.total_tokens,
+        }
+    }
+}
+
+/// Contrato común de todos los backends de chat (API, modelo local, web chats vía Playwright).
+/// Añadir un backend nuevo = implementar este trait y registrarlo en `ProviderRegistry`.
+#[async_trait]
+pub trait ChatProvider: Send + Sync {
+    fn id(&self) -> &str;
+
+    fn capabilities(&self) -> Capabilities;
+
+    async fn complete(&self, messages: Vec<Message>, config: Option<&GenerationConfig>) -> Result<Completion, String>;
+
+    /// Por defecto los proveedores sin streaming real emiten la respuesta completa como un único delta.
+    async fn stream(&self, app_handle: &tauri::AppHandle, request_id: &str, messages: Vec<Message>, config: Option<&GenerationConfig>) -> Result<Completion, String> {
+        let result = self.complete(messages, config).await;
+        let events = match &result {
+            Ok(completion) => vec![(completion.content.clone(), false, None), (String::new(), true, None)],
+            Err(e) => vec![(String::new(), true, Some(e.clone()))],
+        };
+        for (delta, done, error) in events {
+            let _ = app_handle.emit(STREAM_EVENT, StreamEvent {
+                request_id: request_id.to_string(),
+                delta,
+                done,
+                error,
+            });
+        }
+        result
+    }
+
+    /// Coste estimado en USD de una completion.
+    fn cost(&self, _completion: &Completion) -> f64 {
+        0.0
+    }
+}
+
+/// Junta system prompt y mensajes en un único texto para backends que solo aceptan un prompt.
+fn flatten_messages(messages: &[Message]) -> String {
+    let system: Vec<&str> = messages.iter().filter(|m| m.role == "system").map(|m| m.content.as_str()).collect();
+    let user = messages.iter().rev().find(|m| m.role == "user").map(|m| m.content.clone()).unwrap_or_default();
+
+    if system.is_empty() {
+        user
+    } else {
+        format!("Instrucciones del Sistema:\n{}\n\nUsuario:\n{}", system.join("\n"), user)
+    }
+}
+
+/// Cualquier endpoint compatible con la API de OpenAI. `openai_api` es el perfil por defecto;
+/// los perfiles guardados en la DB se registran como `endpoint:<nombre>`.
+pub struct OpenAiProvider {
+    id: String,
+    endpoint: EndpointProfile,
+    key_required: bool,
+    tokens: TokenCounter,
+}
+
+impl OpenAiProvider {
+    pub fn openai_default(tokens: TokenCounter) -> Self {
+        Self { id: "openai_api".to_string(), endpoint: EndpointProfile::openai_default(), key_required: true, tokens }
+    }
+
+    pub fn from_profile(endpoint: EndpointProfile, tokens: TokenCounter) -> Self {
+        Self { id: Self::profile_id(&endpoint.name), endpoint, key_required: false, tokens }
+    }
+
+    pub fn profile_id(name: &str) -> String {
+        format!("endpoint:{}", name)
+    }
+
+    fn api_key(&self) -> Result<Option<String>, String> {
+        if self.key_required {
+            crate::core::auth::AuthManager::get_password("openai_api", "default")
+                .map(Some)
+                .map_err(|_| "API Key not found. Please add account for 'openai_api' with username 'default'".to_string())
+        } else {
+            Ok(crate::core::auth::AuthManager::get_password(ENDPOINT_KEY_SERVICE, &self.endpoint.name).ok())
+        }
+    }
+}
+
+/// Servicio del keyring bajo el que se guardan las keys de los perfiles de endpoint.
+pub const ENDPOINT_KEY_SERVICE: &str = "openai_endpoint";
+
+#[async_trait]
+impl ChatProvider for OpenAiProvider {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            streaming: true,
+            system_prompt: true,
+            multi_turn: true,
+            context_window: self.endpoint.context_window.unwrap_or(DEFAULT_CONTEXT_WINDOW),
+            tokens: self.tokens.clone(),
+        }
+    }
+
+    async fn complete(&self, messages: Vec<Message>, config: Option<&GenerationConfig>) -> Result<Completion, String> {
+        let api_key = self.api_key()?;
+        let (content, usage) = crate::core::openai::send_chat_completion(&self.endpoint, api_key.as_deref(), messages, config).await?;
+        Ok(Completion::with_usage(content, usage))
+    }
+
+    async fn stream(&self, app_handle: &tauri::AppHandle, request_id: &str, messages: Vec<Message>, config: Option<&GenerationConfig>) -> Result<Completion, String> {
+        let api_key = self.api_key()?;
+        let (content, usage) = crate::core::openai::stream_chat_completion(app_handle, request_id, &self.endpoint, api_key.as_deref(), messages, config).await?;
+        Ok(Completion::with_usage(content, usage))
+    }
+
+    fn cost(&self, completion: &Completion) -> f64 {
+        (completion.total_tokens as f64 / 1000.0) * self.endpoint.cost_per_1k_tokens
+    }
+}
+
+/// Modelo ONNX local. Comparte el slot de `AppState` para seguir load/unload.
+pub struct LocalOnnxProvider {
+    engine: EngineSlot,
+}
+
+impl LocalOnnxProvider {
+    pub fn new(engine: EngineSlot) -> Self {
+        Self { engine }
+    }
+}
+
+#[async_trait]
+impl ChatProvider for LocalOnnxProvider {
+    fn id(&self) -> &str {
+        "local_phi2"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // Sin el modelo cargado se estima: el BPE de GPT-2 de Phi-2 parte el texto que no es inglés
+        // en más trozos que los de los modelos de API
+        let tokens = match self.engine.lock().unwrap().as_ref() {
+            Some(loaded) => TokenCounter::Tokenizer(loaded.tokenizer.clone()),
+            None => TokenCounter::CharsPerToken(2.5),
+        };
+        // Phi-2 se entrenó con 2048 tokens de contexto
+        Capabilities { streaming: false, system_prompt: false, multi_turn: false, context_window: 2048, tokens }
+    }
+
+    async fn complete(&self, messages: Vec<Message>, config: Option<&GenerationConfig>) -> Result<Completion, String> {
+        // Sin soporte de system prompt: solo el último mensaje del usuario
+        let prompt = messages.iter().rev().find(|m| m.role == "user").map(|m| m.content.clone()).unwrap_or_default();
+
+        let engine = self
+            .engine
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or("Local model not loaded. Please download/load it first.")?
+            .engine;
+        let config = config.cloned();
+        // La decodificación es CPU pura y larga: fuera de los workers de tokio
+        let content = tokio::task::spawn_blocking(move || engine.lock().unwrap().generate(&prompt, config.as_ref()))
+            .await
+            .map_err(|e| e.to_string())??;
+        Ok(Completion::text(content))
+    }
+}
+
+/// Web chats automatizados con Playwright a través del `Orchestrator`.
+pub struct WebChatProvider {
+    id: String,
+    action: String,
+    orchestrator: Arc<Orchestrator>,
+    tokens: TokenCounter,
+}
+
+impl WebChatProvider {
+    pub fn new(id: &str, action: &str, orchestrator: Arc<Orchestrator>, tokens: TokenCounter) -> Self {
+        Self { id: id.to_string(), action: action.to_string(), orchestrator, tokens }
+    }
+}
+
+#[async_trait]
+impl ChatProvider for WebChatProvider {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // El límite real lo pone la caja de texto de cada web; 32k tokens es seguro en todas
+        Capabilities { streaming: false, system_prompt: true, multi_turn: false, context_window: 32_000, tokens: self.tokens.clone() }
+    }
+
+    // Los web chats no exponen parámetros de muestreo
+    async fn complete(&self, messages: Vec<Message>, _config: Option<&GenerationConfig>) -> Result<Completion, String> {
+        let prompt = flatten_messages(&messages);
+        let payload = serde_json::json!({ "prompt": prompt });
+        let (id, rx) = self.orchestrator.request(&self.action, Some(payload))?;
+
+        let response = match tokio::time::timeout(REQUEST_TIMEOUT, rx).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => return Err(format!("{}: browser backend closed before answering", self.id)),
+            Err(_) => {
+                self.orchestrator.cancel(&id);
+                return Err(format!("{}: timed out waiting for response", self.id));
+            }
+        };
+
+        match response.get("status").and_then(|s| s.as_str()) {
+            Some("response_received") => Ok(Completion::text(
+                response.get("content").and_then(|c| c.as_str()).unwrap_or_default().to_string(),
+            )),
+            Some("error") => Err(format!(
+                "{}: {} ({})",
+                self.id,
+                response.get("error").and_then(|e| e.as_str()).unwrap_or("unknown error"),
+                response.get("details").and_then(|d| d.as_str()).unwrap_or_default()
+            )),
+            other => Err(format!("{}: unexpected response status {:?}", self.id, other)),
+        }
+    }
+}
+
+/// Registro de proveedores por id de modelo (el mismo id que usa el selector de la UI).
+pub struct ProviderRegistry {
+    providers: HashMap<String, Arc<dyn ChatProvider>>,
+    fallback: String,
+    // Contador compartido por los proveedores de API, también los que se registran más tarde
+    api_tokens: TokenCounter,
+}
+
+impl ProviderRegistry {
+    pub fn new(fallback: &str, api_tokens: TokenCounter) -> Self {
+        Self { providers: HashMap::new(), fallback: fallback.to_string(), api_tokens }
+    }
+
+    pub fn api_tokens(&self) -> TokenCounter {
+        self.api_tokens.clone()
+    }
+
+    pub fn register(&mut self, provider: Arc<dyn ChatProvider>) {
+        self.providers.insert(provider.id().to_string(), provider);
+    }
+
+    pub fn unregister(&mut self, id: &str) {
+        self.providers.remove(id);
+    }
+
+    /// Ids desconocidos caen en el proveedor por defecto, como hacía el antiguo `match`.
+    pub fn get(&self, id: &str) -> Option<Arc<dyn ChatProvider>> {
+        self.providers
+            .get(id)
+            .or_else(|| self.providers.get(&self.fallback))
+            .cloned()
+    }
+
+    pub fn list(&self) -> Vec<(String, Capabilities)> {
+        let mut list: Vec<(String, Capabilities)> = self.providers
+            .values()
+            .map(|p| (p.id().to_string(), p.capabilities()))
+            .collect();
+        list.sort_by(|a, b| a.0.cmp(&b.0));
+        list
+    }
+
+    /// Registro con todos los backends incluidos en la app.
+    pub fn with_defaults(local_llm: EngineSlot, orchestrator: Arc<Orchestrator>, endpoints: Vec<EndpointProfile>, api_tokens: TokenCounter) -> Self {
+        let mut registry = Self::new("cloud_deepseek", api_tokens.clone());
+        registry.register(Arc::new(OpenAiProvider::openai_default(api_tokens.clone())));
+        for endpoint in endpoints {
+            registry.register(Arc::new(OpenAiProvider::from_profile(endpoint, api_tokens.clone())));
+        }
+        registry.register(Arc::new(LocalOnnxProvider::new(local_llm)));
+        for (id, action) in [
+            ("chatgpt", "chat_chatgpt"),
+            ("cloud_glm", "chat_glm"),
+            ("cloud_kimi", "chat_kimi"),
+            ("cloud_deepseek", "chat_deepseek"),
+        ] {
+            registry.register(Arc::new(WebChatProvider::new(id, action, orchestrator.clone(), api_tokens.clone())));
+        }
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::openai::tests::{mock_server, profile, user, REPLY};
+
+    struct FakeProvider(&'static str);
+
+    #[async_trait]
+    impl ChatProvider for FakeProvider {
+        fn id(&self) -> &str {
+            self.0
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities { streaming: false, system_prompt: true, multi_turn: true, context_window: 1000, tokens: TokenCounter::CharsPerToken(4.0) }
+        }
+
+        async fn complete(&self, _messages: Vec<Message>, _config: Option<&GenerationConfig>) -> Result<Completion, String> {
+            Ok(Completion::text(self.0.to_string()))
+        }
+    }
+
+    fn registry(ids: &[&'static str]) -> ProviderRegistry {
+        let mut registry = ProviderRegistry::new("cloud_deepseek", TokenCounter::CharsPerToken(4.0));
+        for id in ids {
+            registry.register(Arc::new(FakeProvider(id)));
+        }
+        registry
+    }
+
+    fn ids(registry: &ProviderRegistry) -> Vec<String> {
+        registry.list().into_iter().map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn registry_lists_providers_sorted_by_id() {
+        let registry = registry(&["local_phi2", "cloud_deepseek", "chatgpt"]);
+        assert_eq!(ids(&registry), ["chatgpt", "cloud_deepseek", "local_phi2"]);
+        assert!(registry.list().iter().all(|(_, caps)| caps.context_window == 1000));
+    }
+
+    #[test]
+    fn unknown_ids_fall_back_to_the_default_provider() {
+        let registry = registry(&["chatgpt", "cloud_deepseek"]);
+        assert_eq!(registry.get("chatgpt").unwrap().id(), "chatgpt");
+        assert_eq!(registry.get("no-existe").unwrap().id(), "cloud_deepseek");
+
+        // Sin el proveedor por defecto registrado no hay a dónde caer
+        assert!(ProviderRegistry::new("cloud_deepseek", TokenCounter::CharsPerToken(4.0)).get("no-existe").is_none());
+    }
+
+    #[test]
+    fn register_replaces_and_unregister_removes() {
+        let mut registry = registry(&["chatgpt", "cloud_deepseek"]);
+        registry.register(Arc::new(FakeProvider("chatgpt")));
+        assert_eq!(ids(&registry), ["chatgpt", "cloud_deepseek"]);
+
+        registry.unregister("chatgpt");
+        assert_eq!(ids(&registry), ["cloud_deepseek"]);
+        assert_eq!(registry.get("chatgpt").unwrap().id(), "cloud_deepseek");
+    }
+
+    #[tokio::test]
+    async fn endpoint_provider_reports_usage_cost_and_capabilities() {
+        let (url, server) = mock_server(REPLY).await;
+        let endpoint = EndpointProfile { cost_per_1k_tokens: 2.0, context_window: Some(4096), ..profile(url) };
+        let provider = OpenAiProvider::from_profile(endpoint, TokenCounter::api(None));
+        assert_eq!(provider.id(), "endpoint:test");
+
+        let completion = provider.complete(user("hola"), None).await.unwrap();
+        server.await.unwrap();
+        assert_eq!(completion.content, "hola");
+        assert_eq!((completion.prompt_tokens, completion.completion_tokens, completion.total_tokens), (7, 3, 10));
+        // 10 tokens a 2 USD cada 1000
+        assert!((provider.cost(&completion) - 0.02).abs() < 1e-9);
+
+        let caps = provider.capabilities();
+        assert!(caps.streaming && caps.system_prompt && caps.multi_turn);
+        assert_eq!(caps.context_window, 4096);
+
+        let default_window = OpenAiProvider::from_profile(profile("http://localhost".to_string()), TokenCounter::api(None));
+        assert_eq!(default_window.capabilities().context_window, DEFAULT_CONTEXT_WINDOW);
+        assert_eq!(default_window.cost(&completion), 0.0);
+    }
+
+    // tokenizer.json mínimo pero real: palabras enteras, separadas por espacios y puntuación
+    const WORD_TOKENIZER: &str = r#"{
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [],
+        "normalizer": null,
+        "pre_tokenizer": { "type": "Whitespace" },
+        "post_processor": null,
+        "decoder": null,
+        "model": { "type": "WordLevel", "vocab": { "[UNK]": 0, "hola": 1, "mundo": 2 }, "unk_token": "[UNK]" }
+    }"#;
+
+    fn word_tokenizer(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("tokenizer-{}-{}.json", name, std::process::id()));
+        std::fs::write(&path, WORD_TOKENIZER).unwrap();
+        path
+    }
+
+    #[test]
+    fn api_tokens_are_counted_with_the_bundled_tokenizer() {
+        let path = word_tokenizer("api");
+        let tokens = TokenCounter::api(Some(&path));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(tokens, TokenCounter::Tokenizer(_)));
+
+        // "hola", "mundo", "," y "adiós": 4 tokens, no los ceil(17 / 3) = 6 de la estimación
+        let text = "hola mundo, adiós";
+        assert_eq!(tokens.count(text), 4);
+        assert_eq!(TokenCounter::CharsPerToken(API_CHARS_PER_TOKEN).count(text), 6);
+
+        let provider = OpenAiProvider::from_profile(profile("http://localhost".to_string()), tokens);
+        assert_eq!(provider.capabilities().count_tokens(text), 4);
+    }
+
+    #[test]
+    fn without_the_tokenizer_api_tokens_are_estimated() {
+        let missing = std::env::temp_dir().join(format!("tokenizer-missing-{}.json", std::process::id()));
+        for tokens in [TokenCounter::api(Some(&missing)), TokenCounter::api(None)] {
+            assert!(matches!(tokens, TokenCounter::CharsPerToken(c) if c == API_CHARS_PER_TOKEN));
+            assert_eq!(tokens.count("hola mundo, adiós"), 6);
+        }
+    }
+}
@@ -1,34 +1,368 @@
+use crate::core::bundle::{Bundle, BundleChunk, BundleManifest, BundleSource, EmbeddingInfo, BUNDLE_FORMAT, BUNDLE_FORMAT_VERSION};
+use crate::core::chunking::{ChunkerConfig, CHUNKER_VERSION};
+use crate::core::code_chunking;
+use crate::core::embeddings::{blob_to_vector, cosine_similarity, vector_to_blob, EmbeddingEngine};
+use crate::core::loaders::{self, SkippedFile};
+use crate::core::reranker::CrossEncoder;
 use serde::{Serialize, Deserialize};
-use sqlx::{SqlitePool, Row};
+use sha2::{Digest, Sha256};
+use sqlx::{QueryBuilder, Row, Sqlite, SqlitePool};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokenizers::Tokenizer;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Constante k de reciprocal-rank fusion (valor estándar del paper de Cormack et al.).
+const RRF_K: f64 = 60.0;
+
+/// `Auto` usa lo mejor que haya disponible; `Semantic` e `Hybrid` se piden explícitamente y fallan si
+/// no hay modelo de embeddings cargado, en vez de devolver en silencio resultados solo por keywords.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// Semántica si hay modelo de embeddings cargado, si no keywords
+    #[default]
+    Auto,
+    Keyword,
+    /// Similitud coseno; los chunks aún sin vector del modelo se buscan por keywords y se fusionan
+    Semantic,
+    /// Keywords + semántica fusionadas con reciprocal-rank fusion
+    Hybrid,
+}
+
+/// Opciones de búsqueda seleccionables por query desde `rag_search` y `send_prompt`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchOptions {
+    pub mode: SearchMode,
+    /// Reordenar los mejores `rerank_top_n` candidatos con el cross-encoder
+    pub rerank: bool,
+    pub rerank_top_n: Option<usize>,
+}
+
+/// Filtros de metadatos. Los vacíos no filtran.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchFilters {
+    /// Patrones GLOB de SQLite sobre la ruta (`src/*.rs`, `docs/*`); basta con que encaje uno
+    pub filename_globs: Vec<String>,
+    /// Fechas (`YYYY-MM-DD`) inclusivas sobre `created_at` del chunk (la fecha de su última ingesta)
+    pub created_after: Option<String>,
+    pub created_before: Option<String>,
+    /// Lenguajes detectados por extensión (`rust`, `markdown`, `pdf`...)
+    pub languages: Vec<String>,
+    /// El documento debe tener al menos una de estas etiquetas
+    pub tags: Vec<String>,
+    pub min_score: Option<f64>,
+}
+
+/// Búsqueda sobre una o varias colecciones.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchQuery {
+    pub text: String,
+    pub collections: Vec<String>,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+    #[serde(default)]
+    pub filters: SearchFilters,
+    #[serde(default)]
+    pub options: SearchOptions,
+}
+
+fn default_limit() -> i64 {
+    5
+}
+
+impl SearchQuery {
+    pub fn new(collection: &str, text: &str, limit: i64, options: SearchOptions) -> Self {
+        Self { text: text.to_string(), collections: vec![collection.to_string()], limit, filters: SearchFilters::default(), options }
+    }
+}
+
+/// Añade a la consulta (que ya tiene un `WHERE`) las condiciones de colección y filtros.
+/// Espera los alias `d` (documents) y `s` (sources).
+fn push_filters(qb: &mut QueryBuilder<'_, Sqlite>, query: &SearchQuery) {
+    let filters = &query.filters;
+
+    qb.push(" AND d.collection IN (");
+    let mut list = qb.separated(", ");
+    for collection in &query.collections {
+        list.push_bind(collection.clone());
+    }
+    // `IN ()` no es SQL válido; una lista vacía no debe devolver nada
+    if query.collections.is_empty() {
+        list.push("NULL");
+    }
+    qb.push(")");
+
+    if !filters.filename_globs.is_empty() {
+        qb.push(" AND (");
+        let mut globs = qb.separated(" OR ");
+        for glob in &filters.filename_globs {
+            globs.push("d.filename GLOB ");
+            globs.push_bind_unseparated(glob.clone());
+        }
+        qb.push(")");
+    }
+    if let Some(after) = &filters.created_after {
+        qb.push(" AND DATE(d.created_at) >= DATE(").push_bind(after.clone()).push(")");
+    }
+    if let Some(before) = &filters.created_before {
+        qb.push(" AND DATE(d.created_at) <= DATE(").push_bind(before.clone()).push(")");
+    }
+    if !filters.languages.is_empty() {
+        qb.push(" AND s.language IN (");
+        let mut list = qb.separated(", ");
+        for language in &filters.languages {
+            list.push_bind(language.to_lowercase());
+        }
+        qb.push(")");
+    }
+    if !filters.tags.is_empty() {
+        qb.push(" AND EXISTS (SELECT 1 FROM source_tags t WHERE t.source_id = d.source_id AND t.tag IN (");
+        let mut list = qb.separated(", ");
+        for tag in &filters.tags {
+            list.push_bind(tag.trim().to_lowercase());
+        }
+        qb.push("))");
+    }
+}
+
+/// Colección con sus documentos (sources) y chunks.
+#[derive(Debug, Clone, Serialize)]
+pub struct CollectionInfo {
+    pub name: String,
+    pub documents: i64,
+    pub chunks: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DocumentChunk {
     pub id: i64,
     pub collection: String,
     pub filename: String,
     pub content: String,
+    /// Relevancia (mayor es mejor). Para búsqueda por keywords es el BM25 de FTS5 con signo invertido;
+    /// para búsqueda semántica, la similitud coseno, y si se fusionan varias listas, la puntuación RRF.
+    #[serde(default)]
+    pub score: f64,
+    /// Fragmento con los términos encontrados marcados entre `**`.
+    #[serde(default)]
+    pub snippet: String,
+    /// Rango (bytes) del chunk en el documento original. `None` en documentos ingeridos sin offsets.
+    #[serde(default)]
+    pub start_offset: Option<i64>,
+    #[serde(default)]
+    pub end_offset: Option<i64>,
+    #[serde(default)]
+    pub start_line: Option<i64>,
+    #[serde(default)]
+    pub end_line: Option<i64>,
+    /// Función, clase... a la que pertenece el chunk si viene de código fuente
+    #[serde(default)]
+    pub symbol: Option<String>,
+}
+
+impl DocumentChunk {
+    /// Referencia para citar el chunk en el contexto: `file.rs:120-180` o solo el nombre del fichero.
+    pub fn citation(&self) -> String {
+        match (self.start_line, self.end_line) {
+            (Some(start), Some(end)) if start != end => format!("{}:{}-{}", self.filename, start, end),
+            (Some(start), _) => format!("{}:{}", self.filename, start),
+            _ => self.filename.clone(),
+        }
+    }
+}
+
+/// Número de línea (1-based) del byte `offset`.
+fn line_at(content: &str, offset: usize) -> i64 {
+    content[..offset].matches('\n').count() as i64 + 1
+}
+
+/// Convierte la pregunta del usuario en una query FTS5: cada palabra como término entrecomillado,
+/// unidas con OR para que BM25 ordene por cuántos términos (y cuán raros) aparecen.
+pub fn fts_query(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|t| !t.is_empty())
+        .map(|t| format!("\"{}\"", t))
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" OR "))
+    }
+}
+
+/// Resultado de ingerir un documento frente a lo que ya había en la colección.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IngestOutcome {
+    Added,
+    Updated,
+    Unchanged,
+}
+
+/// Fecha de modificación en segundos Unix.
+fn file_mtime(path: &Path) -> Option<i64> {
+    path.metadata()
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+}
+
+fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// Progreso de `ingest_directory`, emitido por cada fichero procesado.
+#[derive(Debug, Clone, Serialize)]
+pub struct IngestProgress {
+    pub collection: String,
+    pub path: String,
+    /// 1-based
+    pub index: usize,
+    pub total: usize,
+    /// `None` si el fichero se ha saltado (ver `error`)
+    pub outcome: Option<IngestOutcome>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IngestSummary {
+    pub collection: String,
+    /// Ficheros nuevos o modificados
+    pub ingested: usize,
+    pub unchanged: usize,
+    pub skipped: Vec<SkippedFile>,
+}
+
+/// Resultado de `import_collection`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportSummary {
+    pub collection: String,
+    pub sources: usize,
+    pub chunks: usize,
+    /// Chunks importados con su vector
+    pub embeddings: usize,
+    /// Motivo por el que se han descartado los embeddings del bundle (modelo incompatible)
+    pub embeddings_rejected: Option<String>,
+    /// Diferencias con esta app que no impiden importar (p.ej. otra versión del chunker)
+    pub warnings: Vec<String>,
+}
+
+/// Id del modelo de embeddings + un vector por texto.
+type ModelVectors = (String, Vec<Vec<f32>>);
+
+/// Textos por lote de inferencia: cada lote se rellena hasta el más largo, así que un documento
+/// grande en un solo lote daría un tensor enorme.
+const EMBED_BATCH_SIZE: usize = 32;
+
+/// Modelo de embeddings cargado. El motor va en su propio `Mutex` para usarlo desde `spawn_blocking`
+/// sin bloquear el slot; el id se guarda aparte para consultarlo sin esperar a una inferencia.
+#[derive(Clone)]
+struct LoadedEmbedder {
+    model_id: String,
+    model_hash: String,
+    engine: Arc<Mutex<EmbeddingEngine>>,
 }
 
 pub struct RagManager {
     pool: SqlitePool,
+    // Opcional: sin modelo de embeddings la búsqueda es solo por keywords
+    embedder: Mutex<Option<LoadedEmbedder>>,
+    reranker: Mutex<Option<Arc<Mutex<CrossEncoder>>>>,
+    // Tokenizer del modelo de embeddings (sin truncado) para el chunker por ventana de tokens
+    tokenizer: Mutex<Option<Tokenizer>>,
 }
 
 impl RagManager {
     pub fn new(pool: SqlitePool) -> Self {
-        Self { pool }
+        Self { pool, embedder: Mutex::new(None), reranker: Mutex::new(None), tokenizer: Mutex::new(None) }
     }
 
-    pub async fn ingest(&self, collection: &str, filename: &str, content: &str) -> Result<(), String> {
-        // Simple chunking by paragraphs (double newline)
-        let chunks: Vec<&str> = content.split("\n\n").collect();
-        
-        for chunk in chunks {
-            if chunk.trim().len() < 10 { continue; } // Skip very short chunks
-            
-            sqlx::query("INSERT INTO documents (collection, filename, content) VALUES (?, ?, ?)")
-                .bind(collection)
-                .bind(filename)
-                .bind(chunk.trim())
+    pub fn set_reranker(&self, reranker: Option<CrossEncoder>) {
+        *self.reranker.lock().unwrap() = reranker.map(|r| Arc::new(Mutex::new(r)));
+    }
+
+    pub fn set_embedder(&self, engine: Option<EmbeddingEngine>) {
+        *self.tokenizer.lock().unwrap() = engine.as_ref().and_then(|e| e.chunking_tokenizer().ok());
+        *self.embedder.lock().unwrap() = engine.map(|engine| LoadedEmbedder {
+            model_id: engine.model_id().to_string(),
+            model_hash: engine.model_hash().to_string(),
+            engine: Arc::new(Mutex::new(engine)),
+        });
+    }
+
+    /// Chunker configurado para la colección, o el de por defecto si no tiene.
+    pub async fn get_chunker(&self, collection: &str) -> Result<ChunkerConfig, String> {
+        let row: Option<(String,)> = sqlx::query_as("SELECT chunker FROM collections WHERE name = ?")
+            .bind(collection)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        match row {
+            Some((json,)) => serde_json::from_str(&json).map_err(|e| e.to_string()),
+            None => Ok(ChunkerConfig::default()),
+        }
+    }
+
+    /// Solo afecta a lo que se ingiera a partir de ahora; los chunks existentes no se rehacen.
+    pub async fn set_chunker(&self, collection: &str, chunker: &ChunkerConfig) -> Result<(), String> {
+        let json = serde_json::to_string(chunker).map_err(|e| e.to_string())?;
+        sqlx::query(
+            "INSERT INTO collections (name, chunker) VALUES (?, ?)
+             ON CONFLICT(name) DO UPDATE SET chunker = excluded.chunker"
+        )
+            .bind(collection)
+            .bind(json)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Id del modelo de embeddings cargado.
+    fn embedder_model(&self) -> Option<String> {
+        self.embedder.lock().unwrap().as_ref().map(|e| e.model_id.clone())
+    }
+
+    /// Hash del modelo cargado, si su id es `model_id`.
+    fn embedder_hash(&self, model_id: &str) -> Option<String> {
+        self.embedder.lock().unwrap().as_ref().filter(|e| e.model_id == model_id).map(|e| e.model_hash.clone())
+    }
+
+    /// Devuelve el id del modelo y los vectores, o `None` si no hay modelo de embeddings cargado.
+    /// La inferencia va en lotes de `EMBED_BATCH_SIZE` y fuera de los workers de tokio.
+    async fn embed(&self, texts: Vec<String>) -> Result<Option<ModelVectors>, String> {
+        let Some(LoadedEmbedder { model_id, engine, .. }) = self.embedder.lock().unwrap().clone() else {
+            return Ok(None);
+        };
+        let vectors = tokio::task::spawn_blocking(move || {
+            let mut engine = engine.lock().unwrap();
+            let mut vectors = Vec::with_capacity(texts.len());
+            for batch in texts.chunks(EMBED_BATCH_SIZE) {
+                let batch: Vec<&str> = batch.iter().map(String::as_str).collect();
+                vectors.extend(engine.embed(&batch)?);
+            }
+            Ok::<_, String>(vectors)
+        })
+        .await
+        .map_err(|e| e.to_string())??;
+        Ok(Some((model_id, vectors)))
+    }
+
+    async fn store_embeddings(&self, model_id: &str, ids: &[i64], vectors: &[Vec<f32>]) -> Result<(), String> {
+        for (id, vector) in ids.iter().zip(vectors) {
+            sqlx::query("INSERT OR REPLACE INTO embeddings (document_id, model, dim, vector) VALUES (?, ?, ?, ?)")
+                .bind(id)
+                .bind(model_id)
+                .bind(vector.len() as i64)
+                .bind(vector_to_blob(vector))
                 .execute(&self.pool)
                 .await
                 .map_err(|e| e.to_string())?;
@@ -36,18 +370,449 @@ impl RagManager {
         Ok(())
     }
 
-    pub async fn search(&self, collection: &str, query: &str, limit: i64) -> Result<Vec<DocumentChunk>, String> {
-        // Basic keyword search using LIKE
-        // For production RAG, we would use embeddings or FTS5
-        let pattern = format!("%{}%", query);
-        
-        // Split query into keywords for better matching? 
-        // For now, keep it simple: exact phrase or generic like
-        
-        let rows = sqlx::query("SELECT id, collection, filename, content FROM documents WHERE collection = ? AND content LIKE ? LIMIT ?")
+    /// Ingiere (o reemplaza) el documento `path` de la colección. Si el contenido no ha cambiado desde la
+    /// última ingesta no se toca nada; si ha cambiado, sus chunks se sustituyen en una sola transacción.
+    pub async fn ingest(&self, collection: &str, path: &str, content: &str, mtime: Option<i64>) -> Result<IngestOutcome, String> {
+        let hash = content_hash(content);
+        let existing: Option<(i64, String)> = sqlx::query_as("SELECT id, hash FROM sources WHERE collection = ? AND path = ?")
+            .bind(collection)
+            .bind(path)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if let Some((id, existing_hash)) = &existing {
+            if *existing_hash == hash {
+                // Mismo contenido: solo actualizamos el mtime para que la próxima pasada ni lo lea
+                // (y el lenguaje, que los sources anteriores a la columna no tienen)
+                sqlx::query("UPDATE sources SET mtime = ?, language = ? WHERE id = ?")
+                    .bind(mtime)
+                    .bind(loaders::language_for(path))
+                    .bind(id)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                return Ok(IngestOutcome::Unchanged);
+            }
+        }
+
+        // El código fuente se parte por items (funciones, impls, clases); el resto con el chunker de la colección
+        let chunks = match code_chunking::chunk_source(path, content) {
+            Some(chunks) => chunks,
+            None => {
+                let chunker = self.get_chunker(collection).await?;
+                let tokenizer = self.tokenizer.lock().unwrap();
+                chunker.chunk(content, tokenizer.as_ref())
+            }
+        };
+
+        // Embeddings antes de escribir: si el modelo falla no se pierde la versión anterior
+        let texts: Vec<String> = chunks.iter().map(|c| c.content.clone()).collect();
+        let embedded = self.embed(texts).await?;
+
+        let mut tx = self.pool.begin().await.map_err(|e| e.to_string())?;
+
+        let source_id = match &existing {
+            Some((id, _)) => {
+                // Los embeddings se borran en cascada con los chunks
+                sqlx::query("DELETE FROM documents WHERE source_id = ?")
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                sqlx::query("UPDATE sources SET hash = ?, mtime = ?, language = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+                    .bind(&hash)
+                    .bind(mtime)
+                    .bind(loaders::language_for(path))
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                *id
+            }
+            None => sqlx::query("INSERT INTO sources (collection, path, hash, mtime, language) VALUES (?, ?, ?, ?, ?)")
+                .bind(collection)
+                .bind(path)
+                .bind(&hash)
+                .bind(mtime)
+                .bind(loaders::language_for(path))
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?
+                .last_insert_rowid(),
+        };
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            let id = sqlx::query(
+                "INSERT INTO documents (collection, filename, content, source_id, start_offset, end_offset, start_line, end_line, symbol)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+            )
+                .bind(collection)
+                .bind(path)
+                .bind(&chunk.content)
+                .bind(source_id)
+                .bind(chunk.start as i64)
+                .bind(chunk.end as i64)
+                .bind(line_at(content, chunk.start))
+                .bind(line_at(content, chunk.end))
+                .bind(&chunk.symbol)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?
+                .last_insert_rowid();
+
+            if let Some((model_id, vectors)) = &embedded {
+                sqlx::query("INSERT OR REPLACE INTO embeddings (document_id, model, dim, vector) VALUES (?, ?, ?, ?)")
+                    .bind(id)
+                    .bind(model_id)
+                    .bind(vectors[i].len() as i64)
+                    .bind(vector_to_blob(&vectors[i]))
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        Ok(if existing.is_some() { IngestOutcome::Updated } else { IngestOutcome::Added })
+    }
+
+    /// `true` si el documento ya está ingerido con ese mtime (permite saltarse la extracción).
+    async fn is_unmodified(&self, collection: &str, path: &str, mtime: Option<i64>) -> Result<bool, String> {
+        let Some(mtime) = mtime else { return Ok(false) };
+        let row: Option<(i64,)> = sqlx::query_as("SELECT id FROM sources WHERE collection = ? AND path = ? AND mtime = ?")
             .bind(collection)
-            .bind(&pattern)
-            .bind(limit)
+            .bind(path)
+            .bind(mtime)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(row.is_some())
+    }
+
+    /// Borra un documento (source) con todos sus chunks.
+    pub async fn delete_source(&self, source_id: i64) -> Result<(), String> {
+        let mut tx = self.pool.begin().await.map_err(|e| e.to_string())?;
+        sqlx::query("DELETE FROM documents WHERE source_id = ?")
+            .bind(source_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        sqlx::query("DELETE FROM sources WHERE id = ?")
+            .bind(source_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        tx.commit().await.map_err(|e| e.to_string())
+    }
+
+    /// Borra el documento `path` de la colección, o todos los que cuelgan de él si es un directorio.
+    pub async fn delete_source_path(&self, collection: &str, path: &str) -> Result<(), String> {
+        let ids: Vec<(i64,)> = sqlx::query_as("SELECT id FROM sources WHERE collection = ? AND (path = ? OR substr(path, 1, length(?) + 1) = ? || '/')")
+            .bind(collection)
+            .bind(path)
+            .bind(path)
+            .bind(path)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        for (id,) in ids {
+            self.delete_source(id).await?;
+        }
+        Ok(())
+    }
+
+    /// Borra la colección entera: documentos, chunks, embeddings y su configuración.
+    pub async fn drop_collection(&self, collection: &str) -> Result<(), String> {
+        let mut tx = self.pool.begin().await.map_err(|e| e.to_string())?;
+        for sql in [
+            "DELETE FROM documents WHERE collection = ?",
+            "DELETE FROM sources WHERE collection = ?",
+            "DELETE FROM watch_folders WHERE collection = ?",
+            "DELETE FROM collections WHERE name = ?",
+        ] {
+            sqlx::query(sql)
+                .bind(collection)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        tx.commit().await.map_err(|e| e.to_string())
+    }
+
+    /// Reconstruye el índice FTS5 y recalcula los embeddings de la colección con el modelo cargado.
+    /// Devuelve cuántos chunks se han vuelto a vectorizar (0 sin modelo de embeddings).
+    pub async fn rebuild_collection(&self, collection: &str) -> Result<usize, String> {
+        // FTS5 external content solo admite rebuild de la tabla completa
+        sqlx::query("INSERT INTO documents_fts(documents_fts) VALUES ('rebuild')")
+            .execute(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if self.embedder_model().is_none() {
+            return Ok(0);
+        }
+        sqlx::query("DELETE FROM embeddings WHERE document_id IN (SELECT id FROM documents WHERE collection = ?)")
+            .bind(collection)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        self.embed_missing(collection).await
+    }
+
+    /// Ingiere todos los ficheros soportados de `root` (respetando `.gitignore`). Los que no se pueden
+    /// leer o extraer no abortan la ingesta: se devuelven en el resumen.
+    pub async fn ingest_directory<F>(&self, collection: &str, root: &Path, max_file_bytes: u64, on_progress: F) -> Result<IngestSummary, String>
+    where
+        F: Fn(IngestProgress),
+    {
+        if !root.is_dir() {
+            return Err(format!("Not a directory: {}", root.display()));
+        }
+
+        // Ruta canónica: los ficheros se guardan con su ruta completa
+        let root = root.canonicalize().map_err(|e| e.to_string())?;
+        let walk_root = root.clone();
+        let (files, mut skipped) = tokio::task::spawn_blocking(move || loaders::walk_directory(&walk_root, max_file_bytes))
+            .await
+            .map_err(|e| e.to_string())?;
+
+        // Antes se guardaban con la ruta relativa a la carpeta: se sustituyen al volver a ingerirlos
+        let existing: HashSet<String> = sqlx::query_scalar("SELECT path FROM sources WHERE collection = ?")
+            .bind(collection)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .collect();
+
+        let total = files.len();
+        let (mut ingested, mut unchanged) = (0, 0);
+        for (i, path) in files.into_iter().enumerate() {
+            let name = loaders::source_name(&path);
+            let relative = loaders::relative_name(&root, &path);
+            let result = self.ingest_file(collection, &name, path.clone()).await;
+            if result.is_ok() && relative != name && existing.contains(&relative) {
+                self.delete_source_path(collection, &relative).await?;
+            }
+
+            let (outcome, error) = match result {
+                Ok(outcome) => (Some(outcome), None),
+                Err(reason) => {
+                    skipped.push(SkippedFile::failed(&path, reason.clone()));
+                    (None, Some(reason))
+                }
+            };
+            match outcome {
+                Some(IngestOutcome::Unchanged) => unchanged += 1,
+                Some(_) => ingested += 1,
+                None => {}
+            }
+            on_progress(IngestProgress {
+                collection: collection.to_string(),
+                path: name,
+                index: i + 1,
+                total,
+                outcome,
+                error,
+            });
+        }
+
+        Ok(IngestSummary { collection: collection.to_string(), ingested, unchanged, skipped })
+    }
+
+    /// Extrae e ingiere un fichero del disco. Si su mtime coincide con el guardado ni siquiera se lee.
+    pub async fn ingest_file(&self, collection: &str, name: &str, path: PathBuf) -> Result<IngestOutcome, String> {
+        let mtime = file_mtime(&path);
+        if self.is_unmodified(collection, name, mtime).await? {
+            return Ok(IngestOutcome::Unchanged);
+        }
+        self.extract_and_ingest(collection, name, path, mtime).await
+    }
+
+    /// Como `ingest_file` para un fichero que se sabe que ha cambiado (un evento del watcher): el mtime
+    /// tiene resolución de segundos y no distingue dos guardados en el mismo segundo, así que decide el hash.
+    pub async fn reingest_file(&self, collection: &str, name: &str, path: PathBuf) -> Result<IngestOutcome, String> {
+        let mtime = file_mtime(&path);
+        self.extract_and_ingest(collection, name, path, mtime).await
+    }
+
+    async fn extract_and_ingest(&self, collection: &str, name: &str, path: PathBuf, mtime: Option<i64>) -> Result<IngestOutcome, String> {
+        // La extracción (PDF sobre todo) es bloqueante y puede hacer panic con ficheros corruptos
+        let text = tokio::task::spawn_blocking(move || loaders::extract_text(&path))
+            .await
+            .unwrap_or_else(|e| Err(format!("Extraction failed: {}", e)))?;
+        self.ingest(collection, name, &text, mtime).await
+    }
+
+    /// Calcula embeddings de los chunks de la colección que no tienen vector del modelo actual
+    /// (documentos ingeridos antes de cargar el modelo o con otro modelo). Devuelve cuántos se han procesado.
+    pub async fn embed_missing(&self, collection: &str) -> Result<usize, String> {
+        let Some(model_id) = self.embedder_model() else {
+            return Err("Embedding model not loaded".to_string());
+        };
+
+        let rows = sqlx::query_as::<_, (i64, String)>(
+            "SELECT d.id, d.content FROM documents d
+             LEFT JOIN embeddings e ON e.document_id = d.id AND e.model = ?
+             WHERE d.collection = ? AND e.document_id IS NULL"
+        )
+            .bind(&model_id)
+            .bind(collection)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        for batch in rows.chunks(EMBED_BATCH_SIZE) {
+            let ids: Vec<i64> = batch.iter().map(|r| r.0).collect();
+            let texts: Vec<String> = batch.iter().map(|r| r.1.clone()).collect();
+            if let Some((model_id, vectors)) = self.embed(texts).await? {
+                self.store_embeddings(&model_id, &ids, &vectors).await?;
+            }
+        }
+        Ok(rows.len())
+    }
+
+    pub async fn search(&self, query: &SearchQuery) -> Result<Vec<DocumentChunk>, String> {
+        let SearchQuery { text, limit, options, .. } = query;
+        let top_n = candidate_count(*limit, options);
+
+        let mut results = match options.mode {
+            SearchMode::Keyword => self.keyword_search(query, top_n, None).await?,
+            SearchMode::Semantic => {
+                let (model_id, vector) = self.embed_query(text).await?.ok_or("Embedding model not loaded")?;
+                self.vector_search(query, &model_id, &vector, top_n).await?
+            }
+            SearchMode::Auto => match self.embed_query(text).await? {
+                Some((model_id, vector)) => self.vector_search(query, &model_id, &vector, top_n).await?,
+                None => self.keyword_search(query, top_n, None).await?,
+            },
+            SearchMode::Hybrid => {
+                let (model_id, vector) = self.embed_query(text).await?.ok_or("Embedding model not loaded")?;
+                // Listas más largas que el resultado final para que la fusión tenga de dónde elegir
+                let candidates = (top_n * 4).max(20);
+                let keyword = self.keyword_search(query, candidates, None).await?;
+                let semantic = self.semantic_search(query, &model_id, &vector, candidates).await?;
+                let mut fused = reciprocal_rank_fusion(&[keyword, semantic]);
+                fused.truncate(top_n as usize);
+                fused
+            }
+        };
+
+        if options.rerank {
+            self.rerank(text, &mut results).await?;
+        }
+        Ok(keep_best(results, query.filters.min_score, *limit))
+    }
+
+    async fn embed_query(&self, query: &str) -> Result<Option<(String, Vec<f32>)>, String> {
+        Ok(self.embed(vec![query.to_string()]).await?.map(|(model_id, mut vectors)| (model_id, vectors.remove(0))))
+    }
+
+    /// Reordena por la puntuación del cross-encoder (que pasa a ser el `score`).
+    async fn rerank(&self, query: &str, results: &mut [DocumentChunk]) -> Result<(), String> {
+        let reranker = self.reranker.lock().unwrap().clone().ok_or("Reranker model not loaded")?;
+
+        let query = query.to_string();
+        let passages: Vec<String> = results.iter().map(|d| d.content.clone()).collect();
+        let scores = tokio::task::spawn_blocking(move || {
+            let mut reranker = reranker.lock().unwrap();
+            let mut scores = Vec::with_capacity(passages.len());
+            for batch in passages.chunks(EMBED_BATCH_SIZE) {
+                let batch: Vec<&str> = batch.iter().map(String::as_str).collect();
+                scores.extend(reranker.score(&query, &batch)?);
+            }
+            Ok::<_, String>(scores)
+        })
+        .await
+        .map_err(|e| e.to_string())??;
+        for (doc, score) in results.iter_mut().zip(scores) {
+            doc.score = score as f64;
+        }
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        Ok(())
+    }
+
+    /// Búsqueda semántica que no pierde los chunks sin vector del modelo (ingeridos sin él cargado y aún
+    /// sin `embed_missing`): esos se buscan por keywords y se fusionan con RRF, como en `Hybrid`.
+    async fn vector_search(&self, query: &SearchQuery, model_id: &str, query_vector: &[f32], limit: i64) -> Result<Vec<DocumentChunk>, String> {
+        let semantic = self.semantic_search(query, model_id, query_vector, limit).await?;
+        let unembedded = self.keyword_search(query, limit, Some(model_id)).await?;
+        if unembedded.is_empty() {
+            return Ok(semantic);
+        }
+        let mut fused = reciprocal_rank_fusion(&[semantic, unembedded]);
+        fused.truncate(limit.max(0) as usize);
+        Ok(fused)
+    }
+
+    /// Ranking por similitud coseno contra todos los vectores que pasan los filtros (búsqueda exacta en memoria).
+    async fn semantic_search(&self, query: &SearchQuery, model_id: &str, query_vector: &[f32], limit: i64) -> Result<Vec<DocumentChunk>, String> {
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT d.id, d.collection, d.filename, d.content, d.start_offset, d.end_offset,
+                    d.start_line, d.end_line, d.symbol, e.vector
+             FROM embeddings e
+             JOIN documents d ON d.id = e.document_id
+             LEFT JOIN sources s ON s.id = d.source_id
+             WHERE e.model = "
+        );
+        qb.push_bind(model_id);
+        push_filters(&mut qb, query);
+
+        let rows = qb.build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut docs: Vec<DocumentChunk> = rows.iter().map(|row| {
+            let vector = blob_to_vector(row.get::<&[u8], _>("vector"));
+            let content: String = row.get("content");
+            DocumentChunk {
+                id: row.get("id"),
+                collection: row.get("collection"),
+                filename: row.get("filename"),
+                snippet: content.chars().take(200).collect(),
+                content,
+                score: cosine_similarity(query_vector, &vector) as f64,
+                start_offset: row.get("start_offset"),
+                end_offset: row.get("end_offset"),
+                start_line: row.get("start_line"),
+                end_line: row.get("end_line"),
+                symbol: row.get("symbol"),
+            }
+        }).collect();
+
+        docs.sort_by(|a, b| b.score.total_cmp(&a.score));
+        docs.truncate(limit.max(0) as usize);
+        Ok(docs)
+    }
+
+    /// Con `unembedded_for`, solo chunks que no tienen vector de ese modelo.
+    async fn keyword_search(&self, query: &SearchQuery, limit: i64, unembedded_for: Option<&str>) -> Result<Vec<DocumentChunk>, String> {
+        let Some(fts) = fts_query(&query.text) else { return Ok(Vec::new()) };
+
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT d.id, d.collection, d.filename, d.content, d.start_offset, d.end_offset,
+                    d.start_line, d.end_line, d.symbol,
+                    -bm25(documents_fts) AS score,
+                    snippet(documents_fts, 0, '**', '**', '…', 24) AS snippet
+             FROM documents_fts
+             JOIN documents d ON d.id = documents_fts.rowid
+             LEFT JOIN sources s ON s.id = d.source_id
+             WHERE documents_fts MATCH "
+        );
+        qb.push_bind(fts);
+        push_filters(&mut qb, query);
+        if let Some(model_id) = unembedded_for {
+            qb.push(" AND NOT EXISTS (SELECT 1 FROM embeddings e WHERE e.document_id = d.id AND e.model = ")
+                .push_bind(model_id.to_string())
+                .push(")");
+        }
+        qb.push(" ORDER BY bm25(documents_fts) LIMIT ");
+        qb.push_bind(limit);
+
+        let rows = qb.build()
             .fetch_all(&self.pool)
             .await
             .map_err(|e| e.to_string())?;
@@ -57,24 +822,296 @@ impl RagManager {
             collection: row.get("collection"),
             filename: row.get("filename"),
             content: row.get("content"),
+            score: row.get("score"),
+            snippet: row.get("snippet"),
+            start_offset: row.get("start_offset"),
+            end_offset: row.get("end_offset"),
+            start_line: row.get("start_line"),
+            end_line: row.get("end_line"),
+            symbol: row.get("symbol"),
         }).collect();
         
         Ok(docs)
     }
 
-    #[allow(dead_code)]
-    pub async fn get_collections(&self) -> Result<Vec<String>, String> {
-        let rows = sqlx::query("SELECT DISTINCT collection FROM documents")
+    /// Colecciones existentes (con documentos o solo configuradas) con sus conteos.
+    pub async fn get_collections(&self) -> Result<Vec<CollectionInfo>, String> {
+        let rows = sqlx::query_as::<_, (String, i64, i64)>(
+            "SELECT c.name,
+                    (SELECT COUNT(*) FROM sources s WHERE s.collection = c.name),
+                    (SELECT COUNT(*) FROM documents d WHERE d.collection = c.name)
+             FROM (SELECT collection AS name FROM sources
+                   UNION SELECT collection FROM documents
+                   UNION SELECT name FROM collections) c
+             ORDER BY c.name"
+        )
             .fetch_all(&self.pool)
             .await
             .map_err(|e| e.to_string())?;
 
-        let collections = rows.iter().map(|row| row.get("collection")).collect();
+        let collections = rows.into_iter().map(|(name, documents, chunks)| CollectionInfo { name, documents, chunks }).collect();
         Ok(collections)
     }
 
+    /// Sustituye las etiquetas de un documento (source).
+    pub async fn set_tags(&self, source_id: i64, tags: &[String]) -> Result<(), String> {
+        let mut tx = self.pool.begin().await.map_err(|e| e.to_string())?;
+        sqlx::query("DELETE FROM source_tags WHERE source_id = ?")
+            .bind(source_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        for tag in tags {
+            sqlx::query("INSERT OR IGNORE INTO source_tags (source_id, tag) VALUES (?, ?)")
+                .bind(source_id)
+                .bind(tag.trim().to_lowercase())
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        tx.commit().await.map_err(|e| e.to_string())
+    }
+
+    /// Exporta la colección a un bundle portable en `dest`. Los embeddings se incluyen si los hay,
+    /// los del modelo con más vectores en la colección (normalmente el único).
+    pub async fn export_collection(&self, collection: &str, dest: &Path) -> Result<BundleManifest, String> {
+        let source_rows = sqlx::query_as::<_, (i64, String, String, Option<i64>, Option<String>)>(
+            "SELECT id, path, hash, mtime, language FROM sources WHERE collection = ? ORDER BY path"
+        )
+            .bind(collection)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        if source_rows.is_empty() {
+            return Err(format!("Collection {} is empty", collection));
+        }
+
+        let tag_rows = sqlx::query_as::<_, (i64, String)>(
+            "SELECT t.source_id, t.tag FROM source_tags t JOIN sources s ON s.id = t.source_id WHERE s.collection = ? ORDER BY t.tag"
+        )
+            .bind(collection)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+        for (source_id, tag) in tag_rows {
+            tags.entry(source_id).or_default().push(tag);
+        }
+
+        let embedding: Option<(String, i64)> = sqlx::query_as(
+            "SELECT e.model, e.dim FROM embeddings e JOIN documents d ON d.id = e.document_id
+             WHERE d.collection = ? GROUP BY e.model, e.dim ORDER BY COUNT(*) DESC LIMIT 1"
+        )
+            .bind(collection)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let chunk_rows = sqlx::query(
+            "SELECT s.path, d.content, d.start_offset, d.end_offset, d.start_line, d.end_line, d.symbol, e.vector
+             FROM documents d
+             JOIN sources s ON s.id = d.source_id
+             LEFT JOIN embeddings e ON e.document_id = d.id AND e.model = ? AND e.dim = ?
+             WHERE d.collection = ?
+             ORDER BY s.path, d.id"
+        )
+            .bind(embedding.as_ref().map(|(model, _)| model.clone()))
+            .bind(embedding.as_ref().map(|(_, dim)| *dim))
+            .bind(collection)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let chunks: Vec<BundleChunk> = chunk_rows.iter().map(|row| BundleChunk {
+            path: row.get("path"),
+            content: row.get("content"),
+            start_offset: row.get("start_offset"),
+            end_offset: row.get("end_offset"),
+            start_line: row.get("start_line"),
+            end_line: row.get("end_line"),
+            symbol: row.get("symbol"),
+            embedding: row.get::<Option<&[u8]>, _>("vector").map(blob_to_vector),
+        }).collect();
+
+        let sources: Vec<BundleSource> = source_rows.into_iter().map(|(id, path, hash, mtime, language)| BundleSource {
+            path,
+            hash,
+            mtime,
+            language,
+            tags: tags.remove(&id).unwrap_or_default(),
+        }).collect();
+
+        let manifest = BundleManifest {
+            format: BUNDLE_FORMAT.to_string(),
+            format_version: BUNDLE_FORMAT_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            collection: collection.to_string(),
+            chunker: self.get_chunker(collection).await?,
+            chunker_version: CHUNKER_VERSION,
+            embedding: embedding.map(|(model, dim)| EmbeddingInfo {
+                model_hash: self.embedder_hash(&model),
+                model,
+                dim: dim as usize,
+            }),
+            sources: sources.len(),
+            chunks: chunks.len(),
+            exported_at: chrono::Local::now().to_rfc3339(),
+        };
+
+        let bundle = Bundle { manifest: manifest.clone(), sources, chunks };
+        let dest = dest.to_path_buf();
+        tokio::task::spawn_blocking(move || bundle.write(&dest))
+            .await
+            .map_err(|e| e.to_string())??;
+        Ok(manifest)
+    }
+
+    /// Importa un bundle como la colección `collection` (por defecto la del manifiesto), que debe estar vacía.
+    /// Los embeddings solo se importan si son del modelo cargado (o, sin modelo cargado, si no chocan con
+    /// vectores del mismo modelo con otra dimensión); si no, se descartan y se pueden recalcular con `embed_collection`.
+    pub async fn import_collection(&self, src: &Path, collection: Option<&str>) -> Result<ImportSummary, String> {
+        let src = src.to_path_buf();
+        let bundle = tokio::task::spawn_blocking(move || Bundle::read(&src))
+            .await
+            .map_err(|e| e.to_string())??;
+        let collection = collection.unwrap_or(&bundle.manifest.collection).to_string();
+
+        let (existing,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM sources WHERE collection = ?")
+            .bind(&collection)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        if existing > 0 {
+            return Err(format!("Collection {} already has documents; drop it or import under another name", collection));
+        }
+
+        let embeddings_rejected = match &bundle.manifest.embedding {
+            Some(info) => self.embedding_incompatibility(info).await?,
+            None => None,
+        };
+        let embedding_model = bundle.manifest.embedding.as_ref().filter(|_| embeddings_rejected.is_none()).map(|info| info.model.as_str());
+
+        // Los chunks se importan tal cual; con otro chunker los que se re-ingieran se partirán distinto
+        let mut warnings = Vec::new();
+        if bundle.manifest.chunker_version != CHUNKER_VERSION {
+            warnings.push(format!(
+                "Bundle chunks were made with chunker version {}, this app uses {}; re-ingest the documents to re-chunk them",
+                bundle.manifest.chunker_version, CHUNKER_VERSION
+            ));
+        }
+
+        let mut tx = self.pool.begin().await.map_err(|e| e.to_string())?;
+
+        sqlx::query(
+            "INSERT INTO collections (name, chunker) VALUES (?, ?)
+             ON CONFLICT(name) DO UPDATE SET chunker = excluded.chunker"
+        )
+            .bind(&collection)
+            .bind(serde_json::to_string(&bundle.manifest.chunker).map_err(|e| e.to_string())?)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut source_ids: HashMap<&str, i64> = HashMap::new();
+        for source in &bundle.sources {
+            let id = sqlx::query("INSERT INTO sources (collection, path, hash, mtime, language) VALUES (?, ?, ?, ?, ?)")
+                .bind(&collection)
+                .bind(&source.path)
+                .bind(&source.hash)
+                .bind(source.mtime)
+                .bind(&source.language)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?
+                .last_insert_rowid();
+            for tag in &source.tags {
+                sqlx::query("INSERT OR IGNORE INTO source_tags (source_id, tag) VALUES (?, ?)")
+                    .bind(id)
+                    .bind(tag)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            source_ids.insert(&source.path, id);
+        }
+
+        let mut embeddings = 0;
+        for chunk in &bundle.chunks {
+            let id = sqlx::query(
+                "INSERT INTO documents (collection, filename, content, source_id, start_offset, end_offset, start_line, end_line, symbol)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+            )
+                .bind(&collection)
+                .bind(&chunk.path)
+                .bind(&chunk.content)
+                .bind(source_ids[chunk.path.as_str()])
+                .bind(chunk.start_offset)
+                .bind(chunk.end_offset)
+                .bind(chunk.start_line)
+                .bind(chunk.end_line)
+                .bind(&chunk.symbol)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?
+                .last_insert_rowid();
+
+            if let (Some(model), Some(vector)) = (embedding_model, &chunk.embedding) {
+                sqlx::query("INSERT OR REPLACE INTO embeddings (document_id, model, dim, vector) VALUES (?, ?, ?, ?)")
+                    .bind(id)
+                    .bind(model)
+                    .bind(vector.len() as i64)
+                    .bind(vector_to_blob(vector))
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                embeddings += 1;
+            }
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        Ok(ImportSummary {
+            collection,
+            sources: bundle.sources.len(),
+            chunks: bundle.chunks.len(),
+            embeddings,
+            embeddings_rejected,
+            warnings,
+        })
+    }
+
+    /// Motivo por el que los embeddings de `info` no sirven aquí, o `None` si son compatibles.
+    async fn embedding_incompatibility(&self, info: &EmbeddingInfo) -> Result<Option<String>, String> {
+        let loaded = self.embedder_model();
+        if let Some(loaded) = loaded {
+            if loaded != info.model {
+                return Ok(Some(format!("Bundle embeddings were made with {}, but the loaded model is {}", info.model, loaded)));
+            }
+        }
+        // Mismo id con otro fichero (otra exportación o cuantización del modelo)
+        if let (Some(bundle_hash), Some(loaded_hash)) = (&info.model_hash, self.embedder_hash(&info.model)) {
+            if *bundle_hash != loaded_hash {
+                return Ok(Some(format!("Bundle embeddings were made with a different build of {} (model hash differs)", info.model)));
+            }
+        }
+
+        // Mismo nombre de modelo con otra dimensión: no son el mismo modelo aunque se llamen igual
+        let local_dim: Option<(i64,)> = sqlx::query_as("SELECT dim FROM embeddings WHERE model = ? LIMIT 1")
+            .bind(&info.model)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        match local_dim {
+            Some((dim,)) if dim as usize != info.dim => Ok(Some(format!(
+                "Bundle embeddings of {} have {} dimensions, local ones have {}",
+                info.model, info.dim, dim
+            ))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Documentos (sources) de la colección: id, ruta y fecha de la última ingesta.
     pub async fn get_documents(&self, collection: &str) -> Result<Vec<(i64, String, String)>, String> {
-        let rows = sqlx::query_as::<_, (i64, String, String)>("SELECT id, filename, created_at FROM documents WHERE collection = ? ORDER BY created_at DESC")
+        let rows = sqlx::query_as::<_, (i64, String, String)>("SELECT id, path, updated_at FROM sources WHERE collection = ? ORDER BY updated_at DESC")
              .bind(collection)
              .fetch_all(&self.pool)
              .await
@@ -82,3 +1119,379 @@ impl RagManager {
         Ok(rows)
     }
 }
+
+/// Candidatos a recuperar: con rerank, más de los que se devuelven para que el cross-encoder elija.
+fn candidate_count(limit: i64, options: &SearchOptions) -> i64 {
+    if options.rerank {
+        options.rerank_top_n.unwrap_or(20).max(limit.max(0) as usize) as i64
+    } else {
+        limit
+    }
+}
+
+/// Aplica el umbral a la puntuación final (cuya escala depende del modo y del rerank) y se queda
+/// con los `limit` primeros.
+fn keep_best(mut results: Vec<DocumentChunk>, min_score: Option<f64>, limit: i64) -> Vec<DocumentChunk> {
+    if let Some(min_score) = min_score {
+        results.retain(|d| d.score >= min_score);
+    }
+    results.truncate(limit.max(0) as usize);
+    results
+}
+
+/// Fusiona listas ordenadas con RRF: score = Σ 1 / (k + rank). Solo importa la posición en cada
+/// lista, así que no hace falta normalizar BM25 frente a coseno. A igual puntuación gana la lista
+/// anterior (y dentro de ella, el mejor puesto).
+fn reciprocal_rank_fusion(lists: &[Vec<DocumentChunk>]) -> Vec<DocumentChunk> {
+    let mut fused: Vec<DocumentChunk> = Vec::new();
+    let mut positions: HashMap<i64, usize> = HashMap::new();
+
+    for list in lists {
+        for (rank, doc) in list.iter().enumerate() {
+            let contribution = 1.0 / (RRF_K + rank as f64 + 1.0);
+            match positions.get(&doc.id) {
+                Some(&i) => {
+                    let d = &mut fused[i];
+                    d.score += contribution;
+                    // Preferimos el snippet con términos marcados de la búsqueda por keywords
+                    if !d.snippet.contains("**") && doc.snippet.contains("**") {
+                        d.snippet = doc.snippet.clone();
+                    }
+                }
+                None => {
+                    positions.insert(doc.id, fused.len());
+                    fused.push(DocumentChunk { score: contribution, ..doc.clone() });
+                }
+            }
+        }
+    }
+
+    // Orden estable: los empates conservan el orden de aparición
+    fused.sort_by(|a, b| b.score.total_cmp(&a.score));
+    fused
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn paths(rag: &RagManager) -> Vec<String> {
+        let rows: Vec<(String,)> = sqlx::query_as("SELECT path FROM sources ORDER BY path")
+            .fetch_all(&rag.pool)
+            .await
+            .unwrap();
+        rows.into_iter().map(|(p,)| p).collect()
+    }
+
+    #[tokio::test]
+    async fn delete_source_path_treats_like_wildcards_literally() {
+        let rag = RagManager::new(crate::db::memory_pool().await);
+        for path in ["a_b/uno.txt", "a_b/sub/dos.txt", "axb/tres.txt", "a%b/cuatro.txt", "a_bc/cinco.txt", "a_b"] {
+            rag.ingest("docs", path, &format!("Contenido del fichero {}", path), None).await.unwrap();
+        }
+
+        rag.delete_source_path("docs", "a_b").await.unwrap();
+        assert_eq!(paths(&rag).await, ["a%b/cuatro.txt", "a_bc/cinco.txt", "axb/tres.txt"]);
+
+        rag.delete_source_path("docs", "a%b").await.unwrap();
+        assert_eq!(paths(&rag).await, ["a_bc/cinco.txt", "axb/tres.txt"]);
+    }
+
+    #[tokio::test]
+    async fn ingest_directory_keeps_same_named_files_from_different_roots() {
+        let base = std::env::temp_dir().join(format!("rag-roots-{}", std::process::id()));
+        for root in ["uno", "dos"] {
+            std::fs::create_dir_all(base.join(root)).unwrap();
+            std::fs::write(base.join(root).join("README.md"), format!("Léeme de la carpeta {}", root)).unwrap();
+            std::fs::write(base.join(root).join("logo.png"), [0u8; 16]).unwrap();
+        }
+        let base = base.canonicalize().unwrap();
+
+        let rag = RagManager::new(crate::db::memory_pool().await);
+        for root in ["uno", "dos"] {
+            let summary = rag.ingest_directory("docs", &base.join(root), loaders::DEFAULT_MAX_FILE_BYTES, |_| {}).await.unwrap();
+            assert_eq!(summary.ingested, 1);
+            // El .png se salta sin contar como error
+            assert_eq!(summary.skipped.len(), 1);
+            assert!(!summary.skipped[0].error);
+        }
+
+        let expected: Vec<String> = ["dos", "uno"].iter().map(|r| loaders::source_name(&base.join(r).join("README.md"))).collect();
+        assert_eq!(paths(&rag).await, expected);
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    type ChunkRow = (String, String, String, Option<i64>, Option<i64>, Option<i64>, Option<String>);
+
+    /// Ruta, hash, contenido, mtime, líneas y etiquetas de cada chunk de la colección.
+    async fn contents(rag: &RagManager, collection: &str) -> Vec<ChunkRow> {
+        sqlx::query_as(
+            "SELECT s.path, s.hash, d.content, s.mtime, d.start_line, d.end_line, group_concat(t.tag)
+             FROM documents d JOIN sources s ON s.id = d.source_id LEFT JOIN source_tags t ON t.source_id = s.id
+             WHERE d.collection = ? GROUP BY d.id ORDER BY s.path, d.id"
+        )
+        .bind(collection)
+        .fetch_all(&rag.pool)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn export_then_import_round_trips_the_collection() {
+        let rag = RagManager::new(crate::db::memory_pool().await);
+        rag.ingest("docs", "notas.md", "Primera nota sobre el proyecto.\n\nSegunda nota con más detalle.", Some(42)).await.unwrap();
+        rag.ingest("docs", "src/main.rs", "fn main() {\n    println!(\"hola\");\n}\n", None).await.unwrap();
+        let (source_id,): (i64,) = sqlx::query_as("SELECT id FROM sources WHERE path = 'notas.md'").fetch_one(&rag.pool).await.unwrap();
+        rag.set_tags(source_id, &["proyecto".to_string()]).await.unwrap();
+        let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM documents ORDER BY id").fetch_all(&rag.pool).await.unwrap();
+        rag.store_embeddings("mini", &ids, &vec![vec![0.5, 0.25, 1.0]; ids.len()]).await.unwrap();
+
+        let path = std::env::temp_dir().join(format!("bundle-{}.zip", std::process::id()));
+        let manifest = rag.export_collection("docs", &path).await.unwrap();
+        assert_eq!((manifest.sources, manifest.chunks), (2, ids.len()));
+        assert_eq!(manifest.chunker_version, CHUNKER_VERSION);
+
+        let summary = rag.import_collection(&path, Some("copia")).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((summary.sources, summary.chunks, summary.embeddings), (2, ids.len(), ids.len()));
+        assert!(summary.embeddings_rejected.is_none());
+        assert!(summary.warnings.is_empty());
+
+        assert_eq!(contents(&rag, "copia").await, contents(&rag, "docs").await);
+        assert_eq!(rag.get_chunker("copia").await.unwrap(), rag.get_chunker("docs").await.unwrap());
+
+        // La colección de destino tiene que estar vacía
+        let path = std::env::temp_dir().join(format!("bundle-again-{}.zip", std::process::id()));
+        rag.export_collection("docs", &path).await.unwrap();
+        assert!(rag.import_collection(&path, Some("copia")).await.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    async fn chunk_id(rag: &RagManager, filename: &str) -> i64 {
+        sqlx::query_scalar("SELECT id FROM documents WHERE filename = ?").bind(filename).fetch_one(&rag.pool).await.unwrap()
+    }
+
+    fn filenames(results: &[DocumentChunk]) -> Vec<&str> {
+        results.iter().map(|d| d.filename.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn semantic_search_still_finds_chunks_without_vectors() {
+        let rag = RagManager::new(crate::db::memory_pool().await);
+        rag.ingest("docs", "alfa.md", "El gato duerme en el sofá.", None).await.unwrap();
+        rag.ingest("docs", "beta.md", "El perro corre por el parque.", None).await.unwrap();
+        rag.ingest("docs", "gamma.md", "El gato del vecino maúlla.", None).await.unwrap();
+        let (alfa, beta, gamma) = (chunk_id(&rag, "alfa.md").await, chunk_id(&rag, "beta.md").await, chunk_id(&rag, "gamma.md").await);
+        rag.store_embeddings("mini", &[alfa, beta], &[vec![1.0, 0.0], vec![0.0, 1.0]]).await.unwrap();
+
+        let query = SearchQuery::new("docs", "gato", 5, SearchOptions::default());
+        let vector = [1.0, 0.0];
+        // Solo por vectores, gamma.md no aparece
+        assert_eq!(filenames(&rag.semantic_search(&query, "mini", &vector, 5).await.unwrap()), vec!["alfa.md", "beta.md"]);
+
+        let results = rag.vector_search(&query, "mini", &vector, 5).await.unwrap();
+        let mut found = filenames(&results);
+        found.sort();
+        assert_eq!(found, vec!["alfa.md", "beta.md", "gamma.md"]);
+        // Empate en RRF (primero de cada lista): gana la lista semántica
+        assert_eq!(results[0].filename, "alfa.md");
+
+        // Con todos los vectores, el ranking vuelve a ser el coseno
+        rag.store_embeddings("mini", &[gamma], &[vec![0.6, 0.8]]).await.unwrap();
+        let results = rag.vector_search(&query, "mini", &vector, 5).await.unwrap();
+        assert_eq!(filenames(&results), vec!["alfa.md", "gamma.md", "beta.md"]);
+        assert!((results[1].score - 0.6).abs() < 1e-6);
+
+        // Los vectores de otro modelo no cuentan
+        let results = rag.vector_search(&query, "otro", &vector, 5).await.unwrap();
+        let mut found = filenames(&results);
+        found.sort();
+        assert_eq!(found, vec!["alfa.md", "gamma.md"]);
+    }
+
+    fn chunk(id: i64, score: f64, snippet: &str) -> DocumentChunk {
+        DocumentChunk {
+            id,
+            collection: "docs".to_string(),
+            filename: format!("{}.md", id),
+            content: String::new(),
+            score,
+            snippet: snippet.to_string(),
+            start_offset: None,
+            end_offset: None,
+            start_line: None,
+            end_line: None,
+            symbol: None,
+        }
+    }
+
+    fn ranked(ids: &[i64]) -> Vec<DocumentChunk> {
+        ids.iter().map(|&id| chunk(id, 0.0, "")).collect()
+    }
+
+    fn ids(results: &[DocumentChunk]) -> Vec<i64> {
+        results.iter().map(|d| d.id).collect()
+    }
+
+    #[test]
+    fn fusion_ranks_documents_found_by_both_lists_first() {
+        let keyword = vec![chunk(1, 9.0, "el **gato**"), chunk(2, 8.0, "**gato**"), chunk(3, 7.0, "")];
+        let semantic = vec![chunk(4, 0.9, ""), chunk(5, 0.8, ""), chunk(3, 0.7, "sin marcar")];
+        let fused = reciprocal_rank_fusion(&[keyword, semantic]);
+
+        // El 3 está tercero en ambas listas y supera a los primeros de cada una
+        assert_eq!(ids(&fused), vec![3, 1, 4, 2, 5]);
+        assert!((fused[0].score - 2.0 / (RRF_K + 3.0)).abs() < 1e-12);
+        assert!((fused[1].score - 1.0 / (RRF_K + 1.0)).abs() < 1e-12);
+        assert_eq!(fused[0].snippet, "");
+    }
+
+    #[test]
+    fn fusion_breaks_ties_by_list_order() {
+        let fused = reciprocal_rank_fusion(&[ranked(&[1, 2]), ranked(&[3, 4])]);
+        assert_eq!(ids(&fused), vec![1, 3, 2, 4]);
+        assert_eq!(fused[0].score, fused[1].score);
+
+        let fused = reciprocal_rank_fusion(&[ranked(&[3, 4]), ranked(&[1, 2])]);
+        assert_eq!(ids(&fused), vec![3, 1, 4, 2]);
+    }
+
+    #[test]
+    fn fusion_handles_lists_of_different_lengths() {
+        let fused = reciprocal_rank_fusion(&[ranked(&[1, 2, 3, 4, 5]), ranked(&[5]), Vec::new()]);
+        assert_eq!(ids(&fused), vec![5, 1, 2, 3, 4]);
+        assert!(reciprocal_rank_fusion(&[]).is_empty());
+        assert!(reciprocal_rank_fusion(&[Vec::new(), Vec::new()]).is_empty());
+
+        // Un documento repetido conserva el snippet con términos marcados
+        let fused = reciprocal_rank_fusion(&[vec![chunk(1, 0.5, "")], vec![chunk(1, 3.0, "**gato**")]]);
+        assert_eq!(fused.len(), 1);
+        assert_eq!(fused[0].snippet, "**gato**");
+    }
+
+    #[test]
+    fn rerank_fetches_more_candidates_than_it_returns() {
+        let rerank = |top_n| SearchOptions { rerank: true, rerank_top_n: top_n, ..Default::default() };
+        assert_eq!(candidate_count(5, &SearchOptions::default()), 5);
+        assert_eq!(candidate_count(5, &rerank(None)), 20);
+        assert_eq!(candidate_count(5, &rerank(Some(8))), 8);
+        // Nunca menos candidatos que resultados pedidos
+        assert_eq!(candidate_count(30, &rerank(Some(8))), 30);
+    }
+
+    #[test]
+    fn threshold_and_limit_apply_to_the_final_scores() {
+        // Tras el rerank los candidatos llegan ordenados por la puntuación del cross-encoder
+        let reranked: Vec<DocumentChunk> = (0..20).map(|i| chunk(i, 10.0 - i as f64, "")).collect();
+        assert_eq!(ids(&keep_best(reranked.clone(), Some(0.0), 5)), vec![0, 1, 2, 3, 4]);
+        assert_eq!(ids(&keep_best(reranked.clone(), Some(7.5), 5)), vec![0, 1, 2]);
+        assert_eq!(keep_best(reranked.clone(), None, 50).len(), 20);
+        assert!(keep_best(reranked, None, -1).is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_returns_at_most_limit_results_above_the_threshold() {
+        let rag = RagManager::new(crate::db::memory_pool().await);
+        for i in 0..12 {
+            let extra = " gato".repeat(i % 4);
+            rag.ingest("docs", &format!("{:02}.md", i), &format!("Nota {} sobre el gato{}.", i, extra), None).await.unwrap();
+        }
+
+        let mut query = SearchQuery::new("docs", "gato", 5, SearchOptions { mode: SearchMode::Keyword, ..Default::default() });
+        let all = rag.search(&SearchQuery { limit: 50, ..query.clone() }).await.unwrap();
+        assert_eq!(all.len(), 12);
+        assert_eq!(rag.search(&query).await.unwrap().len(), 5);
+
+        // Umbral entre puntuaciones: quedan solo los que lo superan, y nunca más de `limit`
+        let threshold = (all[2].score + all[3].score) / 2.0;
+        query.filters.min_score = Some(threshold);
+        let results = rag.search(&query).await.unwrap();
+        assert!(results.len() <= 5 && !results.is_empty());
+        assert!(results.iter().all(|d| d.score >= threshold));
+        assert_eq!(ids(&results), ids(&all[..results.len()]));
+
+        // Sin reranker cargado, pedir rerank es un error y no una búsqueda sin reordenar
+        query.options.rerank = true;
+        assert_eq!(rag.search(&query).await.unwrap_err(), "Reranker model not loaded");
+    }
+
+    /// Ficheros (sin repetir) que encuentra "gato" en `collections` con estos filtros.
+    async fn found(rag: &RagManager, collections: &[&str], filters: SearchFilters) -> Vec<String> {
+        let query = SearchQuery {
+            text: "gato".to_string(),
+            collections: collections.iter().map(|c| c.to_string()).collect(),
+            limit: 50,
+            filters,
+            options: SearchOptions { mode: SearchMode::Keyword, ..Default::default() },
+        };
+        let mut files: Vec<String> = rag.search(&query).await.unwrap().into_iter().map(|d| d.filename).collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    async fn tag(rag: &RagManager, path: &str, tags: &[&str]) {
+        let (id,): (i64,) = sqlx::query_as("SELECT id FROM sources WHERE path = ?").bind(path).fetch_one(&rag.pool).await.unwrap();
+        rag.set_tags(id, &tags.iter().map(|t| t.to_string()).collect::<Vec<_>>()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn each_filter_narrows_the_results() {
+        let rag = RagManager::new(crate::db::memory_pool().await);
+        for path in ["src/main.rs", "src/lib.rs"] {
+            rag.ingest("codigo", path, "// El gato del código\nfn gato() {}\n", None).await.unwrap();
+        }
+        for path in ["a_b/uno.md", "axb/dos.md", "100%/tres.md", "guia.txt"] {
+            rag.ingest("docs", path, "Un gato en la documentación.", None).await.unwrap();
+        }
+        tag(&rag, "src/main.rs", &["Proyecto"]).await;
+        tag(&rag, "guia.txt", &["proyecto", "manual"]).await;
+        tag(&rag, "a_b/uno.md", &["borrador"]).await;
+        sqlx::query("UPDATE documents SET created_at = '2024-01-15 10:00:00' WHERE filename IN ('src/lib.rs', 'axb/dos.md')")
+            .execute(&rag.pool)
+            .await
+            .unwrap();
+
+        let all = ["100%/tres.md", "a_b/uno.md", "axb/dos.md", "guia.txt", "src/lib.rs", "src/main.rs"];
+        let none = SearchFilters::default;
+        assert_eq!(found(&rag, &["codigo", "docs"], none()).await, all);
+        assert_eq!(found(&rag, &["codigo"], none()).await, ["src/lib.rs", "src/main.rs"]);
+        assert!(found(&rag, &[], none()).await.is_empty());
+        assert!(found(&rag, &["otra"], none()).await.is_empty());
+
+        // GLOB: basta con que encaje un patrón; `_` y `%` son literales
+        let globs = |globs: &[&str]| SearchFilters { filename_globs: globs.iter().map(|g| g.to_string()).collect(), ..none() };
+        assert_eq!(found(&rag, &["codigo", "docs"], globs(&["a_b/*"])).await, ["a_b/uno.md"]);
+        assert_eq!(found(&rag, &["codigo", "docs"], globs(&["100%/*", "src/m*"])).await, ["100%/tres.md", "src/main.rs"]);
+        assert!(found(&rag, &["codigo", "docs"], globs(&["%"])).await.is_empty());
+        assert_eq!(found(&rag, &["codigo", "docs"], globs(&["*.md"])).await, ["100%/tres.md", "a_b/uno.md", "axb/dos.md"]);
+
+        // Fechas inclusivas
+        let dates = |after: Option<&str>, before: Option<&str>| SearchFilters {
+            created_after: after.map(String::from),
+            created_before: before.map(String::from),
+            ..none()
+        };
+        assert_eq!(found(&rag, &["codigo", "docs"], dates(None, Some("2024-01-15"))).await, ["axb/dos.md", "src/lib.rs"]);
+        assert_eq!(found(&rag, &["codigo", "docs"], dates(Some("2024-01-15"), Some("2024-01-15"))).await, ["axb/dos.md", "src/lib.rs"]);
+        assert_eq!(found(&rag, &["codigo", "docs"], dates(Some("2024-01-16"), None)).await, ["100%/tres.md", "a_b/uno.md", "guia.txt", "src/main.rs"]);
+
+        let languages = |languages: &[&str]| SearchFilters { languages: languages.iter().map(|l| l.to_string()).collect(), ..none() };
+        assert_eq!(found(&rag, &["codigo", "docs"], languages(&["Rust"])).await, ["src/lib.rs", "src/main.rs"]);
+        assert_eq!(found(&rag, &["docs"], languages(&["rust", "markdown"])).await, ["100%/tres.md", "a_b/uno.md", "axb/dos.md"]);
+
+        // Al menos una de las etiquetas, sin distinguir mayúsculas
+        let tags = |tags: &[&str]| SearchFilters { tags: tags.iter().map(|t| t.to_string()).collect(), ..none() };
+        assert_eq!(found(&rag, &["codigo", "docs"], tags(&["PROYECTO"])).await, ["guia.txt", "src/main.rs"]);
+        assert_eq!(found(&rag, &["codigo", "docs"], tags(&["manual", "borrador"])).await, ["a_b/uno.md", "guia.txt"]);
+        assert_eq!(found(&rag, &["codigo"], tags(&["manual"])).await, Vec::<String>::new());
+
+        // Los filtros se combinan
+        let combined = SearchFilters { languages: vec!["rust".to_string()], tags: vec!["proyecto".to_string()], ..dates(Some("2024-01-01"), None) };
+        assert_eq!(found(&rag, &["codigo", "docs"], combined).await, ["src/main.rs"]);
+
+        let min_score = |min_score: f64| SearchFilters { min_score: Some(min_score), ..none() };
+        assert!(found(&rag, &["codigo", "docs"], min_score(f64::MAX)).await.is_empty());
+        assert_eq!(found(&rag, &["codigo", "docs"], min_score(f64::MIN)).await, all);
+    }
+}
@@ -0,0 +1,73 @@
+use ort::session::builder::GraphOptimizationLevel;
+use ort::session::Session;
+use ort::value::Tensor;
+use std::path::Path;
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+const MAX_SEQ_LEN: usize = 512;
+
+/// Cross-encoder ONNX (p.ej. ms-marco-MiniLM) que puntúa pares (query, pasaje) para reordenar resultados.
+pub struct CrossEncoder {
+    session: Session,
+    tokenizer: Tokenizer,
+    has_token_type_ids: bool,
+}
+
+impl CrossEncoder {
+    pub fn new<P: AsRef<Path>>(model_path: P, tokenizer_path: P) -> Result<Self, String> {
+        let mut tokenizer = Tokenizer::from_file(tokenizer_path).map_err(|e| e.to_string())?;
+        tokenizer
+            .with_truncation(Some(TruncationParams { max_length: MAX_SEQ_LEN, ..Default::default() }))
+            .map_err(|e| e.to_string())?;
+        tokenizer.with_padding(Some(PaddingParams::default()));
+
+        let session = Session::builder()
+            .map_err(|e: ort::Error| e.to_string())?
+            .with_optimization_level(GraphOptimizationLevel::Level3)
+            .map_err(|e: ort::Error| e.to_string())?
+            .with_intra_threads(4)
+            .map_err(|e: ort::Error| e.to_string())?
+            .commit_from_file(model_path)
+            .map_err(|e| e.to_string())?;
+
+        let has_token_type_ids = session.inputs().iter().any(|i| i.name() == "token_type_ids");
+
+        Ok(CrossEncoder { session, tokenizer, has_token_type_ids })
+    }
+
+    /// Relevancia de cada pasaje para la query (logit; mayor es mejor).
+    pub fn score(&mut self, query: &str, passages: &[&str]) -> Result<Vec<f32>, String> {
+        if passages.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let pairs: Vec<(&str, &str)> = passages.iter().map(|p| (query, *p)).collect();
+        let encodings = self.tokenizer.encode_batch(pairs, true).map_err(|e| e.to_string())?;
+        let batch = encodings.len();
+        let seq_len = encodings[0].get_ids().len();
+
+        let flatten = |f: fn(&tokenizers::Encoding) -> &[u32]| -> Vec<i64> {
+            encodings.iter().flat_map(|e| f(e).iter().map(|&v| v as i64)).collect()
+        };
+
+        let mut inputs = ort::inputs![
+            "input_ids" => Tensor::from_array(([batch, seq_len], flatten(|e| e.get_ids()))).map_err(|e| e.to_string())?,
+            "attention_mask" => Tensor::from_array(([batch, seq_len], flatten(|e| e.get_attention_mask()))).map_err(|e| e.to_string())?,
+        ];
+        if self.has_token_type_ids {
+            inputs.push((
+                "token_type_ids".into(),
+                Tensor::from_array(([batch, seq_len], flatten(|e| e.get_type_ids()))).map_err(|e| e.to_string())?.into(),
+            ));
+        }
+
+        let outputs = self.session.run(inputs).map_err(|e| e.to_string())?;
+        // logits: [batch, 1] (o [batch, 2] en modelos de clasificación binaria: nos quedamos con la clase positiva)
+        let logits = outputs[0].try_extract_array::<f32>().map_err(|e| e.to_string())?;
+        let scores = logits
+            .outer_iter()
+            .map(|row| row.iter().last().copied().unwrap_or_default())
+            .collect();
+        Ok(scores)
+    }
+}
@@ -0,0 +1,313 @@
+use crate::core::loaders::{self, DEFAULT_MAX_FILE_BYTES};
+use crate::core::rag::{IngestOutcome, RagManager};
+use chrono::Local;
+use notify_debouncer_full::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_full::{new_debouncer, DebounceEventResult, Debouncer, RecommendedCache};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Espera tras el último cambio antes de re-ingerir (guardar desde un editor genera varios eventos seguidos).
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchState {
+    /// Ingesta inicial o re-ingesta de cambios en curso
+    Indexing,
+    Watching,
+    /// El watcher del sistema ha fallado y puede haber perdido cambios. Se mantiene hasta que se
+    /// vuelve a vigilar la carpeta, que reconstruye el watcher
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchStatus {
+    pub collection: String,
+    pub path: String,
+    pub state: WatchState,
+    pub last_sync: Option<String>,
+    /// Último error. El de un fichero se limpia en la siguiente sincronización sin errores; el del
+    /// watcher se conserva mientras el estado sea `Error`
+    pub last_error: Option<String>,
+    /// Ficheros añadidos o actualizados desde que se empezó a vigilar
+    pub files_updated: usize,
+    /// Ficheros saltados en la última sincronización (formato no soportado, demasiado grandes)
+    pub files_skipped: usize,
+}
+
+struct FolderWatch {
+    // Al soltarlo se para el watcher, se cierra el canal y termina la tarea de sincronización
+    _debouncer: Debouncer<RecommendedWatcher, RecommendedCache>,
+    status: Arc<Mutex<WatchStatus>>,
+}
+
+/// Carpetas locales vinculadas a colecciones: se ingieren al empezar a vigilarlas y después
+/// se re-ingieren incrementalmente los ficheros que cambian.
+pub struct WatchManager {
+    rag: Arc<RagManager>,
+    watches: Mutex<HashMap<(String, PathBuf), FolderWatch>>,
+}
+
+impl WatchManager {
+    pub fn new(rag: Arc<RagManager>) -> Self {
+        Self { rag, watches: Mutex::new(HashMap::new()) }
+    }
+
+    /// Ruta canónica con la que se identifica (y persiste) la carpeta vigilada.
+    pub fn canonical_path(path: &str) -> Result<PathBuf, String> {
+        let path = Path::new(path).canonicalize().map_err(|e| format!("{}: {}", path, e))?;
+        if !path.is_dir() {
+            return Err(format!("Not a directory: {}", path.display()));
+        }
+        Ok(path)
+    }
+
+    /// Empieza a vigilar la carpeta. Si ya se vigilaba y el watcher había fallado, lo reconstruye.
+    pub fn watch(&self, collection: &str, root: PathBuf) -> Result<(), String> {
+        let key = (collection.to_string(), root.clone());
+        {
+            let mut watches = self.watches.lock().unwrap();
+            match watches.get(&key) {
+                Some(w) if w.status.lock().unwrap().state == WatchState::Error => {
+                    watches.remove(&key);
+                }
+                Some(_) => return Ok(()),
+                None => {}
+            }
+        }
+
+        let status = Arc::new(Mutex::new(WatchStatus {
+            collection: collection.to_string(),
+            path: root.to_string_lossy().to_string(),
+            state: WatchState::Indexing,
+            last_sync: None,
+            last_error: None,
+            files_updated: 0,
+            files_skipped: 0,
+        }));
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut debouncer = new_debouncer(DEBOUNCE, None, move |result: DebounceEventResult| {
+            let batch = match result {
+                Ok(events) => Ok(events.into_iter().flat_map(|e| e.event.paths).collect()),
+                Err(errors) => Err(errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; ")),
+            };
+            let _ = tx.send(batch);
+        })
+        .map_err(|e| e.to_string())?;
+        debouncer.watch(&root, RecursiveMode::Recursive).map_err(|e| e.to_string())?;
+
+        tauri::async_runtime::spawn(sync_loop(self.rag.clone(), collection.to_string(), root, status.clone(), rx));
+        self.watches.lock().unwrap().insert(key, FolderWatch { _debouncer: debouncer, status });
+        Ok(())
+    }
+
+    /// Deja de vigilar la carpeta. Lo ya ingerido se queda en la colección.
+    pub fn unwatch(&self, collection: &str, root: &Path) {
+        self.watches.lock().unwrap().remove(&(collection.to_string(), root.to_path_buf()));
+    }
+
+    /// Deja de vigilar todas las carpetas de la colección (al borrarla).
+    pub fn unwatch_collection(&self, collection: &str) {
+        self.watches.lock().unwrap().retain(|(c, _), _| c != collection);
+    }
+
+    pub fn statuses(&self) -> Vec<WatchStatus> {
+        let mut statuses: Vec<WatchStatus> = self
+            .watches
+            .lock()
+            .unwrap()
+            .values()
+            .map(|w| w.status.lock().unwrap().clone())
+            .collect();
+        statuses.sort_by(|a, b| (&a.collection, &a.path).cmp(&(&b.collection, &b.path)));
+        statuses
+    }
+}
+
+type ChangeBatch = Result<Vec<PathBuf>, String>;
+
+async fn sync_loop(
+    rag: Arc<RagManager>,
+    collection: String,
+    root: PathBuf,
+    status: Arc<Mutex<WatchStatus>>,
+    mut rx: mpsc::UnboundedReceiver<ChangeBatch>,
+) {
+    // Ingesta inicial: con el mtime/hash de `sources` solo se procesa lo que ha cambiado desde la última vez
+    let initial = rag.ingest_directory(&collection, &root, DEFAULT_MAX_FILE_BYTES, |_| {}).await;
+    {
+        let mut status = status.lock().unwrap();
+        match initial {
+            Ok(summary) => {
+                let (failed, skipped): (Vec<_>, Vec<_>) = summary.skipped.into_iter().partition(|s| s.error);
+                status.files_updated += summary.ingested;
+                status.files_skipped = skipped.len();
+                status.last_error = failed.first().map(|s| format!("{}: {}", s.path, s.reason));
+            }
+            Err(e) => status.last_error = Some(e),
+        }
+        status.state = WatchState::Watching;
+        status.last_sync = Some(Local::now().to_rfc3339());
+    }
+
+    // Un fallo del watcher no lo arregla un lote posterior: pueden haberse perdido eventos
+    let mut watcher_error: Option<String> = None;
+    while let Some(batch) = rx.recv().await {
+        let paths = match batch {
+            Ok(paths) => paths,
+            Err(e) => {
+                let mut status = status.lock().unwrap();
+                status.state = WatchState::Error;
+                status.last_error = Some(e.clone());
+                watcher_error = Some(e);
+                continue;
+            }
+        };
+
+        status.lock().unwrap().state = WatchState::Indexing;
+        let (updated, skipped, errors) = sync_changes(&rag, &collection, &root, paths).await;
+
+        let mut status = status.lock().unwrap();
+        status.state = if watcher_error.is_some() { WatchState::Error } else { WatchState::Watching };
+        status.files_updated += updated;
+        status.files_skipped = skipped;
+        status.last_error = watcher_error.clone().or_else(|| errors.into_iter().next());
+        status.last_sync = Some(Local::now().to_rfc3339());
+    }
+}
+
+/// Re-ingiere los ficheros modificados y borra de la colección los eliminados.
+/// Devuelve cuántos ficheros se han actualizado, cuántos se han saltado y los errores encontrados.
+async fn sync_changes(rag: &RagManager, collection: &str, root: &Path, paths: Vec<PathBuf>) -> (usize, usize, Vec<String>) {
+    let changed: BTreeSet<PathBuf> = paths.into_iter().filter(|p| p.starts_with(root)).collect();
+    let mut errors = Vec::new();
+
+    let (existing, removed): (Vec<PathBuf>, Vec<PathBuf>) = changed.into_iter().partition(|p| p.exists());
+    for path in removed {
+        let name = loaders::source_name(&path);
+        if let Err(e) = rag.delete_source_path(collection, &name).await {
+            errors.push(format!("{}: {}", name, e));
+        }
+    }
+    if existing.is_empty() {
+        return (0, 0, errors);
+    }
+
+    // Solo lo que la ingesta de directorio aceptaría (.gitignore, ocultos, formato, tamaño), recorriendo
+    // únicamente lo que ha cambiado: un directorio nuevo (p.ej. movido dentro de la carpeta) puede
+    // llegar como un único evento
+    let walk_root = root.to_path_buf();
+    let (files, skipped) = match tokio::task::spawn_blocking(move || loaders::walk_paths(&walk_root, &existing, DEFAULT_MAX_FILE_BYTES)).await {
+        Ok(walk) => walk,
+        Err(e) => {
+            errors.push(e.to_string());
+            return (0, 0, errors);
+        }
+    };
+
+    let (failed, skipped): (Vec<_>, Vec<_>) = skipped.into_iter().partition(|s| s.error);
+    errors.extend(failed.into_iter().map(|s| format!("{}: {}", s.path, s.reason)));
+
+    let mut updated = 0;
+    for file in files {
+        let name = loaders::source_name(&file);
+        match rag.reingest_file(collection, &name, file).await {
+            Ok(IngestOutcome::Unchanged) => {}
+            Ok(_) => updated += 1,
+            Err(e) => errors.push(format!("{}: {}", name, e)),
+        }
+    }
+
+    (updated, skipped.len(), errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn sources(pool: &sqlx::SqlitePool) -> Vec<(String, String)> {
+        sqlx::query_as("SELECT s.path, d.content FROM sources s JOIN documents d ON d.source_id = s.id ORDER BY s.path, d.id")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("watch-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        root.canonicalize().unwrap()
+    }
+
+    #[tokio::test]
+    async fn sync_changes_applies_modifications_deletions_and_new_directories() {
+        let pool = crate::db::memory_pool().await;
+        let rag = RagManager::new(pool.clone());
+        let root = temp_root("sync");
+        std::fs::write(root.join("uno.md"), "Primera versión.").unwrap();
+        std::fs::write(root.join("dos.md"), "Se va a borrar.").unwrap();
+        std::fs::write(root.join("tres.md"), "No cambia.").unwrap();
+        rag.ingest_directory("docs", &root, DEFAULT_MAX_FILE_BYTES, |_| {}).await.unwrap();
+        let name = |path: &str| loaders::source_name(&root.join(path));
+
+        std::fs::write(root.join("uno.md"), "Segunda versión.").unwrap();
+        std::fs::remove_file(root.join("dos.md")).unwrap();
+        // Un directorio nuevo llega como un único evento
+        std::fs::create_dir_all(root.join("nuevo/sub")).unwrap();
+        std::fs::write(root.join("nuevo/sub/cuatro.md"), "Fichero anidado.").unwrap();
+        std::fs::write(root.join("nuevo/foto.png"), [0u8; 4]).unwrap();
+        let outside = std::env::temp_dir().join("fuera.md");
+
+        let changed = vec![root.join("uno.md"), root.join("dos.md"), root.join("tres.md"), root.join("nuevo"), outside];
+        let (updated, skipped, errors) = sync_changes(&rag, "docs", &root, changed).await;
+        assert_eq!((updated, skipped), (2, 1), "{:?}", errors);
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let mut expected = vec![
+            (name("nuevo/sub/cuatro.md"), "Fichero anidado.".to_string()),
+            (name("tres.md"), "No cambia.".to_string()),
+            (name("uno.md"), "Segunda versión.".to_string()),
+        ];
+        expected.sort();
+        assert_eq!(sources(&pool).await, expected);
+
+        // Sin cambios reales no se vuelve a ingerir nada
+        assert_eq!(sync_changes(&rag, "docs", &root, vec![root.join("uno.md")]).await, (0, 0, Vec::new()));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn watcher_error_stays_visible_after_later_batches() {
+        let rag = Arc::new(RagManager::new(crate::db::memory_pool().await));
+        let root = temp_root("error");
+        std::fs::write(root.join("uno.md"), "Contenido.").unwrap();
+        let status = Arc::new(Mutex::new(WatchStatus {
+            collection: "docs".to_string(),
+            path: root.to_string_lossy().to_string(),
+            state: WatchState::Indexing,
+            last_sync: None,
+            last_error: None,
+            files_updated: 0,
+            files_skipped: 0,
+        }));
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        tx.send(Err("inotify queue overflow".to_string())).unwrap();
+        std::fs::write(root.join("dos.md"), "Nuevo.").unwrap();
+        tx.send(Ok(vec![root.join("dos.md")])).unwrap();
+        drop(tx);
+        sync_loop(rag.clone(), "docs".to_string(), root.clone(), status.clone(), rx).await;
+
+        let status = status.lock().unwrap().clone();
+        assert_eq!(status.state, WatchState::Error);
+        assert_eq!(status.last_error.as_deref(), Some("inotify queue overflow"));
+        // Los lotes siguientes se siguen procesando
+        assert_eq!(status.files_updated, 2);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
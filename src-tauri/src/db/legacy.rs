@@ -0,0 +1,418 @@
+//! Puesta al día de las bases de datos creadas antes de las migraciones numeradas.
+//!
+//! Esas bases se montaban con `CREATE TABLE IF NOT EXISTS` + `ALTER TABLE` sueltos en cada arranque,
+//! así que pueden estar en cualquier punto intermedio. Antes de la migración inicial se completan las
+//! tablas existentes (columnas y FK que faltan) para que `0001_initial.sql` solo tenga que crear las
+//! que no existan; después se rellenan los datos derivados (sources, árbol de mensajes, índices FTS).
+
+use sqlx::{Connection, SqliteConnection, SqlitePool};
+
+/// Columnas añadidas con `ALTER TABLE` a lo largo del tiempo, por tabla.
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("conversations", "pinned", "INTEGER NOT NULL DEFAULT 0"),
+    ("conversations", "archived", "INTEGER NOT NULL DEFAULT 0"),
+    ("conversations", "active_message_id", "INTEGER"),
+    ("messages", "parent_id", "INTEGER REFERENCES messages(id) ON DELETE CASCADE"),
+    ("messages", "model", "TEXT"),
+    ("messages", "agent_id", "INTEGER"),
+    ("messages", "prompt_tokens", "INTEGER"),
+    ("messages", "completion_tokens", "INTEGER"),
+    ("messages", "latency_ms", "INTEGER"),
+    ("messages", "cost", "REAL"),
+    ("messages", "error", "TEXT"),
+    ("usage_stats", "estimated_cost", "REAL DEFAULT 0.0"),
+    ("documents", "start_offset", "INTEGER"),
+    ("documents", "end_offset", "INTEGER"),
+    ("documents", "start_line", "INTEGER"),
+    ("documents", "end_line", "INTEGER"),
+    ("documents", "symbol", "TEXT"),
+    ("documents", "source_id", "INTEGER REFERENCES sources(id) ON DELETE CASCADE"),
+    ("sources", "language", "TEXT"),
+    ("endpoints", "context_window", "INTEGER"),
+];
+
+/// Una base es anterior a las migraciones si tiene tablas pero ninguna migración aplicada
+/// (también si la migración inicial falló a medias tras una puesta al día).
+pub async fn is_legacy(pool: &SqlitePool) -> Result<bool, sqlx::Error> {
+    if !table_exists(pool, "conversations").await? {
+        return Ok(false);
+    }
+    if !table_exists(pool, "_sqlx_migrations").await? {
+        return Ok(true);
+    }
+    let (applied,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM _sqlx_migrations")
+        .fetch_one(pool)
+        .await?;
+    Ok(applied == 0)
+}
+
+/// Completa las tablas existentes antes de aplicar las migraciones. Es idempotente.
+pub async fn upgrade_tables(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    // SQLite no permite cambiar una FK, así que `messages` sin cascada se reconstruye.
+    // Con foreign_keys desactivado para que el DROP no borre en cascada las citas de los mensajes.
+    if table_exists(pool, "messages").await? && !messages_cascade(pool).await? {
+        let mut conn = pool.acquire().await?;
+        sqlx::query("PRAGMA foreign_keys = OFF").execute(&mut *conn).await?;
+        let rebuilt = rebuild_messages(&mut conn).await;
+        // Pase lo que pase, la conexión no vuelve al pool con las FK desactivadas
+        if let Err(e) = sqlx::query("PRAGMA foreign_keys = ON").execute(&mut *conn).await {
+            let _ = conn.detach().close().await;
+            return Err(e);
+        }
+        rebuilt?;
+    }
+
+    for (table, column, definition) in ADDED_COLUMNS {
+        add_column(pool, table, column, definition).await?;
+    }
+    Ok(())
+}
+
+/// Si borrar una conversación ya borra en cascada sus mensajes.
+async fn messages_cascade(pool: &SqlitePool) -> Result<bool, sqlx::Error> {
+    let (cascade,): (bool,) = sqlx::query_as(
+        "SELECT EXISTS (SELECT 1 FROM pragma_foreign_key_list('messages')
+                        WHERE \"from\" = 'conversation_id' AND on_delete = 'CASCADE')"
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(cascade)
+}
+
+/// Copia `messages` a una tabla con la FK en cascada, conservando las columnas ya añadidas.
+/// Todo en una transacción: si algo falla, la tabla original queda como estaba.
+async fn rebuild_messages(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    sqlx::query(
+        "CREATE TABLE messages_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            conversation_id INTEGER NOT NULL,
+            role TEXT NOT NULL,
+            content TEXT NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY(conversation_id) REFERENCES conversations(id) ON DELETE CASCADE
+        )"
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let existing: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info('messages')")
+        .fetch_all(&mut *tx)
+        .await?;
+    let mut columns = vec!["id", "conversation_id", "role", "content", "created_at"];
+    for (table, column, definition) in ADDED_COLUMNS {
+        if *table == "messages" && existing.iter().any(|c| c == column) {
+            sqlx::query(&format!("ALTER TABLE messages_new ADD COLUMN {} {}", column, definition))
+                .execute(&mut *tx)
+                .await?;
+            columns.push(column);
+        }
+    }
+    let columns = columns.join(", ");
+    sqlx::query(&format!("INSERT INTO messages_new ({0}) SELECT {0} FROM messages", columns))
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DROP TABLE messages").execute(&mut *tx).await?;
+    sqlx::query("ALTER TABLE messages_new RENAME TO messages").execute(&mut *tx).await?;
+    tx.commit().await
+}
+
+/// Rellena los datos derivados una vez aplicadas las migraciones. Es idempotente.
+pub async fn backfill(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    // Indexar lo que ya existía antes de los índices FTS (o de que se reconstruyera `messages`).
+    // Lo primero: los triggers de UPDATE borran del índice la fila anterior, que tiene que estar indexada
+    for fts in ["documents_fts", "messages_fts", "conversations_fts"] {
+        sqlx::query(&format!("INSERT INTO {0}({0}) VALUES ('rebuild')", fts))
+            .execute(pool)
+            .await?;
+    }
+
+    // Sin ningún padre asignado, los mensajes son de antes del árbol: se encadenan en orden de
+    // inserción (un único hilo por conversación)
+    let (has_tree,): (bool,) = sqlx::query_as("SELECT EXISTS (SELECT 1 FROM messages WHERE parent_id IS NOT NULL)")
+        .fetch_one(pool)
+        .await?;
+    if !has_tree {
+        sqlx::query(
+            "UPDATE messages SET parent_id = (
+                SELECT MAX(p.id) FROM messages p WHERE p.conversation_id = messages.conversation_id AND p.id < messages.id
+             )"
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    // Un source por cada (colección, fichero) ingerido antes de existir la tabla.
+    // Hash vacío para que la siguiente ingesta del fichero lo reemplace en vez de duplicarlo.
+    sqlx::query(
+        "INSERT OR IGNORE INTO sources (collection, path, hash)
+         SELECT DISTINCT collection, filename, '' FROM documents WHERE source_id IS NULL"
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query(
+        "UPDATE documents SET source_id = (
+            SELECT s.id FROM sources s WHERE s.collection = documents.collection AND s.path = documents.filename
+         ) WHERE source_id IS NULL"
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+async fn table_exists(pool: &SqlitePool, name: &str) -> Result<bool, sqlx::Error> {
+    let (exists,): (bool,) = sqlx::query_as("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?)")
+        .bind(name)
+        .fetch_one(pool)
+        .await?;
+    Ok(exists)
+}
+
+/// Añade la columna si la tabla existe y aún no la tiene (si no existe la crea completa la migración).
+async fn add_column(pool: &SqlitePool, table: &str, column: &str, definition: &str) -> Result<(), sqlx::Error> {
+    if !table_exists(pool, table).await? {
+        return Ok(());
+    }
+    let (present,): (bool,) = sqlx::query_as("SELECT EXISTS (SELECT 1 FROM pragma_table_info(?) WHERE name = ?)")
+        .bind(table)
+        .bind(column)
+        .fetch_one(pool)
+        .await?;
+    if !present {
+        sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+            .execute(pool)
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{migrate, Database};
+
+    /// Esquema de la primera versión de la app, más `message_citations` tal como se creaba antes de
+    /// las migraciones (con `messages` aún sin cascada).
+    const BASELINE_SCHEMA: &str = "
+        CREATE TABLE conversations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            title TEXT NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE TABLE messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            conversation_id INTEGER NOT NULL,
+            role TEXT NOT NULL,
+            content TEXT NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY(conversation_id) REFERENCES conversations(id)
+        );
+        CREATE TABLE usage_stats (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            model_id TEXT NOT NULL,
+            date DATE DEFAULT (DATE('now')),
+            count INTEGER DEFAULT 1,
+            estimated_cost REAL DEFAULT 0.0,
+            UNIQUE(model_id, date)
+        );
+        CREATE TABLE accounts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            service TEXT NOT NULL,
+            username TEXT NOT NULL,
+            is_active BOOLEAN DEFAULT 1,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(service, username)
+        );
+        CREATE TABLE agents (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            description TEXT,
+            system_prompt TEXT NOT NULL,
+            default_model TEXT DEFAULT 'chatgpt',
+            is_built_in BOOLEAN DEFAULT 0
+        );
+        CREATE TABLE documents (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            collection TEXT NOT NULL,
+            filename TEXT NOT NULL,
+            content TEXT NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE TABLE message_citations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            message_id INTEGER NOT NULL,
+            n INTEGER NOT NULL,
+            document_id INTEGER NOT NULL,
+            filename TEXT NOT NULL,
+            start_line INTEGER,
+            end_line INTEGER,
+            symbol TEXT,
+            score REAL NOT NULL DEFAULT 0.0,
+            cited BOOLEAN NOT NULL DEFAULT 0,
+            FOREIGN KEY(message_id) REFERENCES messages(id) ON DELETE CASCADE
+        );
+
+        INSERT INTO conversations (id, title) VALUES (1, 'Dudas de Rust'), (2, 'Otra conversación');
+        INSERT INTO messages (id, conversation_id, role, content) VALUES
+            (1, 1, 'user', '¿Qué hace el borrow checker?'),
+            (2, 1, 'assistant', 'Comprueba los préstamos [1]'),
+            (3, 2, 'user', 'hola'),
+            (4, 1, 'user', 'gracias');
+        INSERT INTO message_citations (message_id, n, document_id, filename, cited) VALUES (2, 1, 1, 'rust.md', 1);
+        INSERT INTO documents (id, collection, filename, content) VALUES
+            (1, 'docs', 'rust.md', 'El borrow checker valida las referencias'),
+            (2, 'docs', 'rust.md', 'Segundo chunk del mismo fichero'),
+            (3, 'notas', 'notas.txt', 'Notas sueltas');
+    ";
+
+    /// Base en memoria sin migrar (una sola conexión, como `memory_pool`).
+    async fn empty_pool() -> SqlitePool {
+        sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
+    async fn fts_rows(pool: &SqlitePool, table: &str, query: &str) -> Vec<i64> {
+        sqlx::query_scalar(&format!("SELECT rowid FROM {0} WHERE {0} MATCH ? ORDER BY rowid", table))
+            .bind(query)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn baseline_database_keeps_its_data_after_migrating() {
+        let pool = empty_pool().await;
+        sqlx::raw_sql(BASELINE_SCHEMA).execute(&pool).await.unwrap();
+        assert!(is_legacy(&pool).await.unwrap());
+
+        migrate(&pool).await.unwrap();
+        // Una segunda pasada no cambia nada
+        assert!(!is_legacy(&pool).await.unwrap());
+        migrate(&pool).await.unwrap();
+
+        // Mensajes encadenados en orden de inserción dentro de cada conversación
+        let messages: Vec<(i64, Option<i64>, String)> = sqlx::query_as("SELECT id, parent_id, content FROM messages ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        let parents: Vec<(i64, Option<i64>)> = messages.iter().map(|(id, parent, _)| (*id, *parent)).collect();
+        assert_eq!(parents, [(1, None), (2, Some(1)), (3, None), (4, Some(2))]);
+        assert_eq!(messages[1].2, "Comprueba los préstamos [1]");
+
+        // Reconstruir `messages` no se lleva por delante las citas
+        let citations: Vec<(i64, String, bool)> = sqlx::query_as("SELECT message_id, filename, cited FROM message_citations")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(citations, [(2, "rust.md".to_string(), true)]);
+        let (messages_sql,): (String,) = sqlx::query_as("SELECT sql FROM sqlite_master WHERE name = 'messages'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(messages_sql.contains("ON DELETE CASCADE"));
+
+        // Lo que ya existía queda indexado
+        assert_eq!(fts_rows(&pool, "messages_fts", "borrow").await, [1]);
+        assert_eq!(fts_rows(&pool, "documents_fts", "borrow").await, [1]);
+        assert_eq!(fts_rows(&pool, "conversations_fts", "rust").await, [1]);
+
+        // Un source por fichero ingerido, enlazado desde sus chunks
+        let sources: Vec<(String, String)> = sqlx::query_as("SELECT collection, path FROM sources ORDER BY collection, path")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(sources, [("docs".to_string(), "rust.md".to_string()), ("notas".to_string(), "notas.txt".to_string())]);
+        let (unlinked,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM documents WHERE source_id IS NULL")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(unlinked, 0);
+
+        let db = Database { pool };
+        let thread: Vec<i64> = db.get_messages(1).await.unwrap().iter().map(|m| m.id).collect();
+        assert_eq!(thread, [1, 2, 4]);
+        assert_eq!(db.get_citations(2).await.unwrap().len(), 1);
+    }
+
+    async fn foreign_keys(pool: &SqlitePool) -> bool {
+        sqlx::query_scalar("PRAGMA foreign_keys").fetch_one(pool).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn messages_are_rebuilt_unless_conversation_id_cascades() {
+        let pool = empty_pool().await;
+        sqlx::raw_sql(BASELINE_SCHEMA).execute(&pool).await.unwrap();
+        // Una puesta al día anterior ya añadió `parent_id` (con su propia cascada) y lo rellenó
+        sqlx::raw_sql(
+            "ALTER TABLE messages ADD COLUMN parent_id INTEGER REFERENCES messages(id) ON DELETE CASCADE;
+             ALTER TABLE messages ADD COLUMN model TEXT;
+             UPDATE messages SET parent_id = 1, model = 'gpt' WHERE id = 2;"
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert!(!messages_cascade(&pool).await.unwrap());
+
+        upgrade_tables(&pool).await.unwrap();
+        assert!(messages_cascade(&pool).await.unwrap());
+        assert!(foreign_keys(&pool).await);
+        let row: (i64, Option<i64>, Option<String>, String) = sqlx::query_as("SELECT conversation_id, parent_id, model, content FROM messages WHERE id = 2")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(row, (1, Some(1), Some("gpt".to_string()), "Comprueba los préstamos [1]".to_string()));
+        let (citations,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM message_citations").fetch_one(&pool).await.unwrap();
+        assert_eq!(citations, 1);
+
+        // Con la cascada ya puesta no se vuelve a reconstruir
+        let (before,): (String,) = sqlx::query_as("SELECT sql FROM sqlite_master WHERE name = 'messages'").fetch_one(&pool).await.unwrap();
+        upgrade_tables(&pool).await.unwrap();
+        let (after,): (String,) = sqlx::query_as("SELECT sql FROM sqlite_master WHERE name = 'messages'").fetch_one(&pool).await.unwrap();
+        assert_eq!(before, after);
+    }
+
+    #[tokio::test]
+    async fn failed_rebuild_restores_foreign_keys() {
+        let pool = empty_pool().await;
+        // Una versión muy antigua sin NOT NULL: la copia a la tabla nueva falla a mitad de la reconstrucción
+        sqlx::raw_sql(
+            "CREATE TABLE conversations (id INTEGER PRIMARY KEY AUTOINCREMENT, title TEXT NOT NULL);
+             CREATE TABLE messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                conversation_id INTEGER REFERENCES conversations(id),
+                role TEXT,
+                content TEXT,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+             );
+             INSERT INTO conversations (id, title) VALUES (1, 'Vieja');
+             INSERT INTO messages (id, conversation_id, role, content) VALUES (1, 1, 'user', 'hola'), (2, 1, 'assistant', NULL);"
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        assert!(upgrade_tables(&pool).await.is_err());
+        assert!(foreign_keys(&pool).await);
+        // La transacción se deshizo: ni tabla a medias ni mensajes perdidos
+        assert!(!table_exists(&pool, "messages_new").await.unwrap());
+        let (messages,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM messages").fetch_one(&pool).await.unwrap();
+        assert_eq!(messages, 2);
+        assert!(!messages_cascade(&pool).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn newer_schema_is_rejected() {
+        let pool = crate::db::memory_pool().await;
+        sqlx::query("INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time) VALUES (9999, 'futura', 1, x'00', 0)")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let error = migrate(&pool).await.unwrap_err().to_string();
+        assert!(error.contains("newer than this app supports"), "{}", error);
+    }
+}
@@ -1,11 +1,191 @@
-use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool};
+use crate::core::citations::Citation;
+use crate::core::openai::EndpointProfile;
+use crate::core::rag::fts_query;
+use serde::{Deserialize, Serialize};
+use sqlx::{migrate::{MigrateDatabase, Migrator}, QueryBuilder, Row, Sqlite, SqlitePool};
 use std::fs;
 use tauri::Manager;
 
+mod legacy;
+
 pub struct Database {
     pool: SqlitePool,
 }
 
+/// Caracteres del último mensaje que se devuelven como vista previa al listar conversaciones.
+const MESSAGE_PREVIEW_CHARS: i64 = 120;
+
+/// Conversación tal como aparece en el listado.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationSummary {
+    pub id: i64,
+    pub title: String,
+    pub created_at: String,
+    /// Fecha del último mensaje (o de creación si no tiene)
+    pub updated_at: String,
+    pub pinned: bool,
+    pub archived: bool,
+    pub message_count: i64,
+    /// Coste estimado acumulado de las respuestas (todas las ramas)
+    pub cost: f64,
+    pub total_tokens: i64,
+    pub last_message: Option<String>,
+}
+
+/// Mensaje de una conversación. Los mensajes forman un árbol (cada uno apunta al anterior del hilo):
+/// editar un prompt o regenerar una respuesta crea una rama hermana en vez de sobrescribir.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatMessage {
+    pub id: i64,
+    pub parent_id: Option<i64>,
+    pub role: String,
+    pub content: String,
+    pub created_at: String,
+    /// Ids de las ramas alternativas en este punto (incluido este mensaje), de la más antigua a la más nueva
+    pub siblings: Vec<i64>,
+    pub meta: MessageMeta,
+}
+
+/// Datos de generación de un mensaje. En los del usuario solo se rellenan modelo y agente.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MessageMeta {
+    /// Proveedor que atendió la petición (ya resuelto el enrutado de `auto`)
+    pub model: Option<String>,
+    pub agent_id: Option<i64>,
+    /// `None` si el proveedor no informa de tokens
+    pub prompt_tokens: Option<i64>,
+    pub completion_tokens: Option<i64>,
+    pub latency_ms: Option<i64>,
+    pub cost: Option<f64>,
+    /// Error del proveedor; el mensaje queda sin contenido
+    pub error: Option<String>,
+}
+
+/// Búsqueda en el historial de conversaciones. Los filtros vacíos no filtran.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoryQuery {
+    pub text: String,
+    /// `user` / `assistant`. Con filtro de rol o de modelo no se buscan títulos
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Id del proveedor con el que se generó el turno (`openai_api`, `endpoint:<nombre>`...)
+    #[serde(default)]
+    pub models: Vec<String>,
+    /// Fechas (`YYYY-MM-DD`) inclusivas
+    #[serde(default)]
+    pub created_after: Option<String>,
+    #[serde(default)]
+    pub created_before: Option<String>,
+    #[serde(default = "default_history_limit")]
+    pub limit: i64,
+}
+
+fn default_history_limit() -> i64 {
+    20
+}
+
+/// Coincidencia en un mensaje o (sin `message_id`) en el título de una conversación.
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryHit {
+    pub conversation_id: i64,
+    pub conversation_title: String,
+    pub message_id: Option<i64>,
+    pub role: Option<String>,
+    pub model: Option<String>,
+    pub snippet: String,
+    pub created_at: String,
+    pub score: f64,
+}
+
+/// Añade los filtros de fecha sobre `column` a una consulta que ya tiene `WHERE`.
+fn push_date_filters(qb: &mut QueryBuilder<'_, Sqlite>, column: &str, query: &HistoryQuery) {
+    if let Some(after) = &query.created_after {
+        qb.push(format!(" AND DATE({}) >= DATE(", column)).push_bind(after.clone()).push(")");
+    }
+    if let Some(before) = &query.created_before {
+        qb.push(format!(" AND DATE({}) <= DATE(", column)).push_bind(before.clone()).push(")");
+    }
+}
+
+/// Migraciones numeradas de `migrations/`, embebidas en el binario.
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Base en memoria con el esquema actual, para los tests.
+#[cfg(test)]
+pub async fn memory_pool() -> SqlitePool {
+    // Una sola conexión: cada conexión a `:memory:` abre una base distinta
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    MIGRATOR.run(&pool).await.unwrap();
+    pool
+}
+
+/// Hace de `leaf` el último mensaje de la rama activa de su conversación y marca en cada nivel
+/// del hilo qué hijo se está viendo.
+async fn activate(tx: &mut sqlx::Transaction<'_, Sqlite>, leaf: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE conversations SET active_message_id = ? WHERE id = (SELECT conversation_id FROM messages WHERE id = ?)")
+        .bind(leaf)
+        .bind(leaf)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query(
+        "WITH RECURSIVE path(id, parent_id) AS (
+            SELECT id, parent_id FROM messages WHERE id = ?
+            UNION ALL
+            SELECT m.id, m.parent_id FROM messages m JOIN path p ON m.id = p.parent_id
+         )
+         UPDATE messages SET active_child_id = (SELECT p.id FROM path p WHERE p.parent_id = messages.id)
+         WHERE id IN (SELECT parent_id FROM path)"
+    )
+        .bind(leaf)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Última migración aplicada a la base (`None` si aún no se ha migrado nunca).
+async fn schema_version(pool: &SqlitePool) -> Result<Option<i64>, sqlx::Error> {
+    let (tracked,): (bool,) = sqlx::query_as("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations')")
+        .fetch_one(pool)
+        .await?;
+    if !tracked {
+        return Ok(None);
+    }
+    let (version,): (Option<i64>,) = sqlx::query_as("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+        .fetch_one(pool)
+        .await?;
+    Ok(version)
+}
+
+/// Lleva la base al esquema actual: pone al día las anteriores a las migraciones y aplica las pendientes.
+/// Falla si la base la ha migrado una versión más nueva de la app.
+async fn migrate(pool: &SqlitePool) -> Result<(), Box<dyn std::error::Error>> {
+    // Una base migrada por una versión más nueva de la app puede tener un esquema que esta no entiende
+    if let Some(version) = schema_version(pool).await? {
+        let supported = MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0);
+        if version > supported {
+            return Err(format!(
+                "Database schema version {} is newer than this app supports ({}); update the app",
+                version, supported
+            )
+            .into());
+        }
+    }
+
+    let legacy = legacy::is_legacy(pool).await?;
+    if legacy {
+        legacy::upgrade_tables(pool).await?;
+    }
+    MIGRATOR.run(pool).await?;
+    if legacy {
+        legacy::backfill(pool).await?;
+    }
+    Ok(())
+}
+
 impl Database {
     pub async fn new<R: tauri::Runtime>(app_handle: tauri::AppHandle<R>) -> Result<Self, Box<dyn std::error::Error>> {
         let app_dir = app_handle.path().app_data_dir()?;
@@ -21,111 +201,8 @@ impl Database {
         }
 
         let pool = SqlitePool::connect(&db_url).await?;
-        
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS conversations (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                title TEXT NOT NULL,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
-            )"
-        )
-        .execute(&pool)
-        .await?;
-
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS messages (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                conversation_id INTEGER NOT NULL,
-                role TEXT NOT NULL,
-                content TEXT NOT NULL,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                FOREIGN KEY(conversation_id) REFERENCES conversations(id)
-            )"
-        )
-        .execute(&pool)
-        .await?;
-
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS usage_stats (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                model_id TEXT NOT NULL,
-                date DATE DEFAULT (DATE('now')),
-                count INTEGER DEFAULT 1,
-                estimated_cost REAL DEFAULT 0.0,
-                UNIQUE(model_id, date)
-            )"
-        )
-        .execute(&pool)
-        .await?;
-
-        // Migración simple para tablas existentes (si falla es que ya existe o tabla nueva)
-        let _ = sqlx::query("ALTER TABLE usage_stats ADD COLUMN estimated_cost REAL DEFAULT 0.0")
-            .execute(&pool)
-            .await;
-
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS accounts (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                service TEXT NOT NULL,
-                username TEXT NOT NULL,
-                is_active BOOLEAN DEFAULT 1,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                UNIQUE(service, username)
-            )"
-        )
-        .execute(&pool)
-        .await?;
-
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS agents (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                name TEXT NOT NULL,
-                description TEXT,
-                system_prompt TEXT NOT NULL,
-                default_model TEXT DEFAULT 'chatgpt',
-                is_built_in BOOLEAN DEFAULT 0
-            )"
-        )
-        .execute(&pool)
-        .await?;
-
-        // Insert Default Agents
-        let _ = sqlx::query(
-            "INSERT INTO agents (name, description, system_prompt, default_model, is_built_in) 
-             VALUES ('Project Scaffolder', 'Genera estructuras de proyectos', 'Eres un experto en inicializar proyectos. Tu objetivo es generar comandos de terminal y estructuras de archivos para nuevos proyectos. Usa bloques de código para los comandos.', 'chatgpt', 1)
-             ON CONFLICT DO NOTHING" // SQLite no tiene ON CONFLICT en INSERT simple sin constraint unique, pero ids autoincrement...
-             // Mejor usamos WHERE NOT EXISTS o un SELECT count
-        )
-        .execute(&pool)
-        .await;
-        
-        // Mejor aproximación para evitar duplicados si no hay unique constraint en name
-        let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM agents WHERE name = 'Project Scaffolder'")
-            .fetch_one(&pool)
-            .await
-            .unwrap_or((0,));
-        
-        if count.0 == 0 {
-             let _ = sqlx::query(
-                "INSERT INTO agents (name, description, system_prompt, default_model, is_built_in) 
-                 VALUES ('Project Scaffolder', 'Genera estructuras de proyectos', 'Eres un experto en inicializar proyectos. Tu objetivo es generar comandos de terminal y estructuras de archivos para nuevos proyectos. Usa bloques de código para los comandos.', 'chatgpt', 1)"
-            )
-            .execute(&pool)
-            .await;
-        }
-
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS documents (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                collection TEXT NOT NULL,
-                filename TEXT NOT NULL,
-                content TEXT NOT NULL,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
-            )"
-        )
-        .execute(&pool)
-        .await?;
 
+        migrate(&pool).await?;
         Ok(Database { pool })
     }
 
@@ -142,39 +219,318 @@ impl Database {
         Ok(id)
     }
 
-    pub async fn add_message(&self, conversation_id: i64, role: &str, content: &str) -> Result<(), sqlx::Error> {
-        sqlx::query("INSERT INTO messages (conversation_id, role, content) VALUES (?, ?, ?)")
+    /// Conversaciones activas o archivadas, las fijadas primero y luego por actividad más reciente.
+    pub async fn list_conversations(&self, archived: bool, limit: i64, offset: i64) -> Result<Vec<ConversationSummary>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT c.id, c.title, c.created_at, c.pinned, c.archived,
+                    COUNT(m.id) AS message_count,
+                    COALESCE(SUM(m.cost), 0.0) AS cost,
+                    COALESCE(SUM(m.prompt_tokens), 0) + COALESCE(SUM(m.completion_tokens), 0) AS total_tokens,
+                    COALESCE(MAX(m.created_at), c.created_at) AS updated_at,
+                    (SELECT substr(content, 1, ?) FROM messages
+                     WHERE id = COALESCE(c.active_message_id, (SELECT MAX(id) FROM messages WHERE conversation_id = c.id))) AS last_message
+             FROM conversations c
+             LEFT JOIN messages m ON m.conversation_id = c.id
+             WHERE c.archived = ?
+             GROUP BY c.id
+             ORDER BY c.pinned DESC, updated_at DESC, c.id DESC
+             LIMIT ? OFFSET ?"
+        )
+            .bind(MESSAGE_PREVIEW_CHARS)
+            .bind(archived)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let conversations = rows.iter().map(|row| ConversationSummary {
+            id: row.get("id"),
+            title: row.get("title"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+            pinned: row.get("pinned"),
+            archived: row.get("archived"),
+            message_count: row.get("message_count"),
+            cost: row.get("cost"),
+            total_tokens: row.get("total_tokens"),
+            last_message: row.get("last_message"),
+        }).collect();
+        Ok(conversations)
+    }
+
+    pub async fn rename_conversation(&self, id: i64, title: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE conversations SET title = ? WHERE id = ?")
+            .bind(title)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Borra la conversación; mensajes y citas se borran en cascada.
+    pub async fn delete_conversation(&self, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM conversations WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_conversation_archived(&self, id: i64, archived: bool) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE conversations SET archived = ? WHERE id = ?")
+            .bind(archived)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_conversation_pinned(&self, id: i64, pinned: bool) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE conversations SET pinned = ? WHERE id = ?")
+            .bind(pinned)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Añade un mensaje como respuesta a `parent_id` (`None` = inicio de la conversación) y lo deja
+    /// como final de la rama activa.
+    pub async fn add_message(&self, conversation_id: i64, parent_id: Option<i64>, role: &str, content: &str, meta: &MessageMeta) -> Result<i64, sqlx::Error> {
+        self.add_message_with_citations(conversation_id, parent_id, role, content, meta, &[]).await
+    }
+
+    /// Como `add_message`, guardando en la misma transacción las citas del contexto: no puede
+    /// quedar una respuesta sin sus citas.
+    pub async fn add_message_with_citations(&self, conversation_id: i64, parent_id: Option<i64>, role: &str, content: &str, meta: &MessageMeta, citations: &[Citation]) -> Result<i64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let id = sqlx::query(
+            "INSERT INTO messages (conversation_id, parent_id, role, content, model, agent_id,
+                                   prompt_tokens, completion_tokens, latency_ms, cost, error)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        )
             .bind(conversation_id)
+            .bind(parent_id)
             .bind(role)
             .bind(content)
-            .execute(&self.pool)
+            .bind(&meta.model)
+            .bind(meta.agent_id)
+            .bind(meta.prompt_tokens)
+            .bind(meta.completion_tokens)
+            .bind(meta.latency_ms)
+            .bind(meta.cost)
+            .bind(&meta.error)
+            .execute(&mut *tx)
+            .await?
+            .last_insert_rowid();
+        for c in citations {
+            sqlx::query(
+                "INSERT INTO message_citations (message_id, n, document_id, filename, start_line, end_line, symbol, score, cited)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+            )
+            .bind(id)
+            .bind(c.n as i64)
+            .bind(c.document_id)
+            .bind(&c.filename)
+            .bind(c.start_line)
+            .bind(c.end_line)
+            .bind(&c.symbol)
+            .bind(c.score)
+            .bind(c.cited)
+            .execute(&mut *tx)
             .await?;
-        Ok(())
+        }
+        activate(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    pub async fn get_message(&self, id: i64) -> Result<Option<(i64, Option<i64>, String, String)>, sqlx::Error> {
+        sqlx::query_as("SELECT conversation_id, parent_id, role, content FROM messages WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
     }
 
-    pub async fn get_messages(&self, conversation_id: i64) -> Result<Vec<(String, String)>, sqlx::Error> {
-        let rows = sqlx::query_as::<_, (String, String)>("SELECT role, content FROM messages WHERE conversation_id = ? ORDER BY created_at ASC")
+    /// Último mensaje de la rama activa de la conversación.
+    pub async fn active_message(&self, conversation_id: i64) -> Result<Option<i64>, sqlx::Error> {
+        let row: Option<(Option<i64>,)> = sqlx::query_as(
+            "SELECT COALESCE(active_message_id, (SELECT MAX(id) FROM messages WHERE conversation_id = ?))
+             FROM conversations WHERE id = ?"
+        )
             .bind(conversation_id)
+            .bind(conversation_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.and_then(|(id,)| id))
+    }
+
+    /// Activa la rama que pasa por `message_id`: por debajo sigue en cada nivel la última rama vista
+    /// (o la respuesta más reciente si nunca se ha cambiado de rama ahí). Devuelve el último mensaje de la rama.
+    pub async fn switch_branch(&self, message_id: i64) -> Result<i64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut leaf = message_id;
+        while let Some((Some(child),)) = sqlx::query_as::<_, (Option<i64>,)>(
+            "SELECT COALESCE(active_child_id, (SELECT MAX(c.id) FROM messages c WHERE c.parent_id = m.id))
+             FROM messages m WHERE m.id = ?"
+        )
+            .bind(leaf)
+            .fetch_optional(&mut *tx)
+            .await?
+        {
+            leaf = child;
+        }
+
+        activate(&mut tx, leaf).await?;
+        tx.commit().await?;
+        Ok(leaf)
+    }
+
+    pub async fn get_citations(&self, message_id: i64) -> Result<Vec<Citation>, sqlx::Error> {
+        let rows = sqlx::query_as::<_, (i64, i64, String, Option<i64>, Option<i64>, Option<String>, f64, bool)>(
+            "SELECT n, document_id, filename, start_line, end_line, symbol, score, cited
+             FROM message_citations WHERE message_id = ? ORDER BY n"
+        )
+            .bind(message_id)
             .fetch_all(&self.pool)
             .await?;
-        Ok(rows)
+
+        let citations = rows.into_iter().map(|(n, document_id, filename, start_line, end_line, symbol, score, cited)| Citation {
+            n: n as usize,
+            document_id,
+            filename,
+            start_line,
+            end_line,
+            symbol,
+            score,
+            cited,
+        }).collect();
+        Ok(citations)
     }
 
-    pub async fn increment_usage(&self, model_id: &str, cost: f64) -> Result<(), sqlx::Error> {
-        sqlx::query(
-            "INSERT INTO usage_stats (model_id, count, estimated_cost) VALUES (?, 1, ?)
-             ON CONFLICT(model_id, date) DO UPDATE SET count = count + 1, estimated_cost = estimated_cost + ?"
+    /// Mensajes (de cualquier rama) y títulos que coinciden con la búsqueda, por relevancia (BM25).
+    pub async fn search_history(&self, query: &HistoryQuery) -> Result<Vec<HistoryHit>, sqlx::Error> {
+        let Some(fts) = fts_query(&query.text) else { return Ok(Vec::new()) };
+
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT * FROM (
+                SELECT m.conversation_id, c.title AS conversation_title, m.id AS message_id, m.role, m.model,
+                       snippet(messages_fts, 0, '**', '**', '…', 16) AS snippet, m.created_at, -bm25(messages_fts) AS score
+                FROM messages_fts
+                JOIN messages m ON m.id = messages_fts.rowid
+                JOIN conversations c ON c.id = m.conversation_id
+                WHERE messages_fts MATCH "
+        );
+        qb.push_bind(fts.clone());
+        if !query.roles.is_empty() {
+            qb.push(" AND m.role IN (");
+            let mut list = qb.separated(", ");
+            for role in &query.roles {
+                list.push_bind(role.clone());
+            }
+            qb.push(")");
+        }
+        if !query.models.is_empty() {
+            qb.push(" AND m.model IN (");
+            let mut list = qb.separated(", ");
+            for model in &query.models {
+                list.push_bind(model.clone());
+            }
+            qb.push(")");
+        }
+        push_date_filters(&mut qb, "m.created_at", query);
+
+        // Los títulos no tienen rol ni modelo: solo se buscan si no se filtra por ellos
+        if query.roles.is_empty() && query.models.is_empty() {
+            qb.push(
+                " UNION ALL
+                SELECT c.id, c.title, NULL, NULL, NULL,
+                       snippet(conversations_fts, 0, '**', '**', '…', 16), c.created_at, -bm25(conversations_fts)
+                FROM conversations_fts
+                JOIN conversations c ON c.id = conversations_fts.rowid
+                WHERE conversations_fts MATCH "
+            );
+            qb.push_bind(fts);
+            push_date_filters(&mut qb, "c.created_at", query);
+        }
+        qb.push(") ORDER BY score DESC LIMIT ");
+        qb.push_bind(query.limit);
+
+        let rows = qb.build().fetch_all(&self.pool).await?;
+        let hits = rows.iter().map(|row| HistoryHit {
+            conversation_id: row.get("conversation_id"),
+            conversation_title: row.get("conversation_title"),
+            message_id: row.get("message_id"),
+            role: row.get("role"),
+            model: row.get("model"),
+            snippet: row.get("snippet"),
+            created_at: row.get("created_at"),
+            score: row.get("score"),
+        }).collect();
+        Ok(hits)
+    }
+
+    /// Mensajes de la rama activa de la conversación, del primero al último.
+    pub async fn get_messages(&self, conversation_id: i64) -> Result<Vec<ChatMessage>, sqlx::Error> {
+        match self.active_message(conversation_id).await? {
+            Some(leaf) => self.get_path(leaf).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Hilo que lleva hasta `leaf` (incluido), desde el inicio de la conversación.
+    pub async fn get_path(&self, leaf: i64) -> Result<Vec<ChatMessage>, sqlx::Error> {
+        let rows = sqlx::query(
+            "WITH RECURSIVE path(id, depth) AS (
+                SELECT id, 0 FROM messages WHERE id = ?
+                UNION ALL
+                SELECT m.parent_id, p.depth + 1 FROM messages m JOIN path p ON m.id = p.id WHERE m.parent_id IS NOT NULL
+             )
+             SELECT m.id, m.parent_id, m.role, m.content, m.created_at, m.model, m.agent_id,
+                    m.prompt_tokens, m.completion_tokens, m.latency_ms, m.cost, m.error,
+                    (SELECT GROUP_CONCAT(id) FROM (
+                        SELECT s.id FROM messages s
+                        WHERE s.conversation_id = m.conversation_id AND s.parent_id IS m.parent_id ORDER BY s.id
+                    )) AS siblings
+             FROM path p JOIN messages m ON m.id = p.id
+             ORDER BY p.depth DESC"
         )
-        .bind(model_id)
-        .bind(cost)
-        .bind(cost)
-        .execute(&self.pool)
-        .await?;
-        Ok(())
+            .bind(leaf)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let messages = rows.iter().map(|row| ChatMessage {
+            id: row.get("id"),
+            parent_id: row.get("parent_id"),
+            role: row.get("role"),
+            content: row.get("content"),
+            created_at: row.get("created_at"),
+            siblings: row
+                .get::<Option<String>, _>("siblings")
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|id| id.parse().ok())
+                .collect(),
+            meta: MessageMeta {
+                model: row.get("model"),
+                agent_id: row.get("agent_id"),
+                prompt_tokens: row.get("prompt_tokens"),
+                completion_tokens: row.get("completion_tokens"),
+                latency_ms: row.get("latency_ms"),
+                cost: row.get("cost"),
+                error: row.get("error"),
+            },
+        }).collect();
+        Ok(messages)
     }
 
+    /// Respuestas y coste de hoy por modelo, calculados desde los metadatos de los mensajes.
     pub async fn get_daily_stats(&self) -> Result<Vec<(String, i64, f64)>, sqlx::Error> {
-        let rows = sqlx::query_as::<_, (String, i64, f64)>("SELECT model_id, count, estimated_cost FROM usage_stats WHERE date = DATE('now')")
+        let rows = sqlx::query_as::<_, (String, i64, f64)>(
+            "SELECT model, COUNT(*), COALESCE(SUM(cost), 0.0) FROM messages
+             WHERE role = 'assistant' AND error IS NULL AND model IS NOT NULL AND DATE(created_at) = DATE('now')
+             GROUP BY model"
+        )
             .fetch_all(&self.pool)
             .await?;
         Ok(rows)
@@ -244,4 +600,410 @@ impl Database {
             .await?;
         Ok(())
     }
+
+    // Endpoint profiles (APIs compatibles con OpenAI)
+    pub async fn save_endpoint(&self, endpoint: &EndpointProfile) -> Result<(), sqlx::Error> {
+        let headers = serde_json::to_string(&endpoint.headers).unwrap_or_else(|_| "{}".to_string());
+        sqlx::query(
+            "INSERT INTO endpoints (name, base_url, model, headers, cost_per_1k_tokens, context_window) VALUES (?, ?, ?, ?, ?, ?)
+             ON CONFLICT(name) DO UPDATE SET base_url = excluded.base_url, model = excluded.model,
+                headers = excluded.headers, cost_per_1k_tokens = excluded.cost_per_1k_tokens,
+                context_window = excluded.context_window"
+        )
+        .bind(&endpoint.name)
+        .bind(&endpoint.base_url)
+        .bind(&endpoint.model)
+        .bind(headers)
+        .bind(endpoint.cost_per_1k_tokens)
+        .bind(endpoint.context_window.map(|w| w as i64))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_endpoints(&self) -> Result<Vec<EndpointProfile>, sqlx::Error> {
+        let rows = sqlx::query_as::<_, (String, String, String, String, f64, Option<i64>)>("SELECT name, base_url, model, headers, cost_per_1k_tokens, context_window FROM endpoints ORDER BY name")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let endpoints = rows.into_iter().map(|(name, base_url, model, headers, cost_per_1k_tokens, context_window)| EndpointProfile {
+            name,
+            base_url,
+            model,
+            headers: serde_json::from_str(&headers).unwrap_or_default(),
+            cost_per_1k_tokens,
+            context_window: context_window.map(|w| w as usize),
+        }).collect();
+        Ok(endpoints)
+    }
+
+    // Carpetas vigiladas por colección
+    pub async fn add_watch_folder(&self, collection: &str, path: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("INSERT OR IGNORE INTO watch_folders (collection, path) VALUES (?, ?)")
+            .bind(collection)
+            .bind(path)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_watch_folders(&self) -> Result<Vec<(String, String)>, sqlx::Error> {
+        let rows = sqlx::query_as::<_, (String, String)>("SELECT collection, path FROM watch_folders ORDER BY id")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+
+    pub async fn remove_watch_folder(&self, collection: &str, path: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM watch_folders WHERE collection = ? AND path = ?")
+            .bind(collection)
+            .bind(path)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_endpoint(&self, name: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM endpoints WHERE name = ?")
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn database() -> Database {
+        Database { pool: memory_pool().await }
+    }
+
+    async fn add(db: &Database, conversation: i64, parent: Option<i64>, role: &str, content: &str) -> i64 {
+        db.add_message(conversation, parent, role, content, &MessageMeta::default()).await.unwrap()
+    }
+
+    fn ids(messages: &[ChatMessage]) -> Vec<i64> {
+        messages.iter().map(|m| m.id).collect()
+    }
+
+    #[tokio::test]
+    async fn get_path_follows_parents_and_lists_siblings() {
+        let db = database().await;
+        let c = db.create_conversation("Ramas").await.unwrap();
+        let u1 = add(&db, c, None, "user", "hola").await;
+        let a1 = add(&db, c, Some(u1), "assistant", "respuesta").await;
+        let u2 = add(&db, c, Some(a1), "user", "sigue").await;
+        let a2 = add(&db, c, Some(u2), "assistant", "más").await;
+        // Otra versión del primer prompt: hermano en la raíz
+        let u1b = add(&db, c, None, "user", "hola de nuevo").await;
+
+        let path = db.get_path(a2).await.unwrap();
+        assert_eq!(ids(&path), [u1, a1, u2, a2]);
+        assert_eq!(path[0].siblings, [u1, u1b]);
+        assert_eq!(path[1].siblings, [a1]);
+        assert_eq!((path[1].parent_id, path[1].role.as_str(), path[1].content.as_str()), (Some(u1), "assistant", "respuesta"));
+
+        // Lo último añadido es la rama activa
+        assert_eq!(ids(&db.get_messages(c).await.unwrap()), [u1b]);
+    }
+
+    #[tokio::test]
+    async fn regenerating_adds_a_sibling_and_activates_it() {
+        let db = database().await;
+        let c = db.create_conversation("Regenerar").await.unwrap();
+        let u1 = add(&db, c, None, "user", "pregunta").await;
+        let a1 = add(&db, c, Some(u1), "assistant", "primera").await;
+        let a1b = add(&db, c, Some(u1), "assistant", "segunda").await;
+
+        let messages = db.get_messages(c).await.unwrap();
+        assert_eq!(ids(&messages), [u1, a1b]);
+        assert_eq!(messages[1].siblings, [a1, a1b]);
+        assert_eq!(db.active_message(c).await.unwrap(), Some(a1b));
+    }
+
+    #[tokio::test]
+    async fn switch_branch_returns_to_the_last_viewed_leaf() {
+        let db = database().await;
+        let c = db.create_conversation("Volver").await.unwrap();
+        let u1 = add(&db, c, None, "user", "pregunta").await;
+        let a1 = add(&db, c, Some(u1), "assistant", "primera").await;
+        let u2 = add(&db, c, Some(a1), "user", "sigue").await;
+        let a2 = add(&db, c, Some(u2), "assistant", "respuesta a").await;
+        let a2b = add(&db, c, Some(u2), "assistant", "respuesta b").await;
+        let a1b = add(&db, c, Some(u1), "assistant", "segunda").await;
+        let u3 = add(&db, c, Some(a1b), "user", "por otro lado").await;
+
+        // Desde la primera respuesta se baja a la última rama creada debajo
+        assert_eq!(db.switch_branch(a1).await.unwrap(), a2b);
+        // Se cambia a la respuesta más antigua de ese nivel...
+        assert_eq!(db.switch_branch(a2).await.unwrap(), a2);
+        // ...se va a la otra rama y al volver se recupera `a2`, no la respuesta más reciente
+        assert_eq!(db.switch_branch(a1b).await.unwrap(), u3);
+        assert_eq!(db.switch_branch(a1).await.unwrap(), a2);
+        assert_eq!(ids(&db.get_messages(c).await.unwrap()), [u1, a1, u2, a2]);
+
+        // Un mensaje nuevo pasa a ser lo último visto en su rama
+        let u4 = add(&db, c, Some(a1b), "user", "otra pregunta").await;
+        assert_eq!(db.switch_branch(a1).await.unwrap(), a2);
+        assert_eq!(db.switch_branch(a1b).await.unwrap(), u4);
+    }
+
+    async fn reply(db: &Database, conversation: i64, parent: i64, model: &str, content: &str) -> i64 {
+        let meta = MessageMeta { model: Some(model.to_string()), ..Default::default() };
+        db.add_message(conversation, Some(parent), "assistant", content, &meta).await.unwrap()
+    }
+
+    fn history(text: &str) -> HistoryQuery {
+        HistoryQuery { text: text.to_string(), roles: Vec::new(), models: Vec::new(), created_after: None, created_before: None, limit: 20 }
+    }
+
+    /// (conversación, mensaje) de cada resultado, en orden.
+    async fn hits(db: &Database, query: &HistoryQuery) -> Vec<(i64, Option<i64>)> {
+        db.search_history(query).await.unwrap().iter().map(|h| (h.conversation_id, h.message_id)).collect()
+    }
+
+    #[tokio::test]
+    async fn search_history_covers_messages_and_titles() {
+        let db = database().await;
+        let rust = db.create_conversation("Dudas de Rust").await.unwrap();
+        let u1 = add(&db, rust, None, "user", "¿Qué hace el borrow checker?").await;
+        let a1 = reply(&db, rust, u1, "openai_api", "El borrow checker comprueba los préstamos: borrow, borrow, borrow.").await;
+        let cocina = db.create_conversation("Recetas").await.unwrap();
+        let u2 = add(&db, cocina, None, "user", "Receta de tortilla").await;
+        let a2 = reply(&db, cocina, u2, "endpoint:local", "La tortilla lleva huevo y patata.").await;
+        let titulo = db.create_conversation("Borrow y lifetimes").await.unwrap();
+
+        // Mensajes y títulos, de más a menos relevante
+        let found = db.search_history(&history("borrow")).await.unwrap();
+        assert!(found.windows(2).all(|w| w[0].score >= w[1].score));
+        // El mensaje que repite el término va antes que el que lo nombra una vez
+        let position = |id| found.iter().position(|h| h.message_id == Some(id)).unwrap();
+        assert!(position(a1) < position(u1));
+        let mut sources: Vec<(i64, Option<i64>)> = found.iter().map(|h| (h.conversation_id, h.message_id)).collect();
+        sources.sort();
+        assert_eq!(sources, [(rust, Some(u1)), (rust, Some(a1)), (titulo, None)]);
+        let title_hit = found.iter().find(|h| h.message_id.is_none()).unwrap();
+        assert_eq!((title_hit.conversation_title.as_str(), title_hit.snippet.as_str()), ("Borrow y lifetimes", "**Borrow** y lifetimes"));
+        assert_eq!(found.iter().find(|h| h.message_id == Some(a1)).unwrap().model.as_deref(), Some("openai_api"));
+
+        // Con filtro de rol o modelo no se buscan títulos
+        let assistant = HistoryQuery { roles: vec!["assistant".to_string()], ..history("borrow tortilla") };
+        let mut found = hits(&db, &assistant).await;
+        found.sort();
+        assert_eq!(found, [(rust, Some(a1)), (cocina, Some(a2))]);
+        let local = HistoryQuery { models: vec!["endpoint:local".to_string()], ..history("borrow tortilla") };
+        assert_eq!(hits(&db, &local).await, [(cocina, Some(a2))]);
+        let user = HistoryQuery { roles: vec!["user".to_string()], ..history("borrow") };
+        assert_eq!(hits(&db, &user).await, [(rust, Some(u1))]);
+
+        // Fechas inclusivas, sobre mensajes y sobre la creación de la conversación
+        sqlx::query("UPDATE messages SET created_at = '2024-03-10 12:00:00' WHERE id = ?").bind(u2).execute(&db.pool).await.unwrap();
+        sqlx::query("UPDATE conversations SET created_at = '2024-03-01 09:00:00' WHERE id = ?").bind(titulo).execute(&db.pool).await.unwrap();
+        let old = |after: &str, before: &str| HistoryQuery {
+            created_after: Some(after.to_string()),
+            created_before: Some(before.to_string()),
+            ..history("borrow tortilla")
+        };
+        let mut found = hits(&db, &old("2024-03-01", "2024-03-10")).await;
+        found.sort();
+        assert_eq!(found, [(cocina, Some(u2)), (titulo, None)]);
+        assert_eq!(hits(&db, &old("2024-03-02", "2024-03-31")).await, [(cocina, Some(u2))]);
+
+        let best = hits(&db, &history("borrow")).await[0];
+        assert_eq!(hits(&db, &HistoryQuery { limit: 1, ..history("borrow") }).await, [best]);
+    }
+
+    #[tokio::test]
+    async fn search_history_accepts_fts_syntax_as_plain_text() {
+        let db = database().await;
+        let c = db.create_conversation("Sintaxis").await.unwrap();
+        let u1 = add(&db, c, None, "user", "Un prompt con \"comillas\" y guiones-medios").await;
+        let u2 = add(&db, c, Some(u1), "user", "prefijo* suelto").await;
+
+        // Comillas sin cerrar, `-`, `*` y operadores se tratan como texto, sin errores de FTS5
+        let mut found = hits(&db, &history("\"comillas -guiones prefijo* NOT OR")).await;
+        found.sort();
+        assert_eq!(found, [(c, Some(u1)), (c, Some(u2))]);
+        assert_eq!(hits(&db, &history("medios")).await, [(c, Some(u1))]);
+        // `*` no es un comodín: "prefi*" no encuentra "prefijo"
+        assert!(hits(&db, &history("prefi*")).await.is_empty());
+        assert!(hits(&db, &history("\"*- ")).await.is_empty());
+    }
+
+    fn titles(conversations: &[ConversationSummary]) -> Vec<&str> {
+        conversations.iter().map(|c| c.title.as_str()).collect()
+    }
+
+    async fn set_created_at(db: &Database, table: &str, id: i64, created_at: &str) {
+        sqlx::query(&format!("UPDATE {} SET created_at = ? WHERE id = ?", table))
+            .bind(created_at)
+            .bind(id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_conversations_orders_pages_and_summarizes() {
+        let db = database().await;
+        let vieja = db.create_conversation("Vieja").await.unwrap();
+        let u = add(&db, vieja, None, "user", "primera pregunta").await;
+        set_created_at(&db, "messages", u, "2024-01-01 10:00:00").await;
+        let a = db.add_message(vieja, Some(u), "assistant", &"respuesta larga ".repeat(20), &MessageMeta {
+            model: Some("openai_api".to_string()),
+            prompt_tokens: Some(10),
+            completion_tokens: Some(5),
+            cost: Some(0.25),
+            ..Default::default()
+        }).await.unwrap();
+        set_created_at(&db, "messages", a, "2024-01-01 10:00:05").await;
+        // Una rama alternativa cuenta para el total, pero la vista previa es la de la rama activa
+        let b = add(&db, vieja, Some(u), "assistant", "otra respuesta").await;
+        set_created_at(&db, "messages", b, "2024-01-01 09:00:00").await;
+        db.switch_branch(a).await.unwrap();
+
+        let reciente = db.create_conversation("Reciente").await.unwrap();
+        let r = add(&db, reciente, None, "user", "hola").await;
+        set_created_at(&db, "messages", r, "2024-02-01 10:00:00").await;
+        let vacia = db.create_conversation("Vacía").await.unwrap();
+        set_created_at(&db, "conversations", vacia, "2024-01-15 10:00:00").await;
+
+        // Más reciente primero (por último mensaje, o creación si no tiene)
+        let all = db.list_conversations(false, 10, 0).await.unwrap();
+        assert_eq!(titles(&all), ["Reciente", "Vacía", "Vieja"]);
+        let summary = &all[2];
+        assert_eq!((summary.message_count, summary.total_tokens), (3, 15));
+        assert!((summary.cost - 0.25).abs() < 1e-9);
+        assert_eq!(summary.updated_at, "2024-01-01 10:00:05");
+        let preview = summary.last_message.as_deref().unwrap();
+        assert_eq!(preview.chars().count(), MESSAGE_PREVIEW_CHARS as usize);
+        assert!(preview.starts_with("respuesta larga"));
+        assert_eq!((all[1].message_count, all[1].last_message.as_deref()), (0, None));
+        assert_eq!(all[1].updated_at, "2024-01-15 10:00:00");
+
+        // Paginación
+        assert_eq!(titles(&db.list_conversations(false, 2, 0).await.unwrap()), ["Reciente", "Vacía"]);
+        assert_eq!(titles(&db.list_conversations(false, 2, 2).await.unwrap()), ["Vieja"]);
+        assert!(db.list_conversations(false, 2, 4).await.unwrap().is_empty());
+
+        // Las fijadas van primero; las archivadas solo salen en su propia lista
+        db.set_conversation_pinned(vieja, true).await.unwrap();
+        db.set_conversation_archived(reciente, true).await.unwrap();
+        db.rename_conversation(vacia, "Renombrada").await.unwrap();
+        let active = db.list_conversations(false, 10, 0).await.unwrap();
+        assert_eq!(titles(&active), ["Vieja", "Renombrada"]);
+        assert!(active[0].pinned && !active[1].pinned);
+        let archived = db.list_conversations(true, 10, 0).await.unwrap();
+        assert_eq!(titles(&archived), ["Reciente"]);
+        assert!(archived[0].archived);
+
+        db.set_conversation_pinned(vieja, false).await.unwrap();
+        db.set_conversation_archived(reciente, false).await.unwrap();
+        assert_eq!(titles(&db.list_conversations(false, 10, 0).await.unwrap()), ["Reciente", "Renombrada", "Vieja"]);
+    }
+
+    fn citation(n: usize) -> Citation {
+        Citation {
+            n,
+            document_id: 7,
+            filename: "rust.md".to_string(),
+            start_line: Some(1),
+            end_line: Some(3),
+            symbol: None,
+            score: 0.5,
+            cited: n == 1,
+        }
+    }
+
+    async fn count(db: &Database, sql: &str, id: i64) -> i64 {
+        sqlx::query_scalar(sql).bind(id).fetch_one(&db.pool).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn deleting_a_conversation_removes_its_messages_and_citations() {
+        let db = database().await;
+        let c = db.create_conversation("Se borra").await.unwrap();
+        let otra = db.create_conversation("Se queda").await.unwrap();
+        let u = add(&db, c, None, "user", "pregunta sobre borrow").await;
+        let a = db.add_message_with_citations(c, Some(u), "assistant", "respuesta [1]", &MessageMeta::default(), &[citation(1)]).await.unwrap();
+        let kept = add(&db, otra, None, "user", "otra pregunta sobre borrow").await;
+
+        db.delete_conversation(c).await.unwrap();
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM messages WHERE conversation_id = ?", c).await, 0);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM message_citations WHERE message_id = ?", a).await, 0);
+        assert!(db.get_citations(a).await.unwrap().is_empty());
+        assert_eq!(titles(&db.list_conversations(false, 10, 0).await.unwrap()), ["Se queda"]);
+        // Tampoco quedan en el índice del historial
+        assert_eq!(hits(&db, &history("borrow")).await, [(otra, Some(kept))]);
+    }
+
+    #[tokio::test]
+    async fn message_meta_round_trips() {
+        let db = database().await;
+        let c = db.create_conversation("Metadatos").await.unwrap();
+        let user_meta = MessageMeta { model: Some("endpoint:local".to_string()), agent_id: Some(3), ..Default::default() };
+        let u = db.add_message(c, None, "user", "pregunta", &user_meta).await.unwrap();
+        let meta = MessageMeta {
+            model: Some("endpoint:local".to_string()),
+            agent_id: Some(3),
+            prompt_tokens: Some(120),
+            completion_tokens: Some(48),
+            latency_ms: Some(1530),
+            cost: Some(0.0042),
+            error: None,
+        };
+        let a = db.add_message(c, Some(u), "assistant", "respuesta", &meta).await.unwrap();
+        let failed = MessageMeta { model: Some("openai_api".to_string()), error: Some("HTTP 429".to_string()), ..Default::default() };
+        let e = db.add_message(c, Some(u), "assistant", "", &failed).await.unwrap();
+        db.switch_branch(a).await.unwrap();
+
+        let messages = db.get_messages(c).await.unwrap();
+        assert_eq!(ids(&messages), [u, a]);
+        assert_eq!(messages[0].meta, user_meta);
+        assert_eq!(messages[1].meta, meta);
+        assert_eq!(db.get_path(e).await.unwrap()[1].meta, failed);
+    }
+
+    #[tokio::test]
+    async fn daily_stats_count_todays_successful_replies_per_model() {
+        let db = database().await;
+        let c = db.create_conversation("Gastos").await.unwrap();
+        let cost = |model: &str, cost: Option<f64>| MessageMeta { model: Some(model.to_string()), cost, ..Default::default() };
+        let u = db.add_message(c, None, "user", "pregunta", &cost("openai_api", None)).await.unwrap();
+        db.add_message(c, Some(u), "assistant", "una", &cost("openai_api", Some(0.125))).await.unwrap();
+        db.add_message(c, Some(u), "assistant", "otra", &cost("openai_api", Some(0.25))).await.unwrap();
+        db.add_message(c, Some(u), "assistant", "gratis", &cost("endpoint:local", None)).await.unwrap();
+        // No cuentan: respuestas fallidas ni de otro día
+        let failed = MessageMeta { error: Some("timeout".to_string()), ..cost("openai_api", Some(1.0)) };
+        db.add_message(c, Some(u), "assistant", "", &failed).await.unwrap();
+        let old = db.add_message(c, Some(u), "assistant", "ayer", &cost("openai_api", Some(1.0))).await.unwrap();
+        sqlx::query("UPDATE messages SET created_at = DATETIME('now', '-1 day') WHERE id = ?").bind(old).execute(&db.pool).await.unwrap();
+
+        let mut stats = db.get_daily_stats().await.unwrap();
+        stats.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(stats, [("endpoint:local".to_string(), 1, 0.0), ("openai_api".to_string(), 2, 0.375)]);
+    }
+
+    #[tokio::test]
+    async fn message_and_citations_are_stored_together() {
+        let db = database().await;
+        let c = db.create_conversation("Citas").await.unwrap();
+        let u = add(&db, c, None, "user", "pregunta").await;
+        let a = db.add_message_with_citations(c, Some(u), "assistant", "respuesta [1]", &MessageMeta::default(), &[citation(1), citation(2)]).await.unwrap();
+        let stored: Vec<(usize, bool)> = db.get_citations(a).await.unwrap().iter().map(|c| (c.n, c.cited)).collect();
+        assert_eq!(stored, [(1, true), (2, false)]);
+
+        // Si falla una cita, no queda la respuesta sin ellas ni cambia la rama activa
+        sqlx::query("CREATE TEMP TRIGGER fail_citation BEFORE INSERT ON message_citations WHEN NEW.n = 2 BEGIN SELECT RAISE(ABORT, 'boom'); END")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let failed = db.add_message_with_citations(c, Some(u), "assistant", "otra [1]", &MessageMeta::default(), &[citation(1), citation(2)]).await;
+        assert!(failed.is_err());
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM messages WHERE conversation_id = ?", c).await, 2);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM message_citations WHERE message_id <> ?", a).await, 0);
+        assert_eq!(db.active_message(c).await.unwrap(), Some(a));
+    }
 }
@@ -2,18 +2,29 @@
 mod core;
 mod db;
 use crate::core::orchestrator::Orchestrator;
-use crate::db::Database;
-use crate::core::rag::RagManager;
-use crate::core::local_llm::LocalInferenceEngine;
-use std::sync::{Arc, Mutex};
-use tauri::{State, Manager};
+use crate::db::{ChatMessage, ConversationSummary, Database, HistoryHit, HistoryQuery, MessageMeta};
+use crate::core::chunking::ChunkerConfig;
+use crate::core::citations::Citation;
+use crate::core::bundle::BundleManifest;
+use crate::core::rag::{CollectionInfo, ImportSummary, IngestOutcome, IngestSummary, RagManager, SearchOptions, SearchQuery};
+use crate::core::reranker::CrossEncoder;
+use crate::core::watcher::{WatchManager, WatchStatus};
+use crate::core::embeddings::EmbeddingEngine;
+use crate::core::local_llm::{EngineSlot, GenerationConfig, LoadedEngine, LocalInferenceEngine};
+use crate::core::openai::EndpointProfile;
+use crate::core::provider::{ChatProvider, OpenAiProvider, ProviderRegistry, TokenCounter, API_TOKENIZER_RESOURCE, ENDPOINT_KEY_SERVICE};
+use std::sync::{Arc, Mutex, RwLock};
+use tauri::{Emitter, State, Manager};
+use tauri_plugin_dialog::{DialogExt, MessageDialogKind};
 
 struct AppState {
-    orchestrator: Mutex<Orchestrator>,
+    orchestrator: Arc<Orchestrator>,
     db: Arc<Database>,
-    local_llm: Mutex<Option<LocalInferenceEngine>>,
+    local_llm: EngineSlot,
+    providers: Arc<RwLock<ProviderRegistry>>,
     telemetry: Arc<crate::core::telemetry::TelemetryManager>,
     rag: Arc<RagManager>,
+    watcher: Arc<WatchManager>,
 }
 
 #[tauri::command]
@@ -78,13 +89,115 @@ async fn update_agent(state: State<'_, AppState>, id: i64, name: &str, descripti
 
 // RAG Commands
 #[tauri::command]
-async fn ingest_document(state: State<'_, AppState>, collection: &str, filename: &str, content: &str) -> Result<(), String> {
-    state.rag.ingest(collection, filename, content).await.map_err(|e| e.to_string())
+async fn ingest_document(state: State<'_, AppState>, collection: &str, filename: &str, content: &str) -> Result<IngestOutcome, String> {
+    state.rag.ingest(collection, filename, content, None).await
 }
 
 #[tauri::command]
-async fn rag_search(state: State<'_, AppState>, collection: &str, query: &str) -> Result<Vec<crate::core::rag::DocumentChunk>, String> {
-    state.rag.search(collection, query, 5).await.map_err(|e| e.to_string())
+async fn delete_document(state: State<'_, AppState>, id: i64) -> Result<(), String> {
+    state.rag.delete_source(id).await
+}
+
+#[tauri::command]
+async fn drop_collection(state: State<'_, AppState>, collection: &str) -> Result<(), String> {
+    state.watcher.unwatch_collection(collection);
+    state.rag.drop_collection(collection).await
+}
+
+#[tauri::command]
+async fn rebuild_collection(state: State<'_, AppState>, collection: &str) -> Result<usize, String> {
+    state.rag.rebuild_collection(collection).await
+}
+
+/// Evento de progreso de `ingest_directory` (un `IngestProgress` por fichero).
+const INGEST_PROGRESS_EVENT: &str = "ingest_progress";
+
+#[tauri::command]
+async fn ingest_directory(app_handle: tauri::AppHandle, state: State<'_, AppState>, collection: &str, path: &str, max_file_bytes: Option<u64>) -> Result<IngestSummary, String> {
+    let max_file_bytes = max_file_bytes.unwrap_or(crate::core::loaders::DEFAULT_MAX_FILE_BYTES);
+    state.rag.ingest_directory(collection, std::path::Path::new(path), max_file_bytes, |progress| {
+        let _ = app_handle.emit(INGEST_PROGRESS_EVENT, progress);
+    }).await
+}
+
+#[tauri::command]
+async fn add_watch_folder(state: State<'_, AppState>, collection: &str, path: &str) -> Result<(), String> {
+    let root = WatchManager::canonical_path(path)?;
+    state.watcher.watch(collection, root.clone())?;
+    state.db.add_watch_folder(collection, &root.to_string_lossy()).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn remove_watch_folder(state: State<'_, AppState>, collection: &str, path: &str) -> Result<(), String> {
+    state.watcher.unwatch(collection, std::path::Path::new(path));
+    state.db.remove_watch_folder(collection, path).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_watch_status(state: State<'_, AppState>) -> Result<Vec<WatchStatus>, String> {
+    Ok(state.watcher.statuses())
+}
+
+#[tauri::command]
+async fn rag_search(state: State<'_, AppState>, collection: &str, query: &str, options: Option<SearchOptions>) -> Result<Vec<crate::core::rag::DocumentChunk>, String> {
+    let query = SearchQuery::new(collection, query, 5, options.unwrap_or_default());
+    state.rag.search(&query).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn rag_query(state: State<'_, AppState>, query: SearchQuery) -> Result<Vec<crate::core::rag::DocumentChunk>, String> {
+    state.rag.search(&query).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_collections(state: State<'_, AppState>) -> Result<Vec<CollectionInfo>, String> {
+    state.rag.get_collections().await
+}
+
+#[tauri::command]
+async fn set_document_tags(state: State<'_, AppState>, id: i64, tags: Vec<String>) -> Result<(), String> {
+    state.rag.set_tags(id, &tags).await
+}
+
+#[tauri::command]
+async fn load_embedding_model(app_handle: tauri::AppHandle, state: State<'_, AppState>, model_name: Option<String>) -> Result<String, String> {
+    let model_name = model_name.unwrap_or_else(|| DEFAULT_EMBEDDING_MODEL.to_string());
+    let engine = load_embedding_engine(&app_handle, &model_name)?;
+    state.rag.set_embedder(Some(engine));
+    Ok(format!("Embedding model {} loaded", model_name))
+}
+
+#[tauri::command]
+async fn load_reranker_model(app_handle: tauri::AppHandle, state: State<'_, AppState>, model_name: Option<String>) -> Result<String, String> {
+    let model_name = model_name.unwrap_or_else(|| DEFAULT_RERANKER_MODEL.to_string());
+    let reranker = load_cross_encoder(&app_handle, &model_name)?;
+    state.rag.set_reranker(Some(reranker));
+    Ok(format!("Reranker model {} loaded", model_name))
+}
+
+#[tauri::command]
+async fn export_collection(state: State<'_, AppState>, collection: &str, path: &str) -> Result<BundleManifest, String> {
+    state.rag.export_collection(collection, std::path::Path::new(path)).await
+}
+
+#[tauri::command]
+async fn import_collection(state: State<'_, AppState>, path: &str, collection: Option<String>) -> Result<ImportSummary, String> {
+    state.rag.import_collection(std::path::Path::new(path), collection.as_deref()).await
+}
+
+#[tauri::command]
+async fn embed_collection(state: State<'_, AppState>, collection: &str) -> Result<usize, String> {
+    state.rag.embed_missing(collection).await
+}
+
+#[tauri::command]
+async fn get_collection_chunker(state: State<'_, AppState>, collection: &str) -> Result<ChunkerConfig, String> {
+    state.rag.get_chunker(collection).await
+}
+
+#[tauri::command]
+async fn set_collection_chunker(state: State<'_, AppState>, collection: &str, chunker: ChunkerConfig) -> Result<(), String> {
+    state.rag.set_chunker(collection, &chunker).await
 }
 
 #[tauri::command]
@@ -92,32 +205,62 @@ async fn get_documents(state: State<'_, AppState>, collection: &str) -> Result<V
     state.rag.get_documents(collection).await.map_err(|e| e.to_string())
 }
 
-#[tauri::command]
-async fn send_prompt(state: State<'_, AppState>, prompt: &str, model: &str, agent_id: Option<i64>, use_search: bool, collection: Option<String>) -> Result<String, String> {
-    let mut final_prompt = prompt.to_string();
-    let mut system_prompt: Option<String> = None;
-    let mut context_text = String::new();
+/// Respuesta de `send_prompt`: el texto y los chunks de RAG que se le pasaron al modelo.
+#[derive(serde::Serialize)]
+struct ChatResponse {
+    conversation_id: i64,
+    /// Id del mensaje del asistente guardado
+    message_id: i64,
+    content: String,
+    citations: Vec<Citation>,
+}
 
-    if use_search {
-        let col = collection.unwrap_or_else(|| "default".to_string());
-        if let Ok(results) = state.rag.search(&col, prompt, 3).await {
-            if !results.is_empty() {
-                context_text.push_str("\n\nContexto Recuperado (RAG):\n");
-                for (i, doc) in results.iter().enumerate() {
-                    context_text.push_str(&format!("[{}] (from {}): {}\n", i+1, doc.filename, doc.content));
-                }
-                context_text.push_str("\n\n");
-            }
-        }
-    }
+/// Longitud máxima del título que se pone a una conversación nueva a partir del primer prompt.
+const CONVERSATION_TITLE_CHARS: usize = 60;
+/// Caracteres de cada turno del primer intercambio que se pasan al modelo para titular la conversación.
+const TITLE_EXCHANGE_CHARS: usize = 1000;
+/// Se emite cuando una conversación nueva recibe su título generado: `(conversation_id, title)`.
+const CONVERSATION_TITLED_EVENT: &str = "conversation_titled";
+
+/// Pide al modelo un título corto para la conversación a partir de su primer intercambio.
+async fn generate_title(provider: &dyn ChatProvider, prompt: &str, answer: &str) -> Option<String> {
+    let exchange = format!(
+        "User: {}\n\nAssistant: {}",
+        prompt.chars().take(TITLE_EXCHANGE_CHARS).collect::<String>(),
+        answer.chars().take(TITLE_EXCHANGE_CHARS).collect::<String>()
+    );
+    let messages = vec![
+        crate::core::openai::Message {
+            role: "system".to_string(),
+            content: "Write a short title (at most 6 words) for this conversation, in its language. Reply with the title only.".to_string(),
+        },
+        crate::core::openai::Message { role: "user".to_string(), content: exchange },
+    ];
+    let completion = provider.complete(messages, None).await.ok()?;
+
+    let title: String = completion.content.lines().next()?.trim().trim_matches(|c| c == '"' || c == '\'' || c == '*').chars().take(CONVERSATION_TITLE_CHARS).collect();
+    (!title.is_empty()).then_some(title)
+}
 
-    if let Some(id) = agent_id {
-        let agents = state.db.get_agents().await.map_err(|e| e.to_string())?;
-        if let Some(agent) = agents.iter().find(|a| a.0 == id) {
-            system_prompt = Some(agent.3.clone());
-        }
-    }
-    
+/// Chunks candidatos que se recuperan para RAG; el packer decide cuántos caben en el contexto.
+const RAG_CANDIDATES: i64 = 8;
+/// Tokens reservados para la respuesta si la petición no fija `max_new_tokens`.
+const DEFAULT_ANSWER_TOKENS: usize = 1024;
+/// Margen para el formato del chat (roles, separadores) y la imprecisión del conteo.
+const PROMPT_OVERHEAD_TOKENS: usize = 64;
+
+/// Ajustes de generación comunes a `send_prompt` y `regenerate_message`.
+struct ReplyOptions {
+    agent_id: Option<i64>,
+    use_search: bool,
+    collection: Option<String>,
+    request_id: Option<String>,
+    generation_config: Option<GenerationConfig>,
+    search_options: Option<SearchOptions>,
+}
+
+/// Proveedor para el modelo pedido; con "auto" se elige según el prompt y si hay modelo local cargado.
+fn resolve_provider(state: &AppState, model: &str, prompt: &str) -> Result<Arc<dyn ChatProvider>, String> {
     // Intelligent Scheduler & Telemetry
     let target_model = if model == "auto" {
         let is_local_loaded = state.local_llm.lock().unwrap().is_some();
@@ -132,6 +275,78 @@ async fn send_prompt(state: State<'_, AppState>, prompt: &str, model: &str, agen
 
     state.telemetry.log_event("prompt_received", &format!("Model: {} -> {}, Length: {}", model, target_model, prompt.len()));
 
+    state.providers.read().unwrap().get(target_model)
+        .ok_or_else(|| format!("No provider registered for {}", target_model))
+}
+
+/// Genera la respuesta al mensaje `user_message_id` (de texto `prompt`), con `history` (el hilo anterior a él)
+/// como contexto, y la guarda como hija suya.
+#[allow(clippy::too_many_arguments)]
+async fn generate_reply(app_handle: &tauri::AppHandle, state: &AppState, provider: &Arc<dyn ChatProvider>, conversation_id: i64, user_message_id: i64, prompt: &str, history: &[ChatMessage], options: ReplyOptions) -> Result<ChatResponse, String> {
+    let ReplyOptions { agent_id, use_search, collection, request_id, generation_config, search_options } = options;
+    let mut final_prompt = prompt.to_string();
+    let mut system_prompt: Option<String> = None;
+    let mut context_text = String::new();
+    let mut context_chunks = Vec::new();
+
+    if let Some(id) = agent_id {
+        let agents = state.db.get_agents().await.map_err(|e| e.to_string())?;
+        if let Some(agent) = agents.iter().find(|a| a.0 == id) {
+            system_prompt = Some(agent.3.clone());
+        }
+    }
+
+    // Presupuesto para contexto RAG + historial: ventana del modelo menos system prompt, pregunta y respuesta
+    let answer_tokens = generation_config.as_ref().and_then(|c| c.max_new_tokens).unwrap_or(DEFAULT_ANSWER_TOKENS);
+    // Los presupuestos se cuentan con el tokenizer del modelo que va a responder (estimados si no lo hay)
+    let capabilities = provider.capabilities();
+    let count_tokens = |text: &str| capabilities.count_tokens(text);
+    let reserved = answer_tokens
+        + PROMPT_OVERHEAD_TOKENS
+        + count_tokens(prompt)
+        + system_prompt.as_deref().map(count_tokens).unwrap_or(0);
+    let mut budget = capabilities.context_window.saturating_sub(reserved);
+
+    if use_search {
+        let col = collection.unwrap_or_else(|| "default".to_string());
+        if let Ok(results) = state.rag.search(&SearchQuery::new(&col, prompt, RAG_CANDIDATES, search_options.unwrap_or_default())).await {
+            let packed = crate::core::context::pack(results, budget, count_tokens);
+            state.telemetry.log_event("rag_context", &serde_json::json!({
+                "provider": provider.id(),
+                "budget": budget,
+                "used": packed.tokens,
+                "included": packed.included.iter().map(|d| d.id).collect::<Vec<_>>(),
+                "dropped": packed.dropped,
+            }).to_string());
+
+            if !packed.included.is_empty() {
+                context_text.push_str("\n\nContexto Recuperado (RAG):\n");
+                for (i, doc) in packed.included.iter().enumerate() {
+                    context_text.push_str(&crate::core::context::format_chunk(i + 1, doc));
+                }
+                context_text.push_str("\n\n");
+            }
+            budget = budget.saturating_sub(packed.tokens);
+            context_chunks = packed.included;
+        }
+    }
+
+    // El historial se queda con lo que no ha usado el contexto RAG; los turnos más antiguos se descartan primero
+    let (history, dropped_turns) = if capabilities.multi_turn {
+        // Las respuestas fallidas no tienen contenido que aportar
+        let turns: Vec<(String, String)> = history
+            .iter()
+            .filter(|m| m.meta.error.is_none())
+            .map(|m| (m.role.clone(), m.content.clone()))
+            .collect();
+        crate::core::context::fit_history(&turns, budget, count_tokens)
+    } else {
+        (Vec::new(), 0)
+    };
+    if dropped_turns > 0 {
+        state.telemetry.log_event("history_truncated", &format!("Conversation: {}, Kept: {}, Dropped: {}", conversation_id, history.len(), dropped_turns));
+    }
+
     // Append context to prompt if using API or Local, or prepending to system prompt if possible
     // For simplicity, we'll prepend to the user prompt for now, or system prompt.
     // Let's prepend to final_prompt for everyone so it's included.
@@ -139,56 +354,170 @@ async fn send_prompt(state: State<'_, AppState>, prompt: &str, model: &str, agen
          final_prompt = format!("{}{}", context_text, final_prompt);
     }
 
-    if target_model == "openai_api" {
-        let api_key = crate::core::auth::AuthManager::get_password("openai_api", "default")
-            .map_err(|_| "API Key not found. Please add account for 'openai_api' with username 'default'".to_string())?;
+    let mut messages = Vec::new();
+    if let Some(sys) = system_prompt {
+        messages.push(crate::core::openai::Message { role: "system".to_string(), content: sys });
+    }
+    messages.extend(history);
+    messages.push(crate::core::openai::Message { role: "user".to_string(), content: final_prompt });
+
+    // Los deltas se emiten como eventos `chat_stream` con este id para que la UI los vaya pintando
+    let request_id = request_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let start = std::time::Instant::now();
+    let result = provider.stream(app_handle, &request_id, messages, generation_config.as_ref()).await;
+    let mut meta = MessageMeta {
+        model: Some(provider.id().to_string()),
+        agent_id,
+        latency_ms: Some(start.elapsed().as_millis() as i64),
+        ..Default::default()
+    };
 
-        let mut messages = Vec::new();
-        if let Some(sys) = system_prompt {
-            messages.push(crate::core::openai::Message { role: "system".to_string(), content: sys.clone() });
+    // Un fallo también se guarda (como respuesta vacía con el error) para que quede en las estadísticas
+    // y se pueda regenerar
+    let completion = match result {
+        Ok(completion) => completion,
+        Err(e) => {
+            meta.error = Some(e.clone());
+            let _ = state.db.add_message(conversation_id, Some(user_message_id), "assistant", "", &meta).await;
+            return Err(e);
         }
-        messages.push(crate::core::openai::Message { role: "user".to_string(), content: final_prompt });
+    };
+    state.telemetry.log_event("inference", &format!("Provider: {}, Duration: {:?}, Chars: {}", provider.id(), start.elapsed(), completion.content.len()));
 
-        let (response, cost) = crate::core::openai::send_chat_completion(&api_key, messages).await?;
-        let _ = state.db.increment_usage(target_model, cost).await;
-        
-        return Ok(response);
+    if completion.total_tokens > 0 {
+        meta.prompt_tokens = Some(completion.prompt_tokens as i64);
+        meta.completion_tokens = Some(completion.completion_tokens as i64);
     }
+    meta.cost = Some(provider.cost(&completion));
 
-    if target_model == "local_phi2" {
-        let engine_guard = state.local_llm.lock().unwrap();
-        if let Some(engine) = engine_guard.as_ref() {
-            let start = std::time::Instant::now();
-            let response = engine.generate(&final_prompt).map_err(|e| e.to_string())?;
-            let duration = start.elapsed();
-            state.telemetry.log_event("local_inference", &format!("Duration: {:?}, Chars: {}", duration, response.len()));
-            return Ok(response);
-        } else {
-            return Err("Local model not loaded. Please download/load it first.".to_string());
+    let citations = crate::core::citations::from_answer(&context_chunks, &completion.content);
+    let message_id = state.db.add_message_with_citations(conversation_id, Some(user_message_id), "assistant", &completion.content, &meta, &citations).await.map_err(|e| e.to_string())?;
+
+    Ok(ChatResponse { conversation_id, message_id, content: completion.content, citations })
+}
+
+/// Envía un prompt en la conversación (o en una nueva) y guarda el turno. Por defecto continúa la rama activa;
+/// `branch_from` abre una rama nueva: desde una respuesta el prompt la continúa, desde un prompt lo sustituye
+/// (queda como versión alternativa, hermana suya).
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn send_prompt(app_handle: tauri::AppHandle, state: State<'_, AppState>, prompt: &str, model: &str, agent_id: Option<i64>, use_search: bool, collection: Option<String>, request_id: Option<String>, generation_config: Option<GenerationConfig>, search_options: Option<SearchOptions>, conversation_id: Option<i64>, branch_from: Option<i64>) -> Result<ChatResponse, String> {
+    let provider = resolve_provider(&state, model, prompt)?;
+
+    let branch_point = match branch_from {
+        Some(id) => Some(state.db.get_message(id).await.map_err(|e| e.to_string())?.ok_or("Message not found")?),
+        None => None,
+    };
+    let conversation_id = match (conversation_id, &branch_point) {
+        (Some(id), Some((message_conversation, ..))) if id != *message_conversation => {
+            return Err("Message belongs to another conversation".to_string());
         }
-    }
+        (_, Some((message_conversation, ..))) => *message_conversation,
+        (Some(id), None) => id,
+        // Sin conversación se empieza una nueva, titulada con el principio del prompt
+        (None, None) => {
+            let title: String = prompt.lines().next().unwrap_or_default().chars().take(CONVERSATION_TITLE_CHARS).collect();
+            state.db.create_conversation(&title).await.map_err(|e| e.to_string())?
+        }
+    };
 
-    if let Some(sys) = system_prompt {
-        final_prompt = format!("Instrucciones del Sistema:\n{}\n\nUsuario:\n{}", sys, final_prompt);
+    let parent_id = match (branch_from, branch_point) {
+        (Some(_), Some((_, parent, role, _))) if role == "user" => parent,
+        (Some(id), _) => Some(id),
+        (None, _) => state.db.active_message(conversation_id).await.map_err(|e| e.to_string())?,
+    };
+    let history = match parent_id {
+        Some(id) => state.db.get_path(id).await.map_err(|e| e.to_string())?,
+        None => Vec::new(),
+    };
+    let first_exchange = history.is_empty() && branch_from.is_none();
+
+    // Se guarda el prompt tal cual, sin el contexto RAG (que se vuelve a recuperar en cada turno)
+    let user_meta = MessageMeta { model: Some(provider.id().to_string()), agent_id, ..Default::default() };
+    let user_message_id = state.db.add_message(conversation_id, parent_id, "user", prompt, &user_meta).await.map_err(|e| e.to_string())?;
+    let options = ReplyOptions { agent_id, use_search, collection, request_id, generation_config, search_options };
+    let response = generate_reply(&app_handle, &state, &provider, conversation_id, user_message_id, prompt, &history, options).await?;
+
+    // Título generado en segundo plano; solo con proveedores de API (a los web chats les abriría otra conversación
+    // y el modelo local es lento). Mientras tanto la conversación conserva el título sacado del prompt.
+    if first_exchange && provider.capabilities().multi_turn {
+        let db = state.db.clone();
+        let provider = provider.clone();
+        let (prompt, answer) = (prompt.to_string(), response.content.clone());
+        tauri::async_runtime::spawn(async move {
+            if let Some(title) = generate_title(provider.as_ref(), &prompt, &answer).await {
+                if db.rename_conversation(conversation_id, &title).await.is_ok() {
+                    let _ = app_handle.emit(CONVERSATION_TITLED_EVENT, (conversation_id, title));
+                }
+            }
+        });
     }
-    // Note: context is already in final_prompt
 
-    let _ = state.db.increment_usage(target_model, 0.0).await; 
+    Ok(response)
+}
 
-    let orchestrator = state.orchestrator.lock().unwrap();
-    
-    let action = match target_model {
-        "chatgpt" => "chat_chatgpt",
-        "cloud_glm" => "chat_glm",
-        "cloud_kimi" => "chat_kimi",
-        "cloud_deepseek" => "chat_deepseek",
-        _ => "chat_deepseek",
+/// Genera otra respuesta al mismo prompt (con el modelo y opciones indicados) como rama hermana de `message_id`.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn regenerate_message(app_handle: tauri::AppHandle, state: State<'_, AppState>, message_id: i64, model: &str, agent_id: Option<i64>, use_search: bool, collection: Option<String>, request_id: Option<String>, generation_config: Option<GenerationConfig>, search_options: Option<SearchOptions>) -> Result<ChatResponse, String> {
+    let (conversation_id, parent, role, _) = state.db.get_message(message_id).await.map_err(|e| e.to_string())?.ok_or("Message not found")?;
+    if role != "assistant" {
+        return Err("Only assistant messages can be regenerated".to_string());
+    }
+    let user_message_id = parent.ok_or("Message has no prompt to answer")?;
+    let (_, user_parent, _, prompt) = state.db.get_message(user_message_id).await.map_err(|e| e.to_string())?.ok_or("Message not found")?;
+    let history = match user_parent {
+        Some(id) => state.db.get_path(id).await.map_err(|e| e.to_string())?,
+        None => Vec::new(),
     };
 
-    let payload = serde_json::json!({ "prompt": final_prompt });
-    orchestrator.send_command(action, Some(payload));
-    
-    Ok(format!("Prompt sent to {}: {}", target_model, prompt))
+    let provider = resolve_provider(&state, model, &prompt)?;
+    let options = ReplyOptions { agent_id, use_search, collection, request_id, generation_config, search_options };
+    generate_reply(&app_handle, &state, &provider, conversation_id, user_message_id, &prompt, &history, options).await
+}
+
+/// Activa la rama que pasa por `message_id` (normalmente un hermano del mensaje visible) y devuelve su hilo.
+#[tauri::command]
+async fn switch_branch(state: State<'_, AppState>, message_id: i64) -> Result<Vec<ChatMessage>, String> {
+    let leaf = state.db.switch_branch(message_id).await.map_err(|e| e.to_string())?;
+    state.db.get_path(leaf).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_providers(state: State<'_, AppState>) -> Result<Vec<(String, crate::core::provider::Capabilities)>, String> {
+    Ok(state.providers.read().unwrap().list())
+}
+
+// Endpoint profiles
+#[tauri::command]
+async fn save_endpoint(state: State<'_, AppState>, endpoint: EndpointProfile, api_key: Option<String>) -> Result<(), String> {
+    if let Some(key) = api_key.filter(|k| !k.is_empty()) {
+        crate::core::auth::AuthManager::save_credentials(ENDPOINT_KEY_SERVICE, &endpoint.name, &key)?;
+    }
+    state.db.save_endpoint(&endpoint).await.map_err(|e| e.to_string())?;
+    let mut providers = state.providers.write().unwrap();
+    let tokens = providers.api_tokens();
+    providers.register(Arc::new(OpenAiProvider::from_profile(endpoint, tokens)));
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_endpoints(state: State<'_, AppState>) -> Result<Vec<EndpointProfile>, String> {
+    state.db.get_endpoints().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_endpoint(state: State<'_, AppState>, name: &str) -> Result<(), String> {
+    // Puede que el perfil no tuviera key
+    let _ = crate::core::auth::AuthManager::delete_credentials(ENDPOINT_KEY_SERVICE, name);
+    state.db.delete_endpoint(name).await.map_err(|e| e.to_string())?;
+    state.providers.write().unwrap().unregister(&OpenAiProvider::profile_id(name));
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_browser_backend_status(state: State<'_, AppState>) -> Result<crate::core::orchestrator::BackendStatus, String> {
+    Ok(state.orchestrator.status())
 }
 
 #[tauri::command]
@@ -203,7 +532,7 @@ async fn get_telemetry_log(app_handle: tauri::AppHandle) -> Result<String, Strin
 }
 
 #[tauri::command]
-async fn load_local_model(app_handle: tauri::AppHandle, state: State<'_, AppState>) -> Result<String, String> {
+async fn load_local_model(app_handle: tauri::AppHandle, state: State<'_, AppState>, generation_config: Option<GenerationConfig>) -> Result<String, String> {
     let app_dir = app_handle.path().app_data_dir().map_err(|e| e.to_string())?;
     let model_path = app_dir.join("models").join("phi-2-int4.onnx");
     let tokenizer_path = app_dir.join("models").join("tokenizer.json");
@@ -212,10 +541,13 @@ async fn load_local_model(app_handle: tauri::AppHandle, state: State<'_, AppStat
         return Err("Model files not found in app data directory".to_string());
     }
 
-    let engine = LocalInferenceEngine::new(&model_path, &tokenizer_path).map_err(|e| e.to_string())?;
+    let mut engine = LocalInferenceEngine::new(&model_path, &tokenizer_path).map_err(|e| e.to_string())?;
+    if let Some(config) = generation_config {
+        engine.set_default_config(config);
+    }
     
     let mut local_store = state.local_llm.lock().unwrap();
-    *local_store = Some(engine);
+    *local_store = Some(LoadedEngine::new(engine));
     
     Ok("Model loaded successfully".to_string())
 }
@@ -241,24 +573,117 @@ async fn create_conversation(state: State<'_, AppState>, title: &str) -> Result<
 }
 
 #[tauri::command]
-async fn add_message(state: State<'_, AppState>, conversation_id: i64, role: &str, content: &str) -> Result<(), String> {
-    state.db.add_message(conversation_id, role, content).await.map_err(|e| e.to_string())
+async fn list_conversations(state: State<'_, AppState>, archived: Option<bool>, limit: Option<i64>, offset: Option<i64>) -> Result<Vec<ConversationSummary>, String> {
+    state.db.list_conversations(archived.unwrap_or(false), limit.unwrap_or(50), offset.unwrap_or(0)).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn rename_conversation(state: State<'_, AppState>, id: i64, title: &str) -> Result<(), String> {
+    state.db.rename_conversation(id, title).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_conversation(state: State<'_, AppState>, id: i64) -> Result<(), String> {
+    state.db.delete_conversation(id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn archive_conversation(state: State<'_, AppState>, id: i64, archived: bool) -> Result<(), String> {
+    state.db.set_conversation_archived(id, archived).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn pin_conversation(state: State<'_, AppState>, id: i64, pinned: bool) -> Result<(), String> {
+    state.db.set_conversation_pinned(id, pinned).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn search_history(state: State<'_, AppState>, query: HistoryQuery) -> Result<Vec<HistoryHit>, String> {
+    state.db.search_history(&query).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn add_message(state: State<'_, AppState>, conversation_id: i64, role: &str, content: &str, citations: Option<Vec<Citation>>) -> Result<i64, String> {
+    let parent_id = state.db.active_message(conversation_id).await.map_err(|e| e.to_string())?;
+    let citations = citations.unwrap_or_default();
+    state.db.add_message_with_citations(conversation_id, parent_id, role, content, &MessageMeta::default(), &citations).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_message_citations(state: State<'_, AppState>, message_id: i64) -> Result<Vec<Citation>, String> {
+    state.db.get_citations(message_id).await.map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn get_messages(state: State<'_, AppState>, conversation_id: i64) -> Result<Vec<(String, String)>, String> {
+async fn get_messages(state: State<'_, AppState>, conversation_id: i64) -> Result<Vec<ChatMessage>, String> {
     state.db.get_messages(conversation_id).await.map_err(|e| e.to_string())
 }
 
+const DEFAULT_EMBEDDING_MODEL: &str = "all-MiniLM-L6-v2";
+
+/// Los modelos de embeddings viven en `models/embeddings/<nombre>/` (model.onnx + tokenizer.json).
+fn load_embedding_engine(app_handle: &tauri::AppHandle, model_name: &str) -> Result<EmbeddingEngine, String> {
+    let app_dir = app_handle.path().app_data_dir().map_err(|e| e.to_string())?;
+    let model_dir = app_dir.join("models").join("embeddings").join(model_name);
+    let model_path = model_dir.join("model.onnx");
+    let tokenizer_path = model_dir.join("tokenizer.json");
+
+    if !model_path.exists() || !tokenizer_path.exists() {
+        return Err(format!("Embedding model files not found in {}", model_dir.display()));
+    }
+
+    EmbeddingEngine::new(model_name, &model_path, &tokenizer_path)
+}
+
+const DEFAULT_RERANKER_MODEL: &str = "ms-marco-MiniLM-L-6-v2";
+
+/// Los cross-encoders viven en `models/rerankers/<nombre>/` (model.onnx + tokenizer.json).
+fn load_cross_encoder(app_handle: &tauri::AppHandle, model_name: &str) -> Result<CrossEncoder, String> {
+    let app_dir = app_handle.path().app_data_dir().map_err(|e| e.to_string())?;
+    let model_dir = app_dir.join("models").join("rerankers").join(model_name);
+    let model_path = model_dir.join("model.onnx");
+    let tokenizer_path = model_dir.join("tokenizer.json");
+
+    if !model_path.exists() || !tokenizer_path.exists() {
+        return Err(format!("Reranker model files not found in {}", model_dir.display()));
+    }
+
+    CrossEncoder::new(&model_path, &tokenizer_path)
+}
+
+/// Error que impide arrancar (p.ej. una base de datos de una versión más nueva de la app): se explica
+/// en un diálogo y la app se cierra al aceptarlo.
+fn exit_with_error(app_handle: &tauri::AppHandle, message: String) {
+    eprintln!("{}", message);
+    let exit_handle = app_handle.clone();
+    app_handle
+        .dialog()
+        .message(message)
+        .title("Cannot start")
+        .kind(MessageDialogKind::Error)
+        .show(move |_| exit_handle.exit(1));
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_dialog::init())
         .setup(|app| {
             let handle = app.handle();
             tauri::async_runtime::block_on(async move {
-                let db = Database::new(handle.clone()).await.expect("Failed to init DB");
+                let db = match Database::new(handle.clone()).await {
+                    Ok(db) => db,
+                    Err(e) => return exit_with_error(handle, format!("Could not open the database: {}", e)),
+                };
                 let rag = RagManager::new(db.get_pool());
+                // Si el modelo de embeddings está descargado, la búsqueda RAG pasa a ser semántica
+                if let Ok(engine) = load_embedding_engine(handle, DEFAULT_EMBEDDING_MODEL) {
+                    rag.set_embedder(Some(engine));
+                }
+                if let Ok(reranker) = load_cross_encoder(handle, DEFAULT_RERANKER_MODEL) {
+                    rag.set_reranker(Some(reranker));
+                }
                 
                 // Copy connection adapter if not exists
                 if let Ok(app_dir) = handle.path().app_data_dir() {
@@ -276,24 +701,47 @@ pub fn run() {
 
                 // Resolve path using updater (fixes unused warning and standardizes path)
                 let adapter_path = crate::core::updater::get_adapter_path(&handle, "playwright-service.js");
-                let orchestrator = Orchestrator::new(adapter_path);
-
-                orchestrator.send_command("init", None);
+                // Si Node no arranca, el backend queda "unavailable" y el supervisor reintenta
+                let orchestrator = Arc::new(Orchestrator::new(adapter_path));
+                tauri::async_runtime::spawn(Orchestrator::supervise(orchestrator.clone()));
                 let telemetry = crate::core::telemetry::TelemetryManager::new(&handle);
 
+                let local_llm = Arc::new(Mutex::new(None));
+                let endpoints = db.get_endpoints().await.unwrap_or_default();
+                let api_tokenizer = handle.path().resolve(API_TOKENIZER_RESOURCE, tauri::path::BaseDirectory::Resource).ok();
+                let api_tokens = TokenCounter::api(api_tokenizer.as_deref());
+                let providers = ProviderRegistry::with_defaults(local_llm.clone(), orchestrator.clone(), endpoints, api_tokens);
+
+                let rag = Arc::new(rag);
+                let watcher = Arc::new(WatchManager::new(rag.clone()));
+                for (collection, path) in db.get_watch_folders().await.unwrap_or_default() {
+                    if let Err(e) = WatchManager::canonical_path(&path).and_then(|root| watcher.watch(&collection, root)) {
+                        eprintln!("Failed to watch {}: {}", path, e);
+                    }
+                }
+
                 handle.manage(AppState {
-                    orchestrator: Mutex::new(orchestrator),
+                    orchestrator: orchestrator.clone(),
                     db: Arc::new(db),
-                    local_llm: Mutex::new(None),
+                    local_llm,
+                    providers: Arc::new(RwLock::new(providers)),
                     telemetry: Arc::new(telemetry),
-                    rag: Arc::new(rag),
+                    rag,
+                    watcher,
                 });
             });
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             greet, 
-            send_prompt, 
+            send_prompt,
+            regenerate_message,
+            switch_branch, 
+            get_providers,
+            get_browser_backend_status,
+            save_endpoint,
+            get_endpoints,
+            delete_endpoint,
             get_stats, 
             add_account, 
             get_accounts, 
@@ -304,16 +752,48 @@ pub fn run() {
             delete_agent, 
             update_agent, 
             ingest_document, 
+            ingest_directory,
+            delete_document,
+            drop_collection,
+            rebuild_collection,
+            add_watch_folder,
+            remove_watch_folder,
+            get_watch_status,
             rag_search, 
+            rag_query,
+            export_collection,
+            import_collection,
+            get_collections,
+            set_document_tags,
             get_documents, 
+            load_embedding_model,
+            load_reranker_model,
+            embed_collection,
+            get_collection_chunker,
+            set_collection_chunker,
             load_local_model, 
             unload_local_model, 
             check_updates, 
             get_telemetry_log,
             create_conversation,
+            list_conversations,
+            search_history,
+            rename_conversation,
+            delete_conversation,
+            archive_conversation,
+            pin_conversation,
             add_message,
+            get_message_citations,
             get_messages
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app_handle, event| {
+            // Cierre limpio del proceso Node (cierra el navegador y guarda la sesión)
+            if let tauri::RunEvent::Exit = event {
+                if let Some(state) = app_handle.try_state::<AppState>() {
+                    state.orchestrator.shutdown();
+                }
+            }
+        });
 }